@@ -1,29 +1,13 @@
 use serde_derive::{Deserialize, Serialize};
 
-#[derive(Serialize)]
-pub struct ErrorResp {
-    pub error: String,
-}
-
-#[derive(Serialize, Debug)]
-pub struct Health {
-    pub healthy: bool,
-    pub version: String,
-}
-
-#[derive(Deserialize, Debug)]
-pub struct Login {
-    pub user: String,
-    pub pw: String,
-}
+// the ones that go over the wire live in shared so the client crate gets them too
+pub use shared::{
+    ErrorResp, Health, Login, LoginResp, NewUser, PasswordChange, Readiness, UserResp,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
-    pub exp: u32, // Required (validate_exp defaults to true in validation). Expiration time
-    pub sub: String, // Optional. Subject (whom token refers to)
-}
-
-#[derive(Debug, Serialize)]
-pub struct LoginResp {
-    pub jwt: String,
+    pub exp: u64, // Required (validate_exp defaults to true in validation). Expiration time
+    pub sub: String, // the user id the token was made for
+    pub jti: String, // random, so two tokens made the same second still differ
 }
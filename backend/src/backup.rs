@@ -0,0 +1,474 @@
+use crate::config::Config;
+use crate::s3_interactions;
+use crate::startup;
+use crate::tables::{self, DynamoClient};
+use crate::token_store::now_secs;
+use dynomite::{
+    dynamodb::{DynamoDb, PutItemInput, ScanInput},
+    Attributes,
+};
+use futures::TryStreamExt;
+use rusoto_s3::{GetObjectRequest, ListObjectsV2Request, S3Client, S3};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+// Everything that goes in a backup, described in manifest.json at the end of the tar:
+//   tables/<name>.jsonl  one DynamoDB item per line, in DynamoDB's own JSON
+//   objects/<key>        each object in the bucket, as it was
+// Tables go by what they're for rather than their name in DynamoDB, so a backup
+// restores into whatever the config calls them this time.
+
+pub static MANIFEST: &str = "manifest.json";
+// goes up when the layout changes in a way an older restore wouldn't understand
+static FORMAT: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub format: u32,
+    // seconds since the epoch
+    pub created_at: u64,
+    pub backend_version: String,
+    pub tables: Vec<TableEntry>,
+    pub objects: Vec<ObjectEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TableEntry {
+    pub name: String,
+    pub path: String,
+    pub items: usize,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ObjectEntry {
+    pub key: String,
+    pub path: String,
+    pub content_type: Option<String>,
+    pub size: u64,
+    pub sha256: String,
+}
+
+// The tables worth keeping and what the config calls them. Not tokens: everyone logging
+// in again after a restore is fine, old refresh tokens coming back to life isn't.
+pub fn tables_to_keep(config: &Config) -> Vec<(&'static str, String)> {
+    vec![
+        ("meals", config.meals_table.clone()),
+        ("days", config.days_table.clone()),
+        ("users", config.users_table.clone()),
+        ("revisions", config.revisions_table.clone()),
+    ]
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+// Builds the tar one entry at a time, the manifest goes in last once every checksum
+// is known.
+pub struct ArchiveWriter<W: Write> {
+    tar: tar::Builder<W>,
+    manifest: Manifest,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(out: W, backend_version: &str) -> ArchiveWriter<W> {
+        ArchiveWriter {
+            tar: tar::Builder::new(out),
+            manifest: Manifest {
+                format: FORMAT,
+                created_at: now_secs(),
+                backend_version: backend_version.to_string(),
+                tables: Vec::new(),
+                objects: Vec::new(),
+            },
+        }
+    }
+
+    fn append(&mut self, path: &str, data: &[u8]) -> Result<(), String> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.manifest.created_at);
+        header.set_cksum();
+        self.tar
+            .append_data(&mut header, path, data)
+            .map_err(|e| format!("couldn't add {} to the archive: {}", path, e))
+    }
+
+    pub fn add_table(&mut self, name: &str, items: &[Attributes]) -> Result<(), String> {
+        let mut data = Vec::new();
+        for item in items {
+            serde_json::to_writer(&mut data, item).map_err(|e| e.to_string())?;
+            data.push(b'\n');
+        }
+        let path = format!("tables/{}.jsonl", name);
+        self.append(&path, &data)?;
+        self.manifest.tables.push(TableEntry {
+            name: name.to_string(),
+            path,
+            items: items.len(),
+            sha256: sha256(&data),
+        });
+        Ok(())
+    }
+
+    pub fn add_object(
+        &mut self,
+        key: &str,
+        content_type: Option<String>,
+        data: &[u8],
+    ) -> Result<(), String> {
+        let path = format!("objects/{}", key);
+        self.append(&path, data)?;
+        self.manifest.objects.push(ObjectEntry {
+            key: key.to_string(),
+            path,
+            content_type,
+            size: data.len() as u64,
+            sha256: sha256(data),
+        });
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<Manifest, String> {
+        let manifest = serde_json::to_vec_pretty(&self.manifest).map_err(|e| e.to_string())?;
+        self.append(MANIFEST, &manifest)?;
+        let mut out = self
+            .tar
+            .into_inner()
+            .map_err(|e| format!("couldn't finish the archive: {}", e))?;
+        out.flush()
+            .map_err(|e| format!("couldn't finish the archive: {}", e))?;
+        Ok(self.manifest)
+    }
+}
+
+// Reads the whole archive once and checks every entry against the manifest, before
+// anything gets restored from it.
+pub fn verify(archive: impl Read) -> Result<Manifest, String> {
+    let mut sums = HashMap::new();
+    let mut manifest = None;
+    let mut tar = tar::Archive::new(archive);
+    for entry in tar.entries().map_err(|e| e.to_string())? {
+        let (path, data) = read_entry(entry)?;
+        if path == MANIFEST {
+            manifest = Some(
+                serde_json::from_slice::<Manifest>(&data)
+                    .map_err(|e| format!("the manifest doesn't make sense: {}", e))?,
+            );
+        } else {
+            sums.insert(path, (data.len() as u64, sha256(&data)));
+        }
+    }
+    let manifest = manifest.ok_or("there's no manifest, is this a backup?")?;
+    if manifest.format != FORMAT {
+        return Err(format!(
+            "this is a format {} backup, only format {} can be restored",
+            manifest.format, FORMAT
+        ));
+    }
+    let expected = manifest
+        .tables
+        .iter()
+        .map(|t| (&t.path, None, &t.sha256))
+        .chain(
+            manifest
+                .objects
+                .iter()
+                .map(|o| (&o.path, Some(o.size), &o.sha256)),
+        );
+    for (path, size, sha) in expected {
+        match sums.get(path) {
+            None => return Err(format!("{} is missing", path)),
+            Some((actual_size, actual_sha))
+                if actual_sha != sha || size.is_some_and(|s| s != *actual_size) =>
+            {
+                return Err(format!("{} doesn't match its checksum", path))
+            }
+            Some(_) => (),
+        }
+    }
+    Ok(manifest)
+}
+
+fn read_entry(entry: std::io::Result<tar::Entry<impl Read>>) -> Result<(String, Vec<u8>), String> {
+    let mut entry = entry.map_err(|e| format!("couldn't read the archive: {}", e))?;
+    let path = entry
+        .path()
+        .map_err(|e| e.to_string())?
+        .to_string_lossy()
+        .into_owned();
+    let mut data = Vec::new();
+    entry
+        .read_to_end(&mut data)
+        .map_err(|e| format!("couldn't read {}: {}", path, e))?;
+    Ok((path, data))
+}
+
+// the items in a tables/*.jsonl entry
+pub fn table_items(data: &[u8]) -> Result<Vec<Attributes>, String> {
+    data.split(|b| *b == b'\n')
+        .filter(|l| !l.is_empty())
+        .map(|l| serde_json::from_slice(l).map_err(|e| e.to_string()))
+        .collect()
+}
+
+// what's in the tables and bucket the config points at, written to `out`
+pub async fn backup(config: &Config, out: impl Write) -> Result<Manifest, String> {
+    let client = tables::get_dynamodb_client(config)?;
+    let s3 = s3_interactions::get_s3_client(config)?;
+    let mut archive = ArchiveWriter::new(out, &config.version);
+    for (name, table) in tables_to_keep(config) {
+        let items = scan_all(&client, &table).await?;
+        info!("{} items from {}", items.len(), table);
+        archive.add_table(name, &items)?;
+    }
+    for key in list_objects(&s3, &config.bucket_name).await? {
+        let (content_type, data) = get_object(&s3, &config.bucket_name, &key).await?;
+        archive.add_object(&key, content_type, &data)?;
+    }
+    archive.finish()
+}
+
+async fn scan_all(client: &DynamoClient, table: &str) -> Result<Vec<Attributes>, String> {
+    let mut items = Vec::new();
+    let mut start_key = None;
+    loop {
+        let scanned = client
+            .scan(ScanInput {
+                table_name: table.to_string(),
+                exclusive_start_key: start_key,
+                // what's there now, not what was there a moment ago
+                consistent_read: Some(true),
+                ..ScanInput::default()
+            })
+            .await
+            .map_err(|e| format!("couldn't scan {}: {}", table, e))?;
+        items.extend(scanned.items.unwrap_or_default());
+        start_key = scanned.last_evaluated_key;
+        if start_key.is_none() {
+            return Ok(items);
+        }
+    }
+}
+
+async fn list_objects(s3: &S3Client, bucket: &str) -> Result<Vec<String>, String> {
+    let mut keys = Vec::new();
+    let mut token = None;
+    loop {
+        let listed = s3
+            .list_objects_v2(ListObjectsV2Request {
+                bucket: bucket.to_string(),
+                continuation_token: token,
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| format!("couldn't list {}: {}", bucket, e))?;
+        keys.extend(
+            listed
+                .contents
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|o| o.key),
+        );
+        token = listed.next_continuation_token;
+        if token.is_none() {
+            return Ok(keys);
+        }
+    }
+}
+
+async fn get_object(
+    s3: &S3Client,
+    bucket: &str,
+    key: &str,
+) -> Result<(Option<String>, Vec<u8>), String> {
+    let got = s3
+        .get_object(GetObjectRequest {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| format!("couldn't get {}: {}", key, e))?;
+    let data = match got.body {
+        Some(body) => body
+            .map_ok(|b| b.to_vec())
+            .try_concat()
+            .await
+            .map_err(|e| format!("couldn't read {}: {}", key, e))?,
+        None => Vec::new(),
+    };
+    Ok((got.content_type, data))
+}
+
+// Puts a backup back into the tables and bucket the config points at. They get made if
+// they aren't there but have to be empty, a restore never overwrites anything. The
+// archive gets checked first so a bad one doesn't leave half a restore behind.
+pub async fn restore(config: &Config, archive: &Path) -> Result<Manifest, String> {
+    let open = || File::open(archive).map_err(|e| format!("couldn't open the archive: {}", e));
+    let manifest = verify(open()?)?;
+    let keep: HashMap<&str, String> = tables_to_keep(config).into_iter().collect();
+    for t in &manifest.tables {
+        if !keep.contains_key(t.name.as_str()) {
+            return Err(format!("there's no {} table to restore into", t.name));
+        }
+    }
+
+    let client = tables::get_dynamodb_client(config)?;
+    let s3 = s3_interactions::get_s3_client(config)?;
+    tables::prepopulate_db(client.clone(), config).await?;
+    startup::retry("making the photo bucket", startup::STARTUP, || {
+        s3_interactions::create_bucket_if_needed(&s3, &config.bucket_name)
+    })
+    .await?;
+    for table in keep.values() {
+        if !is_empty(&client, table).await? {
+            return Err(format!(
+                "{} isn't empty, restores only go into empty tables",
+                table
+            ));
+        }
+    }
+    let listed = s3
+        .list_objects_v2(ListObjectsV2Request {
+            bucket: config.bucket_name.clone(),
+            max_keys: Some(1),
+            ..Default::default()
+        })
+        .await
+        .map_err(|e| format!("couldn't list {}: {}", config.bucket_name, e))?;
+    if listed.key_count.unwrap_or(0) > 0 {
+        return Err(format!(
+            "{} isn't empty, restores only go into an empty bucket",
+            config.bucket_name
+        ));
+    }
+
+    let tables: HashMap<&str, &TableEntry> = manifest
+        .tables
+        .iter()
+        .map(|t| (t.path.as_str(), t))
+        .collect();
+    let objects: HashMap<&str, &ObjectEntry> = manifest
+        .objects
+        .iter()
+        .map(|o| (o.path.as_str(), o))
+        .collect();
+    let mut tar = tar::Archive::new(open()?);
+    for entry in tar.entries().map_err(|e| e.to_string())? {
+        let (path, data) = read_entry(entry)?;
+        if let Some(t) = tables.get(path.as_str()) {
+            let table = &keep[t.name.as_str()];
+            for item in table_items(&data)? {
+                client
+                    .put_item(PutItemInput {
+                        table_name: table.clone(),
+                        item,
+                        ..PutItemInput::default()
+                    })
+                    .await
+                    .map_err(|e| format!("couldn't put an item in {}: {}", table, e))?;
+            }
+            info!("{} items into {}", t.items, table);
+        } else if let Some(o) = objects.get(path.as_str()) {
+            let content_type = o.content_type.as_deref().unwrap_or("binary/octet-stream");
+            s3_interactions::put_photo(&s3, &config.bucket_name, &o.key, content_type, data)
+                .await
+                .map_err(|e| format!("couldn't put {}: {}", o.key, e))?;
+        }
+    }
+    Ok(manifest)
+}
+
+async fn is_empty(client: &DynamoClient, table: &str) -> Result<bool, String> {
+    let scanned = client
+        .scan(ScanInput {
+            table_name: table.to_string(),
+            limit: Some(1),
+            ..ScanInput::default()
+        })
+        .await
+        .map_err(|e| format!("couldn't scan {}: {}", table, e))?;
+    Ok(scanned.items.unwrap_or_default().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dynomite::Attribute;
+
+    fn archive() -> Vec<u8> {
+        let mut item = Attributes::new();
+        item.insert("id".to_string(), "abc".to_string().into_attr());
+        item.insert("stars".to_string(), 4.into_attr());
+        let mut out = Vec::new();
+        let mut w = ArchiveWriter::new(&mut out, "test");
+        w.add_table("meals", &[item.clone(), item]).unwrap();
+        w.add_table("users", &[]).unwrap();
+        w.add_object(
+            "photos/abc/1.png",
+            Some("image/png".to_string()),
+            b"not really a png",
+        )
+        .unwrap();
+        let manifest = w.finish().unwrap();
+        assert_eq!(manifest.tables[0].items, 2);
+        assert_eq!(manifest.objects[0].size, 16);
+        out
+    }
+
+    #[test]
+    fn test_archive_round_trip() {
+        let data = archive();
+        let manifest = verify(&data[..]).unwrap();
+        assert_eq!(manifest.format, FORMAT);
+        assert_eq!(manifest.backend_version, "test");
+        assert_eq!(manifest.tables.len(), 2);
+        assert_eq!(manifest.objects[0].key, "photos/abc/1.png");
+        assert_eq!(
+            manifest.objects[0].content_type.as_deref(),
+            Some("image/png")
+        );
+
+        let mut tar = tar::Archive::new(&data[..]);
+        let (path, items) = read_entry(tar.entries().unwrap().next().unwrap()).unwrap();
+        assert_eq!(path, "tables/meals.jsonl");
+        let items = table_items(&items).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["stars"].n.as_deref(), Some("4"));
+    }
+
+    #[test]
+    fn test_verify_catches_damage() {
+        let data = archive();
+        // flip a byte of the photo, it's the only place that string is
+        let at = data
+            .windows(16)
+            .position(|w| w == b"not really a png")
+            .unwrap();
+        let mut damaged = data.clone();
+        damaged[at] = b'N';
+        assert_eq!(
+            verify(&damaged[..]),
+            Err("objects/photos/abc/1.png doesn't match its checksum".to_string())
+        );
+
+        // no manifest
+        let mut out = Vec::new();
+        let mut tar = tar::Builder::new(&mut out);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_cksum();
+        tar.append_data(&mut header, "tables/meals.jsonl", &[][..])
+            .unwrap();
+        tar.finish().unwrap();
+        drop(tar);
+        assert!(verify(&out[..]).is_err());
+    }
+}
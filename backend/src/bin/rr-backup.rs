@@ -0,0 +1,76 @@
+use backend::backup;
+use backend::config::{Config, Opts, StoreKind};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[macro_use]
+extern crate log;
+
+// Takes the same flags, RR_* variables and rampage.toml as the server, so it finds the
+// same tables and bucket. Point --dynamodb-endpoint and --s3-endpoint at dynamodb-local
+// and minio to try a restore out.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "rr-backup")]
+struct Args {
+    #[structopt(flatten)]
+    opts: Opts,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Write every table and photo to a tar archive
+    Backup {
+        #[structopt(parse(from_os_str))]
+        archive: PathBuf,
+    },
+    /// Put an archive back into empty tables and an empty bucket
+    Restore {
+        #[structopt(parse(from_os_str))]
+        archive: PathBuf,
+    },
+}
+
+#[tokio::main]
+async fn main() {
+    pretty_env_logger::init();
+    // the flattened server flags bring the server's about with them, this one goes on after
+    let matches = Args::clap()
+        .about("Backs up the refeed rampage tables and photo bucket to a tar, and restores them")
+        .get_matches();
+    let args = Args::from_clap(&matches);
+    if let Err(e) = run(args).await {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(args: Args) -> Result<(), String> {
+    let config = Config::from_opts(args.opts).map_err(|e| e.to_string())?;
+    if config.meal_store != StoreKind::Dynamodb {
+        return Err("there's nothing to back up with the in-memory store".to_string());
+    }
+    let manifest = match &args.command {
+        Command::Backup { archive } => {
+            let out = File::create(archive)
+                .map_err(|e| format!("couldn't create {}: {}", archive.display(), e))?;
+            backup::backup(&config, BufWriter::new(out)).await?
+        }
+        Command::Restore { archive } => backup::restore(&config, archive).await?,
+    };
+    let items: usize = manifest.tables.iter().map(|t| t.items).sum();
+    println!(
+        "{} {} items from {} tables and {} objects",
+        match args.command {
+            Command::Backup { .. } => "backed up",
+            Command::Restore { .. } => "restored",
+        },
+        items,
+        manifest.tables.len(),
+        manifest.objects.len()
+    );
+    Ok(())
+}
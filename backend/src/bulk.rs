@@ -0,0 +1,440 @@
+use crate::meal_store::{decode_cursor, MealDb, MealQuery, StoreError, MAX_PAGE_SIZE};
+use bytes::Bytes;
+use futures::{stream, Stream};
+use serde_json::{Map, Value};
+use shared::Meal;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// The formats GET /export writes and POST /import reads. Meals go through shared::Meal's
+// serde either way, so whatever gets exported imports back the same.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // one array of meals
+    Json,
+    // one meal per line
+    JsonLines,
+    // a header row with CSV_COLUMNS then one meal per row
+    Csv,
+}
+
+// every Meal field, in the order the CSV columns go
+static CSV_COLUMNS: [&str; 10] = [
+    "id",
+    "name",
+    "description",
+    "stars",
+    "owner",
+    "version",
+    "photos",
+    "renditions",
+    "deleted_at",
+    "purge_at",
+];
+
+// the CSV columns that hold numbers, everything else is a string
+static CSV_NUMBERS: [&str; 4] = ["stars", "version", "deleted_at", "purge_at"];
+
+impl Format {
+    fn parse(s: &str) -> Result<Format, String> {
+        match s {
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::JsonLines),
+            "csv" => Ok(Format::Csv),
+            other => Err(format!(
+                "unknown format '{}', it's json, jsonl or csv",
+                other
+            )),
+        }
+    }
+
+    // GET /export?format=csv, json when there's no format
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Format, String> {
+        let mut format = Format::Json;
+        for (k, v) in params {
+            match k.as_str() {
+                "format" => format = Format::parse(v)?,
+                other => return Err(format!("unknown query parameter '{}'", other)),
+            }
+        }
+        Ok(format)
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::JsonLines => "application/x-ndjson",
+            Format::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Format::Json => "meals.json",
+            Format::JsonLines => "meals.jsonl",
+            Format::Csv => "meals.csv",
+        }
+    }
+
+    fn header(self) -> String {
+        match self {
+            Format::Json => "[".to_string(),
+            Format::JsonLines => String::new(),
+            Format::Csv => csv_row(CSV_COLUMNS.iter().map(|c| c.to_string())),
+        }
+    }
+
+    fn footer(self) -> &'static str {
+        match self {
+            Format::Json => "]",
+            Format::JsonLines | Format::Csv => "",
+        }
+    }
+
+    // the meal as it goes in the file, first is whether it's the first meal in it
+    fn encode(self, meal: &Meal, first: bool) -> Result<String, StoreError> {
+        let json = serde_json::to_value(meal).map_err(|e| StoreError::Malformed(e.to_string()))?;
+        Ok(match self {
+            Format::Json if first => json.to_string(),
+            Format::Json => format!(",{}", json),
+            Format::JsonLines => format!("{}\n", json),
+            Format::Csv => csv_row(CSV_COLUMNS.iter().map(|c| match json.get(*c) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(s)) => s.clone(),
+                Some(other) => other.to_string(),
+            })),
+        })
+    }
+
+    // Every meal in the file, each one on its own so one bad row doesn't sink the rest.
+    // The Err is for a file that can't be read at all.
+    pub fn decode(self, body: &[u8]) -> Result<Vec<Result<Meal, String>>, String> {
+        let text = std::str::from_utf8(body).map_err(|_| "the file has to be UTF-8")?;
+        match self {
+            Format::Json => {
+                let rows: Vec<Value> = serde_json::from_str(text)
+                    .map_err(|e| format!("expected an array of meals: {}", e))?;
+                Ok(rows
+                    .into_iter()
+                    .map(|v| serde_json::from_value(v).map_err(|e| e.to_string()))
+                    .collect())
+            }
+            Format::JsonLines => Ok(text
+                .lines()
+                .filter(|l| !l.trim().is_empty())
+                .map(|l| serde_json::from_str(l).map_err(|e| e.to_string()))
+                .collect()),
+            Format::Csv => {
+                let mut records = parse_csv(text)?.into_iter();
+                let header = records.next().ok_or("the CSV doesn't have a header row")?;
+                if let Some(unknown) = header.iter().find(|c| !CSV_COLUMNS.contains(&c.as_str())) {
+                    return Err(format!("unknown CSV column '{}'", unknown));
+                }
+                Ok(records.map(|r| csv_meal(&header, r)).collect())
+            }
+        }
+    }
+}
+
+// POST /import?format=csv&dry_run=true&existing=upsert
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImportOptions {
+    pub format: Format,
+    // check everything and say what would happen, but write nothing
+    pub dry_run: bool,
+    // whether meals that are already there get overwritten or left alone
+    pub upsert: bool,
+}
+
+impl ImportOptions {
+    pub fn from_params(params: &HashMap<String, String>) -> Result<ImportOptions, String> {
+        let mut opts = ImportOptions {
+            format: Format::Json,
+            dry_run: false,
+            upsert: false,
+        };
+        for (k, v) in params {
+            match k.as_str() {
+                "format" => opts.format = Format::parse(v)?,
+                "dry_run" => {
+                    opts.dry_run = v
+                        .parse()
+                        .map_err(|_| "dry_run has to be true or false".to_string())?
+                }
+                "existing" => {
+                    opts.upsert = match v.as_str() {
+                        "upsert" => true,
+                        "skip" => false,
+                        _ => return Err("existing has to be upsert or skip".to_string()),
+                    }
+                }
+                other => return Err(format!("unknown query parameter '{}'", other)),
+            }
+        }
+        Ok(opts)
+    }
+}
+
+// a CSV row back into a meal, by way of the same JSON the other formats use
+fn csv_meal(header: &[String], record: Vec<String>) -> Result<Meal, String> {
+    if record.len() != header.len() {
+        return Err(format!(
+            "expected {} columns, got {}",
+            header.len(),
+            record.len()
+        ));
+    }
+    let mut fields = Map::new();
+    for (column, cell) in header.iter().zip(record) {
+        let value = match column.as_str() {
+            // always there, even when empty, so validation says what's wrong with them
+            "name" | "description" => Value::String(cell),
+            _ if cell.is_empty() => continue,
+            c if CSV_NUMBERS.contains(&c) => {
+                serde_json::from_str(&cell).unwrap_or(Value::String(cell))
+            }
+            _ => Value::String(cell),
+        };
+        fields.insert(column.clone(), value);
+    }
+    serde_json::from_value(Value::Object(fields)).map_err(|e| e.to_string())
+}
+
+// RFC 4180: quoted when there's a comma, quote or line break in it, quotes doubled
+fn csv_row(cells: impl Iterator<Item = String>) -> String {
+    let cells: Vec<String> = cells
+        .map(|c| {
+            if c.contains(&[',', '"', '\n', '\r'][..]) {
+                format!("\"{}\"", c.replace('"', "\"\""))
+            } else {
+                c
+            }
+        })
+        .collect();
+    format!("{}\r\n", cells.join(","))
+}
+
+// the records in a CSV file, takes \n or \r\n line endings
+fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if cell.is_empty() => quoted = true,
+            _ if quoted => cell.push(c),
+            ',' => record.push(std::mem::take(&mut cell)),
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' => {
+                record.push(std::mem::take(&mut cell));
+                records.push(std::mem::take(&mut record));
+            }
+            _ => cell.push(c),
+        }
+    }
+    if quoted {
+        return Err("the CSV ends in the middle of a quoted cell".to_string());
+    }
+    // no line break after the last row
+    if !cell.is_empty() || !record.is_empty() {
+        record.push(cell);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+struct Export {
+    store: MealDb,
+    format: Format,
+    // None before the first page
+    after: Option<Uuid>,
+    started: bool,
+    done: bool,
+}
+
+// Every meal that isn't in the trash, a page of scan at a time so the whole table is
+// never in memory. A scan that fails halfway ends the stream with the error, by then
+// the 200 has gone out so the client sees a cut off body.
+pub fn export(store: MealDb, format: Format) -> impl Stream<Item = Result<Bytes, StoreError>> {
+    let start = Export {
+        store,
+        format,
+        after: None,
+        started: false,
+        done: false,
+    };
+    stream::unfold(start, |mut e| async move {
+        if e.done {
+            return None;
+        }
+        let chunk = next_chunk(&mut e).await;
+        if chunk.is_err() {
+            e.done = true;
+        }
+        Some((chunk, e))
+    })
+}
+
+async fn next_chunk(e: &mut Export) -> Result<Bytes, StoreError> {
+    let mut chunk = String::new();
+    if !e.started {
+        chunk.push_str(&e.format.header());
+    }
+    let q = MealQuery {
+        limit: MAX_PAGE_SIZE,
+        start_after: e.after,
+        ..MealQuery::default()
+    };
+    let page = e.store.query(&q).await?;
+    for meal in &page.items {
+        chunk.push_str(&e.format.encode(meal, !e.started)?);
+        e.started = true;
+    }
+    e.started = true;
+    match page.next_cursor {
+        Some(cursor) => e.after = Some(decode_cursor(&cursor).map_err(StoreError::Malformed)?),
+        None => {
+            chunk.push_str(e.format.footer());
+            e.done = true;
+        }
+    }
+    Ok(Bytes::from(chunk))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meal_store::InMemoryMealStore;
+    use futures::StreamExt;
+    use std::sync::Arc;
+
+    fn meals() -> Vec<Meal> {
+        vec![
+            Meal {
+                id: Uuid::new_v4(),
+                name: "Fish, \"chips\"".to_string(),
+                description: "salty\r\nand vinegary".to_string(),
+                stars: Some(4),
+                owner: Some(Uuid::new_v4()),
+                version: 3,
+                photos: Some("a.jpg|b.png".to_string()),
+                renditions: Some("a_thumb.jpg".to_string()),
+                ..Default::default()
+            },
+            Meal {
+                id: Uuid::new_v4(),
+                name: "Oats".to_string(),
+                description: "plain".to_string(),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_export_round_trips() {
+        let store: MealDb = Arc::new(InMemoryMealStore::new());
+        let mut expected = meals();
+        for m in &expected {
+            store.put(m.clone()).await.unwrap();
+        }
+        let trashed = Meal {
+            id: Uuid::new_v4(),
+            deleted_at: Some(10),
+            ..expected[1].clone()
+        };
+        store.put(trashed).await.unwrap();
+        expected.sort_by_key(|m| m.id);
+
+        for format in &[Format::Json, Format::JsonLines, Format::Csv] {
+            let chunks: Vec<Bytes> = export(store.clone(), *format)
+                .map(|c| c.unwrap())
+                .collect()
+                .await;
+            let body = chunks.concat();
+            let back: Vec<Meal> = format
+                .decode(&body)
+                .unwrap()
+                .into_iter()
+                .map(|m| m.unwrap())
+                .collect();
+            assert_eq!(back, expected, "{:?}", format);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_nothing() {
+        let store: MealDb = Arc::new(InMemoryMealStore::new());
+        let body: Vec<Bytes> = export(store, Format::Json)
+            .map(|c| c.unwrap())
+            .collect()
+            .await;
+        assert_eq!(body.concat(), b"[]".to_vec());
+    }
+
+    #[test]
+    fn test_decode_rows() {
+        let rows = Format::JsonLines
+            .decode(b"{\"name\":\"a\",\"id\":\"6a1e7ad1-7a37-4b5c-9a2a-5c3e7d3f4a10\",\"description\":\"b\",\"stars\":null}\n\nnope\n")
+            .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].as_ref().unwrap().name, "a");
+        assert!(rows[1].is_err());
+
+        let csv = "id,name,description,stars\r\n\
+                   6a1e7ad1-7a37-4b5c-9a2a-5c3e7d3f4a10,a,\"b, c\",\r\n\
+                   6a1e7ad1-7a37-4b5c-9a2a-5c3e7d3f4a11,a,b,lots\n\
+                   6a1e7ad1-7a37-4b5c-9a2a-5c3e7d3f4a12,a";
+        let rows = Format::Csv.decode(csv.as_bytes()).unwrap();
+        assert_eq!(rows.len(), 3);
+        let first = rows[0].as_ref().unwrap();
+        assert_eq!((first.description.as_str(), first.stars), ("b, c", None));
+        assert!(rows[1].is_err());
+        assert_eq!(rows[2], Err("expected 4 columns, got 2".to_string()));
+
+        assert!(Format::Csv.decode(b"id,calories\n").is_err());
+        assert!(Format::Csv.decode(b"id,name\n\"1,a\n").is_err());
+        assert!(Format::Json.decode(b"{\"name\":\"a\"}").is_err());
+        assert!(Format::Json.decode(&[0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn test_params() {
+        let params = |kv: &[(&str, &str)]| -> HashMap<String, String> {
+            kv.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        assert_eq!(Format::from_params(&params(&[])), Ok(Format::Json));
+        assert_eq!(
+            Format::from_params(&params(&[("format", "jsonl")])),
+            Ok(Format::JsonLines)
+        );
+        assert!(Format::from_params(&params(&[("format", "xml")])).is_err());
+        assert!(Format::from_params(&params(&[("limit", "3")])).is_err());
+
+        let opts = ImportOptions::from_params(&params(&[
+            ("format", "csv"),
+            ("dry_run", "true"),
+            ("existing", "upsert"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            opts,
+            ImportOptions {
+                format: Format::Csv,
+                dry_run: true,
+                upsert: true
+            }
+        );
+        assert!(!ImportOptions::from_params(&params(&[])).unwrap().upsert);
+        assert!(ImportOptions::from_params(&params(&[("existing", "replace")])).is_err());
+        assert!(ImportOptions::from_params(&params(&[("dry_run", "yes")])).is_err());
+    }
+}
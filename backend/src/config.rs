@@ -0,0 +1,621 @@
+use rusoto_core::Region;
+use serde_derive::Deserialize;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use structopt::StructOpt;
+
+// set by the release build, eg `RR_VERSION=v1.2.3 cargo build --release`
+static VERSION: Option<&str> = option_env!("RR_VERSION");
+
+// Command line flags. Every flag can also come from its RR_* environment variable,
+// anything not given here falls back to the TOML file and then the defaults.
+#[derive(StructOpt, Debug, Default)]
+#[structopt(name = "backend", about = "Refeed rampage API server")]
+pub struct Opts {
+    /// TOML config file, optional when it's the default and doesn't exist
+    #[structopt(long, env = "RR_CONFIG", parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// Address to listen on, eg 127.0.0.1:3030
+    #[structopt(long, env = "RR_BIND_ADDR")]
+    bind_addr: Option<SocketAddr>,
+    /// AWS region name, eg us-west-2
+    #[structopt(long, env = "RR_REGION")]
+    region: Option<String>,
+    /// Where meals and refeed days live: dynamodb or memory
+    #[structopt(long, env = "RR_MEAL_STORE")]
+    meal_store: Option<StoreKind>,
+    /// DynamoDB endpoint, leave unset for real DynamoDB
+    #[structopt(long, env = "RR_DYNAMODB_ENDPOINT")]
+    dynamodb_endpoint: Option<String>,
+    /// S3 endpoint, leave unset for real S3
+    #[structopt(long, env = "RR_S3_ENDPOINT")]
+    s3_endpoint: Option<String>,
+    #[structopt(long, env = "RR_MEALS_TABLE")]
+    meals_table: Option<String>,
+    #[structopt(long, env = "RR_DAYS_TABLE")]
+    days_table: Option<String>,
+    #[structopt(long, env = "RR_USERS_TABLE")]
+    users_table: Option<String>,
+    #[structopt(long, env = "RR_TOKENS_TABLE")]
+    tokens_table: Option<String>,
+    #[structopt(long, env = "RR_REVISIONS_TABLE")]
+    revisions_table: Option<String>,
+    /// How often expired login tokens get cleared out, in seconds
+    #[structopt(long, env = "RR_TOKEN_SWEEP_SECS")]
+    token_sweep_secs: Option<u64>,
+    /// How long deleted meals stay in the trash before they're purged, in seconds
+    #[structopt(long, env = "RR_TRASH_RETENTION_SECS")]
+    trash_retention_secs: Option<u64>,
+    /// How often the trash gets checked for meals to purge, in seconds
+    #[structopt(long, env = "RR_TRASH_SWEEP_SECS")]
+    trash_sweep_secs: Option<u64>,
+    /// How long an access token from /login or /token/refresh is good for, in seconds
+    #[structopt(long, env = "RR_ACCESS_TOKEN_SECS")]
+    access_token_secs: Option<u64>,
+    /// How long a refresh token is good for if it's never used, in seconds
+    #[structopt(long, env = "RR_REFRESH_TOKEN_SECS")]
+    refresh_token_secs: Option<u64>,
+    /// Mark the refresh cookie Secure, turn off for plain http local work
+    #[structopt(long, env = "RR_SECURE_COOKIES")]
+    secure_cookies: Option<bool>,
+    /// Path the refresh cookie gets sent to, /api/token when nginx serves us from under /api
+    #[structopt(long, env = "RR_REFRESH_COOKIE_PATH")]
+    refresh_cookie_path: Option<String>,
+    /// How long a just spent refresh token still works for, in seconds, so two tabs
+    /// refreshing at once don't look like a stolen token
+    #[structopt(long, env = "RR_REFRESH_GRACE_SECS")]
+    refresh_grace_secs: Option<u64>,
+    /// Failed logins in a row before a username is locked out, an IP gets five times as many
+    #[structopt(long, env = "RR_LOGIN_LOCKOUT_FAILURES")]
+    login_lockout_failures: Option<u32>,
+    /// How long a lockout lasts, in seconds
+    #[structopt(long, env = "RR_LOGIN_LOCKOUT_SECS")]
+    login_lockout_secs: Option<u64>,
+    /// Take the client address from X-Forwarded-For, only when behind our nginx
+    #[structopt(long, env = "RR_TRUST_PROXY")]
+    trust_proxy: Option<bool>,
+    #[structopt(long, env = "RR_BUCKET_NAME")]
+    bucket_name: Option<String>,
+    /// Largest photo upload accepted, in bytes
+    #[structopt(long, env = "RR_MAX_PHOTO_BYTES")]
+    max_photo_bytes: Option<u64>,
+    /// How long presigned photo URLs stay good for, in seconds
+    #[structopt(long, env = "RR_PHOTO_URL_EXPIRY_SECS")]
+    photo_url_expiry_secs: Option<u64>,
+    /// How long /health/ready waits on DynamoDB and S3 before calling them down, in milliseconds
+    #[structopt(long, env = "RR_READY_TIMEOUT_MS")]
+    ready_timeout_ms: Option<u64>,
+    /// Whether /health/ready is a 503 when the photo bucket doesn't answer, turn off to
+    /// only call it degraded since everything but photos still works without it
+    #[structopt(long, env = "RR_S3_REQUIRED")]
+    s3_required: Option<bool>,
+    /// How long requests in flight get to finish after SIGTERM or ctrl-c, in seconds
+    #[structopt(long, env = "RR_SHUTDOWN_GRACE_SECS")]
+    shutdown_grace_secs: Option<u64>,
+    /// Allowed CORS origins, comma separated in the environment variable
+    #[structopt(long = "cors-origin", env = "RR_CORS_ORIGINS", use_delimiter = true)]
+    cors_origins: Vec<String>,
+    /// Admin account made at startup if there isn't one by that name yet
+    #[structopt(long, env = "RR_ADMIN_USER")]
+    admin_user: Option<String>,
+    /// The admin account's first password, ignored once the account exists
+    #[structopt(long, env = "RR_LOGIN_PASSWORD", hide_env_values = true)]
+    login_password: Option<String>,
+    #[structopt(long, env = "RR_JWT_SECRET", hide_env_values = true)]
+    jwt_secret: Option<String>,
+}
+
+// What the TOML file can contain. Same names as the flags, with underscores.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    bind_addr: Option<SocketAddr>,
+    region: Option<String>,
+    meal_store: Option<StoreKind>,
+    dynamodb_endpoint: Option<String>,
+    s3_endpoint: Option<String>,
+    meals_table: Option<String>,
+    days_table: Option<String>,
+    users_table: Option<String>,
+    tokens_table: Option<String>,
+    revisions_table: Option<String>,
+    token_sweep_secs: Option<u64>,
+    trash_retention_secs: Option<u64>,
+    trash_sweep_secs: Option<u64>,
+    access_token_secs: Option<u64>,
+    refresh_token_secs: Option<u64>,
+    secure_cookies: Option<bool>,
+    refresh_cookie_path: Option<String>,
+    refresh_grace_secs: Option<u64>,
+    login_lockout_failures: Option<u32>,
+    login_lockout_secs: Option<u64>,
+    trust_proxy: Option<bool>,
+    bucket_name: Option<String>,
+    max_photo_bytes: Option<u64>,
+    photo_url_expiry_secs: Option<u64>,
+    ready_timeout_ms: Option<u64>,
+    s3_required: Option<bool>,
+    shutdown_grace_secs: Option<u64>,
+    cors_origins: Option<Vec<String>>,
+    admin_user: Option<String>,
+    login_password: Option<String>,
+    jwt_secret: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    Dynamodb,
+    Memory,
+}
+
+impl FromStr for StoreKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dynamodb" => Ok(StoreKind::Dynamodb),
+            "memory" => Ok(StoreKind::Memory),
+            _ => Err(format!(
+                "unknown meal store '{}', use dynamodb or memory",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(p, e) => write!(f, "couldn't read {}: {}", p.display(), e),
+            ConfigError::Parse(p, e) => write!(f, "couldn't parse {}: {}", p.display(), e),
+            ConfigError::Invalid(e) => write!(f, "invalid config: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Everything the backend needs to know at runtime, validated at startup
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind_addr: SocketAddr,
+    pub region: Region,
+    pub meal_store: StoreKind,
+    pub dynamodb_endpoint: Option<String>,
+    pub s3_endpoint: Option<String>,
+    pub meals_table: String,
+    pub days_table: String,
+    pub users_table: String,
+    pub tokens_table: String,
+    pub revisions_table: String,
+    pub token_sweep_secs: u64,
+    pub trash_retention_secs: u64,
+    pub trash_sweep_secs: u64,
+    pub access_token_secs: u64,
+    pub refresh_token_secs: u64,
+    pub secure_cookies: bool,
+    pub refresh_cookie_path: String,
+    pub refresh_grace_secs: u64,
+    pub login_lockout_failures: u32,
+    pub login_lockout_secs: u64,
+    pub trust_proxy: bool,
+    pub bucket_name: String,
+    pub max_photo_bytes: u64,
+    pub photo_url_expiry_secs: u64,
+    pub ready_timeout_ms: u64,
+    pub s3_required: bool,
+    pub shutdown_grace_secs: u64,
+    pub cors_origins: Vec<String>,
+    pub admin_user: String,
+    pub login_password: String,
+    pub jwt_secret: String,
+    pub version: String,
+}
+
+impl Config {
+    // flags and env vars, then the TOML file, then defaults
+    pub fn load() -> Result<Config, ConfigError> {
+        Config::from_opts(Opts::from_args())
+    }
+
+    // for tools that take the server's flags along with their own
+    pub fn from_opts(opts: Opts) -> Result<Config, ConfigError> {
+        let file = match &opts.config {
+            Some(path) => read_file(path)?,
+            None => {
+                let default_path = Path::new("rampage.toml");
+                if default_path.exists() {
+                    read_file(default_path)?
+                } else {
+                    FileConfig::default()
+                }
+            }
+        };
+        Config::merge(opts, file)
+    }
+
+    fn merge(opts: Opts, file: FileConfig) -> Result<Config, ConfigError> {
+        let region_name = opts
+            .region
+            .or(file.region)
+            .unwrap_or_else(|| "us-west-2".to_string());
+        let region = Region::from_str(&region_name)
+            .map_err(|_| ConfigError::Invalid(format!("unknown region '{}'", region_name)))?;
+        let cors_origins = if opts.cors_origins.is_empty() {
+            file.cors_origins.unwrap_or_else(|| {
+                vec![
+                    "http://localhost:8080".to_string(),
+                    "http://127.0.0.1:8080".to_string(),
+                ]
+            })
+        } else {
+            opts.cors_origins
+        };
+
+        let c = Config {
+            bind_addr: opts
+                .bind_addr
+                .or(file.bind_addr)
+                .unwrap_or_else(|| ([127, 0, 0, 1], 3030).into()),
+            region,
+            meal_store: opts
+                .meal_store
+                .or(file.meal_store)
+                .unwrap_or(StoreKind::Dynamodb),
+            dynamodb_endpoint: non_empty(opts.dynamodb_endpoint.or(file.dynamodb_endpoint)),
+            s3_endpoint: non_empty(opts.s3_endpoint.or(file.s3_endpoint)),
+            meals_table: opts
+                .meals_table
+                .or(file.meals_table)
+                .unwrap_or_else(|| "meals".to_string()),
+            days_table: opts
+                .days_table
+                .or(file.days_table)
+                .unwrap_or_else(|| "refeed_days".to_string()),
+            users_table: opts
+                .users_table
+                .or(file.users_table)
+                .unwrap_or_else(|| "users".to_string()),
+            tokens_table: opts
+                .tokens_table
+                .or(file.tokens_table)
+                .unwrap_or_else(|| "tokens".to_string()),
+            revisions_table: opts
+                .revisions_table
+                .or(file.revisions_table)
+                .unwrap_or_else(|| "meal_revisions".to_string()),
+            token_sweep_secs: opts
+                .token_sweep_secs
+                .or(file.token_sweep_secs)
+                .unwrap_or(10 * 60),
+            trash_retention_secs: opts
+                .trash_retention_secs
+                .or(file.trash_retention_secs)
+                .unwrap_or(30 * 24 * 60 * 60),
+            trash_sweep_secs: opts
+                .trash_sweep_secs
+                .or(file.trash_sweep_secs)
+                .unwrap_or(60 * 60),
+            access_token_secs: opts
+                .access_token_secs
+                .or(file.access_token_secs)
+                .unwrap_or(15 * 60),
+            refresh_token_secs: opts
+                .refresh_token_secs
+                .or(file.refresh_token_secs)
+                .unwrap_or(30 * 24 * 60 * 60),
+            secure_cookies: opts.secure_cookies.or(file.secure_cookies).unwrap_or(true),
+            refresh_cookie_path: opts
+                .refresh_cookie_path
+                .or(file.refresh_cookie_path)
+                .unwrap_or_else(|| "/token".to_string()),
+            refresh_grace_secs: opts
+                .refresh_grace_secs
+                .or(file.refresh_grace_secs)
+                .unwrap_or(10),
+            login_lockout_failures: opts
+                .login_lockout_failures
+                .or(file.login_lockout_failures)
+                .unwrap_or(10),
+            login_lockout_secs: opts
+                .login_lockout_secs
+                .or(file.login_lockout_secs)
+                .unwrap_or(15 * 60),
+            trust_proxy: opts.trust_proxy.or(file.trust_proxy).unwrap_or(false),
+            bucket_name: opts
+                .bucket_name
+                .or(file.bucket_name)
+                .unwrap_or_else(|| "refeed-rampage".to_string()),
+            max_photo_bytes: opts
+                .max_photo_bytes
+                .or(file.max_photo_bytes)
+                .unwrap_or(10 * 1024 * 1024),
+            photo_url_expiry_secs: opts
+                .photo_url_expiry_secs
+                .or(file.photo_url_expiry_secs)
+                .unwrap_or(60 * 60),
+            ready_timeout_ms: opts
+                .ready_timeout_ms
+                .or(file.ready_timeout_ms)
+                .unwrap_or(2000),
+            s3_required: opts.s3_required.or(file.s3_required).unwrap_or(true),
+            shutdown_grace_secs: opts
+                .shutdown_grace_secs
+                .or(file.shutdown_grace_secs)
+                .unwrap_or(30),
+            cors_origins,
+            admin_user: opts
+                .admin_user
+                .or(file.admin_user)
+                .unwrap_or_else(|| "admin".to_string()),
+            login_password: opts
+                .login_password
+                .or(file.login_password)
+                .unwrap_or_default(),
+            jwt_secret: opts.jwt_secret.or(file.jwt_secret).unwrap_or_default(),
+            version: VERSION.unwrap_or("dev").to_string(),
+        };
+        c.validate()?;
+        Ok(c)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.login_password.is_empty() {
+            return Err(ConfigError::Invalid(
+                "login_password must be set".to_string(),
+            ));
+        }
+        if self.jwt_secret.len() < 16 {
+            return Err(ConfigError::Invalid(
+                "jwt_secret must be at least 16 characters".to_string(),
+            ));
+        }
+        if self.admin_user.trim().is_empty() {
+            return Err(ConfigError::Invalid("admin_user must be set".to_string()));
+        }
+        let mut tables = vec![
+            &self.meals_table,
+            &self.days_table,
+            &self.users_table,
+            &self.tokens_table,
+            &self.revisions_table,
+        ];
+        if tables.iter().any(|t| t.is_empty()) || self.bucket_name.is_empty() {
+            return Err(ConfigError::Invalid(
+                "table names and bucket_name can't be empty".to_string(),
+            ));
+        }
+        tables.sort();
+        tables.dedup();
+        if tables.len() != 5 {
+            return Err(ConfigError::Invalid(
+                "meals_table, days_table, users_table, tokens_table and revisions_table need to be different tables"
+                    .to_string(),
+            ));
+        }
+        if self.token_sweep_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "token_sweep_secs must be more than zero".to_string(),
+            ));
+        }
+        if self.trash_retention_secs == 0 || self.trash_sweep_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "trash_retention_secs and trash_sweep_secs must be more than zero".to_string(),
+            ));
+        }
+        if self.access_token_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "access_token_secs must be more than zero".to_string(),
+            ));
+        }
+        // otherwise every refresh hands out a token that outlives its refresh token
+        if self.refresh_token_secs <= self.access_token_secs {
+            return Err(ConfigError::Invalid(
+                "refresh_token_secs must be longer than access_token_secs".to_string(),
+            ));
+        }
+        if !self.refresh_cookie_path.starts_with('/') || self.refresh_cookie_path.contains(';') {
+            return Err(ConfigError::Invalid(
+                "refresh_cookie_path must be a path starting with /".to_string(),
+            ));
+        }
+        // it's for requests racing each other, not for keeping spent tokens around
+        if self.refresh_grace_secs >= self.access_token_secs {
+            return Err(ConfigError::Invalid(
+                "refresh_grace_secs must be shorter than access_token_secs".to_string(),
+            ));
+        }
+        if self.login_lockout_failures == 0 || self.login_lockout_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "login_lockout_failures and login_lockout_secs must be more than zero".to_string(),
+            ));
+        }
+        if self.max_photo_bytes == 0 {
+            return Err(ConfigError::Invalid(
+                "max_photo_bytes must be more than zero".to_string(),
+            ));
+        }
+        // SigV4 presigned URLs top out at a week
+        if self.photo_url_expiry_secs == 0 || self.photo_url_expiry_secs > 7 * 24 * 60 * 60 {
+            return Err(ConfigError::Invalid(
+                "photo_url_expiry_secs must be between 1 and 604800".to_string(),
+            ));
+        }
+        if self.ready_timeout_ms == 0 {
+            return Err(ConfigError::Invalid(
+                "ready_timeout_ms must be more than zero".to_string(),
+            ));
+        }
+        if self.cors_origins.iter().any(|o| !o.starts_with("http")) {
+            return Err(ConfigError::Invalid(
+                "cors_origins must be http(s) origins".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // local endpoints get a custom region so rusoto talks to them instead of AWS
+    pub fn dynamodb_region(&self) -> Region {
+        self.region_for(&self.dynamodb_endpoint)
+    }
+
+    pub fn s3_region(&self) -> Region {
+        self.region_for(&self.s3_endpoint)
+    }
+
+    fn region_for(&self, endpoint: &Option<String>) -> Region {
+        match endpoint {
+            Some(e) => Region::Custom {
+                name: self.region.name().to_string(),
+                endpoint: e.clone(),
+            },
+            None => self.region.clone(),
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<FileConfig, ConfigError> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+    toml::from_str(&contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+}
+
+fn non_empty(s: Option<String>) -> Option<String> {
+    s.filter(|x| !x.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_with_secrets() -> FileConfig {
+        FileConfig {
+            login_password: Some("hunter2".to_string()),
+            jwt_secret: Some("sixteencharacter".to_string()),
+            ..FileConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let c = Config::merge(Opts::default(), file_with_secrets()).unwrap();
+        assert_eq!(c.bind_addr, ([127, 0, 0, 1], 3030).into());
+        assert_eq!(c.region, Region::UsWest2);
+        assert_eq!(c.meal_store, StoreKind::Dynamodb);
+        assert_eq!(c.meals_table, "meals");
+        assert_eq!(c.days_table, "refeed_days");
+        assert_eq!(c.users_table, "users");
+        assert_eq!(c.tokens_table, "tokens");
+        assert_eq!(c.revisions_table, "meal_revisions");
+        assert_eq!(c.token_sweep_secs, 600);
+        assert_eq!(c.trash_retention_secs, 30 * 24 * 60 * 60);
+        assert_eq!(c.trash_sweep_secs, 3600);
+        assert_eq!(c.access_token_secs, 900);
+        assert_eq!(c.refresh_token_secs, 30 * 24 * 60 * 60);
+        assert!(c.secure_cookies);
+        assert_eq!(c.refresh_cookie_path, "/token");
+        assert_eq!(c.refresh_grace_secs, 10);
+        assert_eq!(c.login_lockout_failures, 10);
+        assert_eq!(c.login_lockout_secs, 900);
+        assert!(!c.trust_proxy);
+        assert_eq!(c.admin_user, "admin");
+        assert_eq!(c.bucket_name, "refeed-rampage");
+        assert_eq!(c.max_photo_bytes, 10 * 1024 * 1024);
+        assert_eq!(c.photo_url_expiry_secs, 3600);
+        assert_eq!(c.ready_timeout_ms, 2000);
+        assert!(c.s3_required);
+        assert_eq!(c.shutdown_grace_secs, 30);
+        assert_eq!(c.dynamodb_region(), Region::UsWest2);
+    }
+
+    #[test]
+    fn test_flags_beat_file() {
+        let file: FileConfig = toml::from_str(
+            r#"
+            bind_addr = "0.0.0.0:4000"
+            region = "us-east-1"
+            meal_store = "memory"
+            s3_endpoint = "http://localhost:9000"
+            login_password = "hunter2"
+            jwt_secret = "sixteencharacter"
+            "#,
+        )
+        .unwrap();
+        let opts = Opts {
+            bind_addr: Some(([127, 0, 0, 1], 5000).into()),
+            ..Opts::default()
+        };
+        let c = Config::merge(opts, file).unwrap();
+        assert_eq!(c.bind_addr, ([127, 0, 0, 1], 5000).into());
+        assert_eq!(c.meal_store, StoreKind::Memory);
+        assert_eq!(
+            c.s3_region(),
+            Region::Custom {
+                name: "us-east-1".to_string(),
+                endpoint: "http://localhost:9000".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(Config::merge(Opts::default(), FileConfig::default()).is_err());
+
+        let short_secret = FileConfig {
+            jwt_secret: Some("short".to_string()),
+            ..file_with_secrets()
+        };
+        assert!(Config::merge(Opts::default(), short_secret).is_err());
+
+        let bad_region = FileConfig {
+            region: Some("moon-1".to_string()),
+            ..file_with_secrets()
+        };
+        assert!(Config::merge(Opts::default(), bad_region).is_err());
+
+        let same_tables = FileConfig {
+            days_table: Some("meals".to_string()),
+            ..file_with_secrets()
+        };
+        assert!(Config::merge(Opts::default(), same_tables).is_err());
+        let same_tables = FileConfig {
+            tokens_table: Some("users".to_string()),
+            ..file_with_secrets()
+        };
+        assert!(Config::merge(Opts::default(), same_tables).is_err());
+        let same_tables = FileConfig {
+            revisions_table: Some("meals".to_string()),
+            ..file_with_secrets()
+        };
+        assert!(Config::merge(Opts::default(), same_tables).is_err());
+
+        let short_refresh = FileConfig {
+            access_token_secs: Some(3600),
+            refresh_token_secs: Some(60),
+            ..file_with_secrets()
+        };
+        assert!(Config::merge(Opts::default(), short_refresh).is_err());
+        let long_grace = FileConfig {
+            refresh_grace_secs: Some(900),
+            ..file_with_secrets()
+        };
+        assert!(Config::merge(Opts::default(), long_grace).is_err());
+        let relative_path = FileConfig {
+            refresh_cookie_path: Some("token".to_string()),
+            ..file_with_secrets()
+        };
+        assert!(Config::merge(Opts::default(), relative_path).is_err());
+
+        assert!(toml::from_str::<FileConfig>("not_a_setting = 1").is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use crate::meal_store::StoreError;
+use crate::tables::DynamoClient;
+use async_trait::async_trait;
+use dynomite::{
+    dynamodb::{DeleteItemInput, DynamoDb, GetItemInput, PutItemInput, ScanInput},
+    FromAttributes, Item,
+};
+use shared::RefeedDay;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+// the refeed days everyone has logged, one per date
+pub type DayDb = Arc<dyn DayStore>;
+
+#[async_trait]
+pub trait DayStore: Send + Sync {
+    async fn get(&self, id: Uuid) -> Result<Option<RefeedDay>, StoreError>;
+    async fn list(&self) -> Result<Vec<RefeedDay>, StoreError>;
+    async fn put(&self, day: RefeedDay) -> Result<(), StoreError>;
+    async fn delete(&self, id: Uuid) -> Result<(), StoreError>;
+}
+
+pub struct DynamoDayStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoDayStore {
+    pub fn new(client: DynamoClient, table_name: &str) -> DynamoDayStore {
+        DynamoDayStore {
+            client,
+            table_name: table_name.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl DayStore for DynamoDayStore {
+    async fn get(&self, id: Uuid) -> Result<Option<RefeedDay>, StoreError> {
+        let d = RefeedDay {
+            id,
+            ..Default::default()
+        };
+        let item = self
+            .client
+            .get_item(GetItemInput {
+                table_name: self.table_name.clone(),
+                key: d.key(),
+                ..GetItemInput::default()
+            })
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+        match item.item {
+            Some(attrs) => RefeedDay::from_attrs(attrs)
+                .map(Some)
+                .map_err(|e| StoreError::Malformed(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<RefeedDay>, StoreError> {
+        let mut days = Vec::new();
+        let mut start_key = None;
+        loop {
+            let scanned = self
+                .client
+                .scan(ScanInput {
+                    table_name: self.table_name.clone(),
+                    exclusive_start_key: start_key,
+                    ..ScanInput::default()
+                })
+                .await
+                .map_err(|e| StoreError::Backend(e.to_string()))?;
+            for attrs in scanned.items.unwrap_or_default() {
+                days.push(
+                    RefeedDay::from_attrs(attrs)
+                        .map_err(|e| StoreError::Malformed(e.to_string()))?,
+                );
+            }
+            start_key = scanned.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(days);
+            }
+        }
+    }
+
+    async fn put(&self, day: RefeedDay) -> Result<(), StoreError> {
+        self.client
+            .put_item(PutItemInput {
+                table_name: self.table_name.clone(),
+                item: day.into(),
+                ..PutItemInput::default()
+            })
+            .await
+            .map(|_| ())
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), StoreError> {
+        let d = RefeedDay {
+            id,
+            ..Default::default()
+        };
+        self.client
+            .delete_item(DeleteItemInput {
+                table_name: self.table_name.clone(),
+                key: d.key(),
+                ..DeleteItemInput::default()
+            })
+            .await
+            .map(|_| ())
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryDayStore {
+    days: Mutex<HashMap<Uuid, RefeedDay>>,
+}
+
+impl InMemoryDayStore {
+    pub fn new() -> InMemoryDayStore {
+        InMemoryDayStore::default()
+    }
+}
+
+#[async_trait]
+impl DayStore for InMemoryDayStore {
+    async fn get(&self, id: Uuid) -> Result<Option<RefeedDay>, StoreError> {
+        Ok(self.days.lock().await.get(&id).cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<RefeedDay>, StoreError> {
+        Ok(self.days.lock().await.values().cloned().collect())
+    }
+
+    async fn put(&self, day: RefeedDay) -> Result<(), StoreError> {
+        self.days.lock().await.insert(day.id, day);
+        Ok(())
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), StoreError> {
+        self.days.lock().await.remove(&id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_memory_round_trip() {
+        let store = InMemoryDayStore::new();
+        let d = RefeedDay {
+            id: Uuid::new_v4(),
+            date: "2020-08-01".to_string(),
+            meals: vec![Uuid::new_v4()],
+            notes: "Slept like a rock".to_string(),
+            energy: Some(4),
+            ..Default::default()
+        };
+
+        store.put(d.clone()).await.unwrap();
+        assert_eq!(store.get(d.id).await.unwrap(), Some(d.clone()));
+        assert_eq!(store.list().await.unwrap(), vec![d.clone()]);
+
+        store.delete(d.id).await.unwrap();
+        assert_eq!(store.get(d.id).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_dynamo_attrs_round_trip() {
+        let d = RefeedDay {
+            id: Uuid::new_v4(),
+            date: "2020-08-01".to_string(),
+            meals: vec![Uuid::new_v4(), Uuid::new_v4()],
+            notes: "".to_string(),
+            mood: Some(5),
+            ..Default::default()
+        };
+        let attrs: dynomite::Attributes = d.clone().into();
+        assert_eq!(RefeedDay::from_attrs(attrs).unwrap(), d);
+    }
+}
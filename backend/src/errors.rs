@@ -0,0 +1,247 @@
+use crate::backend_types::ErrorResp;
+use crate::meal_store::StoreError;
+use std::convert::Infallible;
+use warp::http::StatusCode;
+use warp::reject::{
+    InvalidHeader, InvalidQuery, LengthRequired, MethodNotAllowed, MissingCookie, MissingHeader,
+    PayloadTooLarge, UnsupportedMediaType,
+};
+use warp::Rejection;
+
+// Everything that can go wrong with a request, as far as the client is concerned. Each one
+// goes out as an ErrorResp with its code so clients don't have to parse messages.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiError {
+    BadRequest(String),
+    // no token, or one we don't know about
+    Unauthorized,
+    Forbidden(String),
+    NotFound(String),
+    MethodNotAllowed,
+    Conflict(String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+    Unprocessable(String),
+    TooManyRequests(String),
+    Internal(String),
+    // something we depend on isn't there right now, eg the photo bucket
+    Unavailable(String),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    // stable, clients can match on these
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::MethodNotAllowed => "method_not_allowed",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::Unprocessable(_) => "unprocessable",
+            ApiError::TooManyRequests(_) => "too_many_requests",
+            ApiError::Internal(_) => "internal",
+            ApiError::Unavailable(_) => "unavailable",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ApiError::Unauthorized => "missing or invalid credentials".to_string(),
+            ApiError::MethodNotAllowed => "that method isn't allowed here".to_string(),
+            ApiError::BadRequest(m)
+            | ApiError::Forbidden(m)
+            | ApiError::NotFound(m)
+            | ApiError::Conflict(m)
+            | ApiError::PayloadTooLarge(m)
+            | ApiError::UnsupportedMediaType(m)
+            | ApiError::Unprocessable(m)
+            | ApiError::TooManyRequests(m)
+            | ApiError::Internal(m)
+            | ApiError::Unavailable(m) => m.clone(),
+        }
+    }
+
+    pub fn body(&self) -> ErrorResp {
+        ErrorResp {
+            error: self.message(),
+            code: self.code().to_string(),
+        }
+    }
+}
+
+impl warp::reject::Reject for ApiError {}
+
+impl warp::Reply for ApiError {
+    fn into_response(self) -> warp::reply::Response {
+        warp::reply::with_status(warp::reply::json(&self.body()), self.status()).into_response()
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(e: StoreError) -> ApiError {
+        match e {
+            StoreError::Conflict => ApiError::Conflict(e.to_string()),
+            _ => ApiError::Internal(e.to_string()),
+        }
+    }
+}
+
+// for the auth closures, they reject with this when the token's no good
+pub fn unauthorized() -> Rejection {
+    warp::reject::custom(ApiError::Unauthorized)
+}
+
+// Turns whatever made every route reject into a proper status and ErrorResp. A request
+// gets rejected by each route it doesn't match, so look for the most telling reason first:
+// ours, then problems with the body or headers, then the method, then nothing matched.
+pub async fn handle_rejection(err: Rejection) -> Result<impl warp::Reply, Infallible> {
+    let e = if let Some(e) = err.find::<ApiError>() {
+        e.clone()
+    } else if let Some(e) = err.find::<warp::body::BodyDeserializeError>() {
+        ApiError::BadRequest(e.to_string())
+    } else if err.find::<PayloadTooLarge>().is_some() {
+        ApiError::PayloadTooLarge("request body is too big".to_string())
+    } else if err.find::<UnsupportedMediaType>().is_some() {
+        ApiError::UnsupportedMediaType("wrong content-type for this request".to_string())
+    } else if let Some(e) = err.find::<MissingHeader>() {
+        if e.name().eq_ignore_ascii_case("authorization") {
+            ApiError::Unauthorized
+        } else {
+            ApiError::BadRequest(e.to_string())
+        }
+    } else if let Some(e) = err.find::<InvalidHeader>() {
+        // multipart bodies without a multipart content-type end up here
+        if e.name().eq_ignore_ascii_case("content-type") {
+            ApiError::UnsupportedMediaType(e.to_string())
+        } else {
+            ApiError::BadRequest(e.to_string())
+        }
+    } else if let Some(e) = err.find::<InvalidQuery>() {
+        ApiError::BadRequest(e.to_string())
+    } else if let Some(e) = err.find::<LengthRequired>() {
+        ApiError::BadRequest(e.to_string())
+    } else if let Some(e) = err.find::<MissingCookie>() {
+        ApiError::BadRequest(e.to_string())
+    } else if err.find::<MethodNotAllowed>().is_some() {
+        ApiError::MethodNotAllowed
+    } else if err.is_not_found() {
+        ApiError::NotFound("nothing here".to_string())
+    } else {
+        error!("unhandled rejection: {:?}", err);
+        ApiError::Internal("something went wrong".to_string())
+    };
+    Ok(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::Filter;
+
+    #[test]
+    fn test_store_errors() {
+        assert_eq!(ApiError::from(StoreError::Conflict).code(), "conflict");
+        assert_eq!(
+            ApiError::from(StoreError::Backend("nope".to_string())).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejections() {
+        let routes = warp::path!("things" / u32)
+            .and(warp::post())
+            .and(warp::header::<String>("Authorization"))
+            .and(warp::body::content_length_limit(16))
+            .and(warp::body::json())
+            .map(|_: u32, _: String, _: serde_json::Value| "ok")
+            .or(warp::path!("private")
+                .and(warp::get())
+                .and_then(|| async { Err::<&str, _>(unauthorized()) }))
+            .recover(handle_rejection);
+
+        let status =
+            move |req: warp::test::RequestBuilder| async move { req.reply(&routes).await.status() };
+        assert_eq!(
+            status(warp::test::request().path("/nope")).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(warp::test::request().path("/things/x").method("POST")).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(warp::test::request().path("/things/1")).await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            status(warp::test::request().path("/things/1").method("POST")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(warp::test::request().path("/private")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let authed = || {
+            warp::test::request()
+                .path("/things/1")
+                .method("POST")
+                .header("Authorization", "bearer: x")
+        };
+        assert_eq!(
+            status(
+                authed()
+                    .header("content-type", "application/json")
+                    .body("{nope")
+            )
+            .await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(
+                authed()
+                    .header("content-type", "application/json")
+                    .body("[1, 2, 3, 4, 5, 6, 7, 8, 9]")
+            )
+            .await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            status(authed().header("content-type", "text/plain").body("{}")).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(
+            status(
+                authed()
+                    .header("content-type", "application/json")
+                    .body("{}")
+            )
+            .await,
+            StatusCode::OK
+        );
+
+        let resp = warp::test::request().path("/nope").reply(&routes).await;
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["code"], "not_found");
+    }
+}
@@ -1,45 +1,1642 @@
-use crate::s3_interactions;
-use dynomite::{
-    dynamodb::{DynamoDb, DynamoDbClient, GetItemInput},
-    FromAttributes, Item,
-};
-use shared::Meal;
+use crate::backend_types;
+use crate::bulk::{self, Format, ImportOptions};
+use crate::config::Config;
+use crate::day_store::DayDb;
+use crate::errors::ApiError;
+use crate::meal_store::{MealDb, MealPatch, StoreError};
+use crate::revision_store::RevisionDb;
+use crate::s3_interactions::{self, Presigner};
+use crate::thumbnails;
+use crate::token_store::{self, JwtDb};
+use crate::user_store::{self, User, UserDb};
+use bytes::Buf;
+use chrono::NaiveDate;
+use futures::StreamExt;
+use rusoto_s3::S3Client;
+use shared::{ImportOutcome, ImportReport, ImportRow, Meal, MealDetail, RefeedDay, RevisionAction};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use uuid::Uuid;
 use warp::http::StatusCode;
+use warp::multipart::FormData;
 
 pub async fn specific_meal(
     i: Uuid,
-    client: dynomite::retry::RetryingDynamoDb<DynamoDbClient>,
+    store: MealDb,
+    presigner: Presigner,
 ) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
-    let m = Meal {
-        id: i,
-        ..Default::default()
-    };
-    let item = client
-        .get_item(GetItemInput {
-            table_name: "meals".to_string(),
-            key: m.key(),
-            ..GetItemInput::default()
-        })
-        .await
-        .map(|result| result.item.map(Meal::from_attrs));
-    match item {
-        Ok(item_found) => {
+    match store.get(i).await {
+        Ok(Some(item_found)) if !item_found.in_trash() => {
             info!("success, item be all {:?}", item_found);
-            // TODO: get image locations here and return them with presigned URLs
-            // something similar to this but working:
-            // let _images = s3_interactions::keys_from_list(&item_found.unwrap().unwrap().photos.unwrap())
-
-            // temporarily get rid of compiler warnings about unused function:
-            let _images = s3_interactions::keys_from_list("fake");
-            let r = warp::reply::json(&item_found.unwrap().unwrap());
+            let keys = s3_interactions::keys_from_list(item_found.photos.as_deref().unwrap_or(""));
+            let renditions =
+                s3_interactions::keys_from_list(item_found.renditions.as_deref().unwrap_or(""));
+            let photos = match presigner.photos(&keys, &renditions).await {
+                Ok(p) => p,
+                Err(e) => {
+                    info!("couldn't presign photo URLs: {:?}", e);
+                    return Ok(Box::new(ApiError::Internal(e.to_string())));
+                }
+            };
+            let r = warp::reply::json(&MealDetail::new(item_found, photos));
             return Ok(Box::new(warp::reply::with_status(r, StatusCode::OK)));
         }
+        Ok(_) => {
+            info!("no meal with id {}", i);
+            return Ok(Box::new(ApiError::NotFound("no such meal".to_string())));
+        }
         Err(e) => info!("It blew up :( {:?}", e),
     }
-    let r = warp::reply::json(&());
-    Ok(Box::new(warp::reply::with_status(
-        r,
-        StatusCode::BAD_REQUEST,
+    Ok(Box::new(ApiError::Internal(
+        "couldn't look up the meal".to_string(),
     )))
 }
+
+// curl -i -X POST -H "Authorization: bearer: $JWT" -F "photo=@burrito.jpg;type=image/jpeg" http://127.0.0.1:3030/meals/f11b1c5e-d6d8-4dce-8a9d-9e05d870b881/photos
+pub async fn upload_photo(
+    i: Uuid,
+    caller: Uuid,
+    form: FormData,
+    store: MealDb,
+    users: UserDb,
+    s3: S3Client,
+    config: Arc<Config>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let meal = match changeable_meal(i, caller, &store, &users).await {
+        Ok(m) => m,
+        Err(resp) => return Ok(resp),
+    };
+
+    let (content_type, data) = match photo_from_form(form, config.max_photo_bytes).await {
+        Ok(p) => p,
+        Err(e) => return Ok(Box::new(e)),
+    };
+    // no surprises: what they said it was has to match what it is
+    if s3_interactions::sniff_content_type(&data) != Some(content_type.as_str()) {
+        return Ok(Box::new(ApiError::UnsupportedMediaType(
+            "photo contents don't match its content type".to_string(),
+        )));
+    }
+    let file_name = match s3_interactions::new_photo_name(&content_type) {
+        Some(n) => n,
+        None => {
+            return Ok(Box::new(ApiError::UnsupportedMediaType(
+                "photos must be jpeg, png or webp".to_string(),
+            )))
+        }
+    };
+    let key = s3_interactions::photo_key(i, &file_name);
+
+    // decode before storing anything so a corrupt photo leaves nothing behind
+    let ct = content_type.clone();
+    let made = tokio::task::spawn_blocking(move || {
+        let renditions = thumbnails::make_renditions(&data, &ct);
+        (renditions, data)
+    })
+    .await;
+    let (renditions, data) = match made {
+        Ok((Ok(renditions), data)) => (renditions, data),
+        Ok((Err(e), _)) => {
+            info!("couldn't make thumbnails for {}: {}", key, e);
+            return Ok(Box::new(ApiError::Unprocessable(e.to_string())));
+        }
+        Err(e) => {
+            info!("thumbnail task blew up: {:?}", e);
+            return Ok(Box::new(ApiError::Internal(e.to_string())));
+        }
+    };
+
+    if let Err(e) =
+        s3_interactions::put_photo(&s3, &config.bucket_name, &key, &content_type, data).await
+    {
+        info!("couldn't store photo {}: {:?}", key, e);
+        return Ok(Box::new(ApiError::Internal(e.to_string())));
+    }
+    let mut new_renditions = Vec::new();
+    for r in renditions {
+        let r_key = s3_interactions::rendition_key(&key, r.name, r.content_type);
+        if let Err(e) =
+            s3_interactions::put_photo(&s3, &config.bucket_name, &r_key, r.content_type, r.data)
+                .await
+        {
+            info!("couldn't store rendition {}: {:?}", r_key, e);
+            forget_photos(&s3, &config.bucket_name, &key, &new_renditions).await;
+            return Ok(Box::new(ApiError::Internal(e.to_string())));
+        }
+        new_renditions.push(r_key);
+    }
+
+    let saved = save_photo_change(meal, &store, |m| {
+        let mut keys = s3_interactions::keys_from_list(m.photos.as_deref().unwrap_or(""));
+        keys.push(key.clone());
+        let mut rendition_keys =
+            s3_interactions::keys_from_list(m.renditions.as_deref().unwrap_or(""));
+        rendition_keys.extend(new_renditions.iter().cloned());
+        m.photos = s3_interactions::list_from_keys(&keys);
+        m.renditions = s3_interactions::list_from_keys(&rendition_keys);
+    })
+    .await;
+    match saved {
+        Ok(Some(meal)) => {
+            info!("added photo to meal {}", i);
+            let r = warp::reply::json(&meal);
+            Ok(Box::new(warp::reply::with_status(r, StatusCode::CREATED)))
+        }
+        Ok(None) => {
+            forget_photos(&s3, &config.bucket_name, &key, &new_renditions).await;
+            Ok(Box::new(ApiError::NotFound("no such meal".to_string())))
+        }
+        Err(e) => {
+            info!("stored the photo but couldn't update the meal: {:?}", e);
+            forget_photos(&s3, &config.bucket_name, &key, &new_renditions).await;
+            Ok(Box::new(ApiError::Internal(e.to_string())))
+        }
+    }
+}
+
+// the meal never got to hear about them, so nothing would ever clean them up otherwise
+async fn forget_photos(s3: &S3Client, bucket_name: &str, key: &str, renditions: &[String]) {
+    for k in renditions.iter().map(|k| k.as_str()).chain(Some(key)) {
+        if let Err(e) = s3_interactions::delete_photo(s3, bucket_name, k).await {
+            warn!("couldn't clean up {}, it's orphaned now: {:?}", k, e);
+        }
+    }
+}
+
+// curl -i -X DELETE -H "Authorization: bearer: $JWT" http://127.0.0.1:3030/meals/f11b1c5e-d6d8-4dce-8a9d-9e05d870b881/photos/<file name>
+pub async fn delete_photo(
+    i: Uuid,
+    file_name: String,
+    caller: Uuid,
+    store: MealDb,
+    users: UserDb,
+    s3: S3Client,
+    config: Arc<Config>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let meal = match changeable_meal(i, caller, &store, &users).await {
+        Ok(m) => m,
+        Err(resp) => return Ok(resp),
+    };
+    let key = s3_interactions::photo_key(i, &file_name);
+    let keys = s3_interactions::keys_from_list(meal.photos.as_deref().unwrap_or(""));
+    if !keys.contains(&key) {
+        return Ok(Box::new(ApiError::NotFound(
+            "meal has no such photo".to_string(),
+        )));
+    }
+
+    let rendition_keys = s3_interactions::keys_from_list(meal.renditions.as_deref().unwrap_or(""));
+    let doomed = s3_interactions::renditions_of(&key, &rendition_keys);
+
+    for k in doomed.iter().chain(std::iter::once(&key)) {
+        if let Err(e) = s3_interactions::delete_photo(&s3, &config.bucket_name, k).await {
+            info!("couldn't delete photo {}: {:?}", k, e);
+            return Ok(Box::new(ApiError::Internal(e.to_string())));
+        }
+    }
+    let saved = save_photo_change(meal, &store, |m| {
+        let mut keys = s3_interactions::keys_from_list(m.photos.as_deref().unwrap_or(""));
+        let mut rendition_keys =
+            s3_interactions::keys_from_list(m.renditions.as_deref().unwrap_or(""));
+        keys.retain(|k| k != &key);
+        rendition_keys.retain(|k| !doomed.contains(k));
+        m.photos = s3_interactions::list_from_keys(&keys);
+        m.renditions = s3_interactions::list_from_keys(&rendition_keys);
+    })
+    .await;
+    match saved {
+        // gone already is gone
+        Ok(_) => {
+            info!("removed photo {} from meal {}", key, i);
+            Ok(Box::new(StatusCode::NO_CONTENT))
+        }
+        Err(e) => {
+            info!("deleted the photo but couldn't update the meal: {:?}", e);
+            Ok(Box::new(ApiError::Internal(e.to_string())))
+        }
+    }
+}
+
+// Adding or removing a photo doesn't depend on the rest of the meal, so if someone else
+// saves it first just make the same change to their copy. None if the meal went away.
+async fn save_photo_change(
+    mut meal: Meal,
+    store: &MealDb,
+    change: impl Fn(&mut Meal),
+) -> Result<Option<Meal>, StoreError> {
+    for _ in 0..5 {
+        let expected = meal.version;
+        change(&mut meal);
+        meal.version = expected + 1;
+        match store.put_if_version(meal.clone(), expected).await {
+            Ok(_) => return Ok(Some(meal)),
+            Err(StoreError::Conflict) => match store.get(meal.id).await? {
+                Some(fresh) => meal = fresh,
+                None => return Ok(None),
+            },
+            Err(e) => return Err(e),
+        }
+    }
+    Err(StoreError::Conflict)
+}
+
+// a meal's owner can change it, and admins can change anything
+pub async fn can_change_meal(
+    meal: &Meal,
+    caller: Uuid,
+    users: &UserDb,
+) -> Result<bool, StoreError> {
+    if meal.owner == Some(caller) {
+        return Ok(true);
+    }
+    Ok(users.get(caller).await?.is_some_and(|u| u.admin))
+}
+
+// Whether the caller can save a meal under an id that isn't in the table. A purged meal's
+// id still has its history, and whoever saved it again would get to read and restore all
+// of it, so only whoever first saved it or an admin can. Ids nobody has used are free.
+pub async fn can_recreate(
+    i: Uuid,
+    caller: Uuid,
+    revisions: &RevisionDb,
+    users: &UserDb,
+) -> Result<bool, StoreError> {
+    match revisions.get(i, 1).await? {
+        Some(first) => can_change_meal(&first.meal, caller, users).await,
+        None => Ok(true),
+    }
+}
+
+// the 403 for when can_change_meal says no
+pub fn not_yours() -> ApiError {
+    ApiError::Forbidden("only the meal's owner or an admin can change it".to_string())
+}
+
+// the meal if it's there and the caller is allowed to change it
+async fn changeable_meal(
+    i: Uuid,
+    caller: Uuid,
+    store: &MealDb,
+    users: &UserDb,
+) -> Result<Meal, Box<dyn warp::Reply>> {
+    let meal = match store.get(i).await {
+        Ok(Some(m)) if !m.in_trash() => m,
+        Ok(_) => return Err(Box::new(ApiError::NotFound("no such meal".to_string()))),
+        Err(e) => {
+            info!("couldn't look up meal {}: {:?}", i, e);
+            return Err(Box::new(ApiError::Internal(e.to_string())));
+        }
+    };
+    match can_change_meal(&meal, caller, users).await {
+        Ok(true) => Ok(meal),
+        Ok(false) => Err(Box::new(not_yours())),
+        Err(e) => Err(Box::new(ApiError::Internal(e.to_string()))),
+    }
+}
+
+// pull the "photo" part out of the upload, along with its declared content type
+async fn photo_from_form(
+    mut form: FormData,
+    max_bytes: u64,
+) -> Result<(String, Vec<u8>), ApiError> {
+    while let Some(part) = form.next().await {
+        let part = part.map_err(|e| ApiError::BadRequest(e.to_string()))?;
+        if part.name() != "photo" {
+            continue;
+        }
+        let content_type = part.content_type().unwrap_or_default().to_string();
+        let mut data = Vec::new();
+        let mut chunks = part.stream();
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk.map_err(|e| ApiError::BadRequest(e.to_string()))?;
+            data.extend_from_slice(chunk.bytes());
+            if data.len() as u64 > max_bytes {
+                return Err(ApiError::PayloadTooLarge(format!(
+                    "photos can't be bigger than {} bytes",
+                    max_bytes
+                )));
+            }
+        }
+        if data.is_empty() {
+            return Err(ApiError::BadRequest("photo is empty".to_string()));
+        }
+        return Ok((content_type, data));
+    }
+    Err(ApiError::BadRequest(
+        "expected a multipart field named photo".to_string(),
+    ))
+}
+
+// The history is nice to have but the change already went through, so losing a
+// revision gets logged rather than failing the request.
+pub async fn record_revision(
+    revisions: &RevisionDb,
+    meal: Meal,
+    action: RevisionAction,
+    changed_by: Uuid,
+) {
+    let id = meal.id;
+    if let Err(e) = revisions.add(meal, action, changed_by).await {
+        error!("couldn't record a revision of meal {}: {:?}", id, e);
+    }
+}
+
+// Only the fields in the patch get written, so nobody's other edits get clobbered. Those
+// fields get the same checks as a create, the ones it leaves alone aren't its business.
+// curl -i -X PATCH -H "Authorization: bearer: $JWT" -H "content-type: application/merge-patch+json" -d '{"stars": 5}' localhost:3030/meals/$ID
+pub async fn patch_meal(
+    i: Uuid,
+    caller: Uuid,
+    body: bytes::Bytes,
+    store: MealDb,
+    revisions: RevisionDb,
+    users: UserDb,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let patch = match serde_json::from_slice(&body)
+        .map_err(|e| e.to_string())
+        .and_then(|j| MealPatch::from_json(&j))
+    {
+        Ok(p) => p,
+        Err(e) => return Ok(Box::new(ApiError::BadRequest(e))),
+    };
+    if let Err(e) = validate_patch(&patch) {
+        return Ok(Box::new(ApiError::BadRequest(e)));
+    }
+    // if it changes between checking and writing, check the new version instead
+    for _ in 0..5 {
+        let meal = match changeable_meal(i, caller, &store, &users).await {
+            Ok(m) => m,
+            Err(resp) => return Ok(resp),
+        };
+        if patch.is_empty() {
+            let r = warp::reply::json(&meal);
+            return Ok(Box::new(warp::reply::with_status(r, StatusCode::OK)));
+        }
+        match store.patch(i, &patch, meal.version).await {
+            Ok(patched) => {
+                record_revision(&revisions, patched.clone(), RevisionAction::Updated, caller).await;
+                let r = warp::reply::json(&patched);
+                return Ok(Box::new(warp::reply::with_status(r, StatusCode::OK)));
+            }
+            Err(StoreError::Conflict) => continue,
+            Err(e) => {
+                info!("couldn't patch meal {}: {:?}", i, e);
+                return Ok(Box::new(ApiError::from(e)));
+            }
+        }
+    }
+    Ok(Box::new(ApiError::from(StoreError::Conflict)))
+}
+
+// Only for whoever can change the meal, old revisions can have things in them that were
+// taken out for a reason. Goes by the owner in the first revision since the meal itself
+// might be purged, and it's who the history belongs to whoever saved the id since.
+// curl -i -H "Authorization: bearer: $JWT" localhost:3030/meals/$ID/revisions
+pub async fn list_revisions(
+    i: Uuid,
+    caller: Uuid,
+    revisions: RevisionDb,
+    users: UserDb,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let found = match revisions.list(i).await {
+        Ok(r) => r,
+        Err(e) => {
+            info!("couldn't list revisions of {}: {:?}", i, e);
+            return Ok(Box::new(ApiError::from(e)));
+        }
+    };
+    if let Some(first) = found.last() {
+        match can_change_meal(&first.meal, caller, &users).await {
+            Ok(true) => (),
+            Ok(false) => {
+                return Ok(Box::new(ApiError::Forbidden(
+                    "only the meal's owner or an admin can see its history".to_string(),
+                )))
+            }
+            Err(e) => return Ok(Box::new(ApiError::from(e))),
+        }
+    }
+    let r = warp::reply::json(&found);
+    Ok(Box::new(warp::reply::with_status(r, StatusCode::OK)))
+}
+
+// Puts the meal back how it was at that revision, as a new revision on top. Photos and the
+// owner stay as they are now, same as an update. Works on meals in the trash and ones that
+// have been purged too, though a purged meal's photos are gone and it goes back to
+// whoever first saved it.
+// curl -i -X POST -H "Authorization: bearer: $JWT" localhost:3030/meals/$ID/revisions/2/restore
+pub async fn restore_revision(
+    i: Uuid,
+    rev: u64,
+    caller: Uuid,
+    store: MealDb,
+    revisions: RevisionDb,
+    users: UserDb,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let wanted = match revisions.get(i, rev).await {
+        Ok(Some(r)) => r,
+        Ok(None) => return Ok(Box::new(ApiError::NotFound("no such revision".to_string()))),
+        Err(e) => {
+            info!("couldn't look up revision {} of {}: {:?}", rev, i, e);
+            return Ok(Box::new(ApiError::from(e)));
+        }
+    };
+    let current = match store.get(i).await {
+        // same as restore_from_trash, the sweep could be halfway through purging it
+        Ok(Some(m)) if due(&m) => {
+            return Ok(Box::new(ApiError::NotFound(
+                "that meal is being purged".to_string(),
+            )))
+        }
+        Ok(m) => m,
+        Err(e) => {
+            info!("couldn't look up meal {}: {:?}", i, e);
+            return Ok(Box::new(ApiError::from(e)));
+        }
+    };
+    let history = match revisions.list(i).await {
+        Ok(r) => r,
+        Err(e) => {
+            info!("couldn't list revisions of {}: {:?}", i, e);
+            return Ok(Box::new(ApiError::from(e)));
+        }
+    };
+    // same as list_revisions, it's up to whoever first saved the meal
+    let first = history
+        .last()
+        .map(|r| r.meal.clone())
+        .unwrap_or(wanted.meal.clone());
+    match can_change_meal(&first, caller, &users).await {
+        Ok(true) => (),
+        Ok(false) => return Ok(Box::new(not_yours())),
+        Err(e) => return Ok(Box::new(ApiError::from(e))),
+    }
+    let expected = current.as_ref().map(|m| m.version).unwrap_or(0);
+    // a purged meal's last revision is how it was when it went in the trash
+    let base = match current {
+        Some(m) => m,
+        None => Meal {
+            photos: None,
+            renditions: None,
+            owner: first.owner,
+            ..history
+                .first()
+                .map(|r| r.meal.clone())
+                .unwrap_or(wanted.meal.clone())
+        },
+    };
+    let meal = Meal {
+        photos: base.photos,
+        renditions: base.renditions,
+        owner: base.owner,
+        version: base.version + 1,
+        deleted_at: None,
+        purge_at: None,
+        ..wanted.meal
+    };
+    match store.put_if_version(meal.clone(), expected).await {
+        Ok(_) => {
+            record_revision(&revisions, meal.clone(), RevisionAction::Restored, caller).await;
+            let r = warp::reply::json(&meal);
+            Ok(Box::new(warp::reply::with_status(r, StatusCode::OK)))
+        }
+        Err(e) => {
+            info!("couldn't restore {} to revision {}: {:?}", i, rev, e);
+            Ok(Box::new(ApiError::from(e)))
+        }
+    }
+}
+
+// a trashed meal that's past its purge_at, the sweep could be getting rid of it right now
+fn due(meal: &Meal) -> bool {
+    meal.in_trash() && meal.purge_at.is_some_and(|p| p <= token_store::now_secs())
+}
+
+// Your meals in the trash, or everyone's for an admin. Most recently deleted first.
+// curl -i -H "Authorization: bearer: $JWT" localhost:3030/trash
+pub async fn list_trash(
+    caller: Uuid,
+    store: MealDb,
+    users: UserDb,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let admin = match users.get(caller).await {
+        Ok(u) => u.is_some_and(|u| u.admin),
+        Err(e) => return Ok(Box::new(ApiError::from(e))),
+    };
+    match store.list().await {
+        Ok(meals) => {
+            let mut trash: Vec<Meal> = meals
+                .into_iter()
+                .filter(|m| m.in_trash() && (admin || m.owner == Some(caller)))
+                .collect();
+            trash.sort_by_key(|m| std::cmp::Reverse(m.deleted_at));
+            let r = warp::reply::json(&trash);
+            Ok(Box::new(warp::reply::with_status(r, StatusCode::OK)))
+        }
+        Err(e) => {
+            info!("couldn't list the trash: {:?}", e);
+            Ok(Box::new(ApiError::from(e)))
+        }
+    }
+}
+
+// curl -i -X POST -H "Authorization: bearer: $JWT" localhost:3030/trash/$ID/restore
+pub async fn restore_from_trash(
+    i: Uuid,
+    caller: Uuid,
+    store: MealDb,
+    revisions: RevisionDb,
+    users: UserDb,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    // once it's due the sweep could be halfway through purging it
+    let meal = match store.get(i).await {
+        Ok(Some(m)) if m.in_trash() && !due(&m) => m,
+        Ok(_) => {
+            return Ok(Box::new(ApiError::NotFound(
+                "that meal isn't in the trash".to_string(),
+            )))
+        }
+        Err(e) => {
+            info!("couldn't look up meal {}: {:?}", i, e);
+            return Ok(Box::new(ApiError::from(e)));
+        }
+    };
+    match can_change_meal(&meal, caller, &users).await {
+        Ok(true) => (),
+        Ok(false) => return Ok(Box::new(not_yours())),
+        Err(e) => return Ok(Box::new(ApiError::from(e))),
+    }
+    let restored = Meal {
+        deleted_at: None,
+        purge_at: None,
+        version: meal.version + 1,
+        ..meal.clone()
+    };
+    match store.put_if_version(restored.clone(), meal.version).await {
+        Ok(_) => {
+            record_revision(
+                &revisions,
+                restored.clone(),
+                RevisionAction::Restored,
+                caller,
+            )
+            .await;
+            let r = warp::reply::json(&restored);
+            Ok(Box::new(warp::reply::with_status(r, StatusCode::OK)))
+        }
+        Err(e) => {
+            info!("couldn't take {} out of the trash: {:?}", i, e);
+            Ok(Box::new(ApiError::from(e)))
+        }
+    }
+}
+
+// Gets rid of trashed meals that are past their purge_at, photos first so they don't get
+// left behind in the bucket. Gives back how many went. The table's TTL gets rid of the
+// items eventually anyway, this is what takes the photos with them.
+pub async fn purge_trash(
+    store: &MealDb,
+    s3: &S3Client,
+    bucket_name: &str,
+    now: u64,
+) -> Result<usize, StoreError> {
+    let mut purged = 0;
+    for meal in store.list().await? {
+        if !meal.in_trash() || meal.purge_at.is_none_or(|p| p > now) {
+            continue;
+        }
+        let mut keys = s3_interactions::keys_from_list(meal.photos.as_deref().unwrap_or(""));
+        keys.extend(s3_interactions::keys_from_list(
+            meal.renditions.as_deref().unwrap_or(""),
+        ));
+        let mut photos_gone = true;
+        for k in &keys {
+            if let Err(e) = s3_interactions::delete_photo(s3, bucket_name, k).await {
+                info!("couldn't purge photo {}: {:?}", k, e);
+                photos_gone = false;
+            }
+        }
+        // leave it for the next sweep rather than lose track of the photos
+        if !photos_gone {
+            continue;
+        }
+        // only if nobody's brought it back since it was listed
+        match store.delete_if_version(meal.id, meal.version).await {
+            Ok(()) => purged += 1,
+            Err(StoreError::Conflict) => info!("{} changed while it was being purged", meal.id),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(purged)
+}
+
+// curl -o meals.csv "localhost:3030/export?format=csv"
+pub async fn export_meals(
+    params: HashMap<String, String>,
+    store: MealDb,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let format = match Format::from_params(&params) {
+        Ok(f) => f,
+        Err(e) => return Ok(Box::new(ApiError::BadRequest(e))),
+    };
+    let body = bulk::export(store, format).map(|chunk| {
+        if let Err(e) = &chunk {
+            error!("export stopped partway: {:?}", e);
+        }
+        chunk
+    });
+    let r = warp::http::Response::builder()
+        .header("content-type", format.content_type())
+        .header(
+            "content-disposition",
+            format!("attachment; filename=\"{}\"", format.file_name()),
+        )
+        .body(warp::hyper::Body::wrap_stream(body));
+    match r {
+        Ok(r) => Ok(Box::new(r)),
+        Err(e) => Ok(Box::new(ApiError::Internal(e.to_string()))),
+    }
+}
+
+// Meals from a file made by GET /export, or anything else in the same shape. Every row
+// gets checked the way a create would check it and the report says what happened to
+// each one. Admins import meals as they are. Everyone else can only bring in their own,
+// meals without an owner become theirs.
+// curl -X POST -H "Authorization: bearer: $JWT" --data-binary @meals.csv "localhost:3030/import?format=csv&dry_run=true"
+pub async fn import_meals(
+    params: HashMap<String, String>,
+    caller: Uuid,
+    body: bytes::Bytes,
+    store: MealDb,
+    revisions: RevisionDb,
+    users: UserDb,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let opts = match ImportOptions::from_params(&params) {
+        Ok(o) => o,
+        Err(e) => return Ok(Box::new(ApiError::BadRequest(e))),
+    };
+    let rows = match opts.format.decode(&body) {
+        Ok(r) => r,
+        Err(e) => return Ok(Box::new(ApiError::BadRequest(e))),
+    };
+    let admin = match users.get(caller).await {
+        Ok(u) => u.is_some_and(|u| u.admin),
+        Err(e) => return Ok(Box::new(ApiError::from(e))),
+    };
+
+    let mut report = ImportReport {
+        dry_run: opts.dry_run,
+        ..ImportReport::default()
+    };
+    let mut seen = HashSet::new();
+    for (i, row) in rows.into_iter().enumerate() {
+        let (id, result) = match row {
+            Ok(meal) if !seen.insert(meal.id) => (
+                Some(meal.id),
+                Err("that id is in the file more than once".to_string()),
+            ),
+            Ok(meal) => (
+                Some(meal.id),
+                import_meal(meal, caller, admin, opts, &store, &revisions, &users).await,
+            ),
+            Err(e) => (None, Err(e)),
+        };
+        let (outcome, error) = match result {
+            Ok(outcome) => (outcome, None),
+            Err(e) => (ImportOutcome::Failed, Some(e)),
+        };
+        report.add(ImportRow {
+            row: i + 1,
+            id,
+            outcome,
+            error,
+        });
+    }
+    info!(
+        "import by {}: {} created, {} updated, {} skipped, {} failed{}",
+        caller,
+        report.created,
+        report.updated,
+        report.skipped,
+        report.failed,
+        if report.dry_run { " (dry run)" } else { "" }
+    );
+    let r = warp::reply::json(&report);
+    Ok(Box::new(warp::reply::with_status(r, StatusCode::OK)))
+}
+
+// what happened to one meal from an import, or why it couldn't go in
+async fn import_meal(
+    mut meal: Meal,
+    caller: Uuid,
+    admin: bool,
+    opts: ImportOptions,
+    store: &MealDb,
+    revisions: &RevisionDb,
+    users: &UserDb,
+) -> Result<ImportOutcome, String> {
+    validate_meal(&meal)?;
+    if !admin {
+        match meal.owner {
+            None => meal.owner = Some(caller),
+            Some(o) if o == caller => (),
+            Some(_) => return Err("only admins can import someone else's meals".to_string()),
+        }
+    }
+    // nothing comes in already in the trash, or it'd be purged with whatever photos it lists
+    meal.deleted_at = None;
+    meal.purge_at = None;
+    let existing = store.get(meal.id).await.map_err(|e| e.to_string())?;
+    let (expected, outcome, action) = match existing {
+        Some(_) if !opts.upsert => return Ok(ImportOutcome::Skipped),
+        Some(e) if e.in_trash() => {
+            return Err("that meal is in the trash, restore it first".to_string())
+        }
+        Some(e) if !admin && e.owner != Some(caller) => return Err(not_yours().message()),
+        // carries on from the stored version so anyone editing it finds out, and photos
+        // only change through their own endpoints same as an update
+        Some(e) => {
+            meal.version = e.version + 1;
+            meal.photos = e.photos;
+            meal.renditions = e.renditions;
+            (e.version, ImportOutcome::Updated, RevisionAction::Updated)
+        }
+        None if !can_recreate(meal.id, caller, revisions, users)
+            .await
+            .map_err(|e| e.to_string())? =>
+        {
+            return Err("that id belongs to a meal someone else made".to_string())
+        }
+        // Starts out without photos like any new meal. Keys in the file could be anyone's, or
+        // from a meal that's been purged along with them.
+        None => {
+            meal.photos = None;
+            meal.renditions = None;
+            (0, ImportOutcome::Created, RevisionAction::Created)
+        }
+    };
+    if opts.dry_run {
+        return Ok(outcome);
+    }
+    match store.put_if_version(meal.clone(), expected).await {
+        Ok(()) => {
+            record_revision(revisions, meal, action, caller).await;
+            Ok(outcome)
+        }
+        Err(StoreError::Conflict) => {
+            Err("someone else changed that meal while it was importing".to_string())
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// newest first
+pub async fn all_days(days: DayDb) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match days.list().await {
+        Ok(mut found) => {
+            found.sort_by(|a, b| b.date.cmp(&a.date));
+            let r = warp::reply::json(&found);
+            Ok(Box::new(warp::reply::with_status(r, StatusCode::OK)))
+        }
+        Err(e) => {
+            info!("couldn't list refeed days: {:?}", e);
+            Ok(Box::new(ApiError::Internal(e.to_string())))
+        }
+    }
+}
+
+pub async fn specific_day(i: Uuid, days: DayDb) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match days.get(i).await {
+        Ok(Some(day)) => {
+            let r = warp::reply::json(&day);
+            Ok(Box::new(warp::reply::with_status(r, StatusCode::OK)))
+        }
+        Ok(None) => Ok(Box::new(ApiError::NotFound(
+            "no such refeed day".to_string(),
+        ))),
+        Err(e) => {
+            info!("couldn't get refeed day {}: {:?}", i, e);
+            Ok(Box::new(ApiError::Internal(e.to_string())))
+        }
+    }
+}
+
+// curl -i -X POST -H "Authorization: bearer: $JWT" -H "content-type: application/json" -d '{"date":"2020-08-01","meals":["f11b1c5e-d6d8-4dce-8a9d-9e05d870b881"],"notes":"","energy":4,"mood":5,"sleep":3}' http://127.0.0.1:3030/days
+pub async fn create_day(
+    day: RefeedDay,
+    days: DayDb,
+    meals: MealDb,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let newone = RefeedDay {
+        id: Uuid::new_v4(),
+        ..day
+    };
+    if let Err(e) = check_day(&newone, &days, &meals).await {
+        return Ok(Box::new(e));
+    }
+    match days.put(newone.clone()).await {
+        Ok(_) => {
+            info!("added refeed day {}", newone.date);
+            let r = warp::reply::json(&newone);
+            Ok(Box::new(warp::reply::with_status(r, StatusCode::CREATED)))
+        }
+        Err(e) => {
+            info!("couldn't add refeed day: {:?}", e);
+            Ok(Box::new(ApiError::Internal(e.to_string())))
+        }
+    }
+}
+
+pub async fn update_day(
+    i: Uuid,
+    day: RefeedDay,
+    days: DayDb,
+    meals: MealDb,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    if day.id != i {
+        return Ok(Box::new(ApiError::BadRequest(
+            "id in the body doesn't match the URL".to_string(),
+        )));
+    }
+    match days.get(i).await {
+        Ok(Some(_)) => (),
+        Ok(None) => {
+            return Ok(Box::new(ApiError::NotFound(
+                "no such refeed day".to_string(),
+            )))
+        }
+        Err(e) => {
+            info!("couldn't look up refeed day {}: {:?}", i, e);
+            return Ok(Box::new(ApiError::Internal(e.to_string())));
+        }
+    }
+    if let Err(e) = check_day(&day, &days, &meals).await {
+        return Ok(Box::new(e));
+    }
+    match days.put(day.clone()).await {
+        Ok(_) => {
+            let r = warp::reply::json(&day);
+            Ok(Box::new(warp::reply::with_status(r, StatusCode::ACCEPTED)))
+        }
+        Err(e) => {
+            info!("couldn't update refeed day {}: {:?}", i, e);
+            Ok(Box::new(ApiError::Internal(e.to_string())))
+        }
+    }
+}
+
+pub async fn delete_day(i: Uuid, days: DayDb) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match days.delete(i).await {
+        Ok(_) => {
+            info!("refeed day got deleted {}", i);
+            Ok(Box::new(StatusCode::NO_CONTENT))
+        }
+        Err(e) => {
+            info!("refeed day couldn't be deleted: {:?}", e);
+            Ok(Box::new(ApiError::Internal(e.to_string())))
+        }
+    }
+}
+
+// curl -i -X POST -H "Authorization: bearer: $JWT" -H "content-type: application/json" -d '{"username":"sam","password":"a good long one"}' http://127.0.0.1:3030/users
+pub async fn create_user(
+    caller: Uuid,
+    new_user: backend_types::NewUser,
+    users: UserDb,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    match users.get(caller).await {
+        Ok(Some(u)) if u.admin => (),
+        Ok(_) => {
+            return Ok(Box::new(ApiError::Forbidden(
+                "only admins can add users".to_string(),
+            )))
+        }
+        Err(e) => {
+            info!("couldn't look up user {}: {:?}", caller, e);
+            return Ok(Box::new(ApiError::Internal(e.to_string())));
+        }
+    }
+    let username = new_user.username.trim().to_string();
+    if username.is_empty() || username.len() > 64 {
+        return Ok(Box::new(ApiError::BadRequest(
+            "username has to be 1 to 64 characters".to_string(),
+        )));
+    }
+    if let Err(e) = check_new_password(&new_user.password) {
+        return Ok(Box::new(ApiError::BadRequest(e)));
+    }
+    match users.find_by_username(&username).await {
+        Ok(None) => (),
+        Ok(Some(_)) => {
+            return Ok(Box::new(ApiError::Conflict(
+                "there's already a user by that name".to_string(),
+            )))
+        }
+        Err(e) => return Ok(Box::new(ApiError::Internal(e.to_string()))),
+    }
+    let password_hash = match hash_off_thread(new_user.password).await {
+        Ok(h) => h,
+        Err(e) => return Ok(Box::new(ApiError::Internal(e))),
+    };
+
+    let user = User {
+        id: Uuid::new_v4(),
+        username,
+        password_hash,
+        admin: new_user.admin,
+    };
+    match users.put(user.clone()).await {
+        Ok(_) => {
+            info!("{} added user {}", caller, user.id);
+            let r = warp::reply::json(&backend_types::UserResp {
+                id: user.id,
+                username: user.username,
+                admin: user.admin,
+            });
+            Ok(Box::new(warp::reply::with_status(r, StatusCode::CREATED)))
+        }
+        Err(e) => {
+            info!("couldn't add user: {:?}", e);
+            Ok(Box::new(ApiError::Internal(e.to_string())))
+        }
+    }
+}
+
+// Change your own password. Every token you have stops working, including the one
+// used to make this request, so log in again afterwards.
+pub async fn change_password(
+    caller: Uuid,
+    change: backend_types::PasswordChange,
+    users: UserDb,
+    jwtdb: JwtDb,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let mut user = match users.get(caller).await {
+        Ok(Some(u)) => u,
+        Ok(None) => return Ok(Box::new(ApiError::NotFound("no such user".to_string()))),
+        Err(e) => {
+            info!("couldn't look up user {}: {:?}", caller, e);
+            return Ok(Box::new(ApiError::Internal(e.to_string())));
+        }
+    };
+    let hash = user.password_hash.clone();
+    let current = change.current_password;
+    let matches = tokio::task::spawn_blocking(move || user_store::verify_password(&hash, &current))
+        .await
+        .unwrap_or(false);
+    if !matches {
+        return Ok(Box::new(ApiError::Forbidden(
+            "current password is wrong".to_string(),
+        )));
+    }
+    if let Err(e) = check_new_password(&change.new_password) {
+        return Ok(Box::new(ApiError::BadRequest(e)));
+    }
+    user.password_hash = match hash_off_thread(change.new_password).await {
+        Ok(h) => h,
+        Err(e) => return Ok(Box::new(ApiError::Internal(e))),
+    };
+    if let Err(e) = users.put(user).await {
+        info!("couldn't save new password for {}: {:?}", caller, e);
+        return Ok(Box::new(ApiError::Internal(e.to_string())));
+    }
+
+    if let Err(e) = jwtdb.revoke_user(caller).await {
+        info!(
+            "changed password for {} but couldn't revoke their tokens: {:?}",
+            caller, e
+        );
+        return Ok(Box::new(ApiError::Internal(e.to_string())));
+    }
+    info!("changed password for {} and revoked their tokens", caller);
+    Ok(Box::new(StatusCode::NO_CONTENT))
+}
+
+fn check_new_password(password: &str) -> Result<(), String> {
+    // the upper bound keeps argon2 from chewing on megabytes of "password"
+    if password.chars().count() < 8 || password.len() > 1024 {
+        return Err("passwords have to be at least 8 characters".to_string());
+    }
+    Ok(())
+}
+
+async fn hash_off_thread(password: String) -> Result<String, String> {
+    match tokio::task::spawn_blocking(move || user_store::hash_password(&password)).await {
+        Ok(Ok(h)) => Ok(h),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// what the frontend insists on before it'll save one
+pub fn validate_meal(meal: &Meal) -> Result<(), String> {
+    check_name(&meal.name)?;
+    check_description(&meal.description)?;
+    check_stars(meal.stars)
+}
+
+// validate_meal for just the fields a patch has in it
+fn validate_patch(patch: &MealPatch) -> Result<(), String> {
+    if let Some(n) = &patch.name {
+        check_name(n)?;
+    }
+    if let Some(d) = &patch.description {
+        check_description(d)?;
+    }
+    if let Some(s) = patch.stars {
+        check_stars(s)?;
+    }
+    Ok(())
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("name can't be empty".to_string());
+    }
+    Ok(())
+}
+
+fn check_description(description: &str) -> Result<(), String> {
+    if description.trim().is_empty() {
+        return Err("description can't be empty".to_string());
+    }
+    Ok(())
+}
+
+fn check_stars(stars: Option<i32>) -> Result<(), String> {
+    if let Some(s) = stars {
+        if !(1..=5).contains(&s) {
+            return Err("stars has to be from 1 to 5".to_string());
+        }
+    }
+    Ok(())
+}
+
+// everything about a day that can be checked without looking anything up
+fn validate_day(day: &RefeedDay) -> Result<(), String> {
+    if NaiveDate::parse_from_str(&day.date, "%Y-%m-%d").is_err() {
+        return Err(format!("date '{}' should look like 2020-08-01", day.date));
+    }
+    for (name, score) in &[
+        ("energy", day.energy),
+        ("mood", day.mood),
+        ("sleep", day.sleep),
+    ] {
+        if let Some(s) = score {
+            if *s < 1 || *s > 5 {
+                return Err(format!("{} has to be from 1 to 5", name));
+            }
+        }
+    }
+    Ok(())
+}
+
+// the meals have to exist and there's only one refeed day per date
+async fn check_day(day: &RefeedDay, days: &DayDb, meals: &MealDb) -> Result<(), ApiError> {
+    validate_day(day).map_err(ApiError::BadRequest)?;
+    for meal_id in &day.meals {
+        match meals.get(*meal_id).await {
+            Ok(Some(m)) if !m.in_trash() => (),
+            Ok(_) => return Err(ApiError::BadRequest(format!("no meal with id {}", meal_id))),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let existing = days.list().await.map_err(ApiError::from)?;
+    if existing
+        .iter()
+        .any(|d| d.date == day.date && d.id != day.id)
+    {
+        return Err(ApiError::Conflict(format!(
+            "there's already a refeed day for {}",
+            day.date
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::Reply;
+
+    // empty in-memory stores for a handler to work on
+    fn stores() -> (MealDb, RevisionDb, UserDb) {
+        use crate::meal_store::InMemoryMealStore;
+        use crate::revision_store::InMemoryRevisionStore;
+        use crate::user_store::InMemoryUserStore;
+
+        (
+            Arc::new(InMemoryMealStore::new()),
+            Arc::new(InMemoryRevisionStore::new()),
+            Arc::new(InMemoryUserStore::new()),
+        )
+    }
+
+    #[test]
+    fn test_validate_meal() {
+        let good = Meal {
+            name: "Tacos".to_string(),
+            description: "Crunchy".to_string(),
+            stars: Some(5),
+            ..Default::default()
+        };
+        assert!(validate_meal(&good).is_ok());
+        assert!(validate_meal(&Meal {
+            stars: None,
+            ..good.clone()
+        })
+        .is_ok());
+        for bad in &[
+            Meal {
+                name: " ".to_string(),
+                ..good.clone()
+            },
+            Meal {
+                description: "".to_string(),
+                ..good.clone()
+            },
+            Meal {
+                stars: Some(0),
+                ..good.clone()
+            },
+        ] {
+            assert!(validate_meal(bad).is_err());
+        }
+    }
+
+    #[test]
+    fn test_validate_patch() {
+        let patch = |j: serde_json::Value| MealPatch::from_json(&j).unwrap();
+        // a meal with no description can still get its stars changed
+        assert!(validate_patch(&patch(serde_json::json!({ "stars": 5 }))).is_ok());
+        assert!(validate_patch(&patch(serde_json::json!({ "stars": null }))).is_ok());
+        assert!(validate_patch(&patch(serde_json::json!({}))).is_ok());
+        for bad in &[
+            serde_json::json!({ "stars": 6 }),
+            serde_json::json!({ "name": " " }),
+            serde_json::json!({ "stars": 4, "description": "" }),
+        ] {
+            assert!(validate_patch(&patch(bad.clone())).is_err());
+        }
+    }
+
+    #[test]
+    fn test_validate_day() {
+        let good = RefeedDay {
+            date: "2020-08-01".to_string(),
+            energy: Some(1),
+            sleep: Some(5),
+            ..Default::default()
+        };
+        assert!(validate_day(&good).is_ok());
+
+        for date in &["", "08/01/2020", "2020-02-30"] {
+            let d = RefeedDay {
+                date: date.to_string(),
+                ..good.clone()
+            };
+            assert!(validate_day(&d).is_err());
+        }
+
+        let bad_mood = RefeedDay {
+            mood: Some(6),
+            ..good.clone()
+        };
+        assert_eq!(
+            validate_day(&bad_mood),
+            Err("mood has to be from 1 to 5".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_can_change_meal() {
+        use crate::user_store::InMemoryUserStore;
+
+        let users: UserDb = Arc::new(InMemoryUserStore::new());
+        let (owner, other, admin) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        users
+            .put(User {
+                id: admin,
+                admin: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let meal = Meal {
+            owner: Some(owner),
+            ..Default::default()
+        };
+        assert!(can_change_meal(&meal, owner, &users).await.unwrap());
+        assert!(!can_change_meal(&meal, other, &users).await.unwrap());
+        assert!(can_change_meal(&meal, admin, &users).await.unwrap());
+
+        let unowned = Meal::default();
+        assert!(!can_change_meal(&unowned, owner, &users).await.unwrap());
+        assert!(can_change_meal(&unowned, admin, &users).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_restore_revision() {
+        let (store, revisions, users) = stores();
+        let (owner, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let meal = Meal {
+            id: Uuid::new_v4(),
+            description: "the good one".to_string(),
+            photos: Some("meals/x/a.jpg".to_string()),
+            owner: Some(owner),
+            version: 1,
+            ..Default::default()
+        };
+        record_revision(&revisions, meal.clone(), RevisionAction::Created, owner).await;
+        let edited = Meal {
+            description: "oops".to_string(),
+            version: 2,
+            ..meal.clone()
+        };
+        record_revision(&revisions, edited.clone(), RevisionAction::Updated, owner).await;
+        record_revision(&revisions, edited, RevisionAction::Deleted, owner).await;
+
+        let restore = |rev: u64, caller: Uuid| {
+            restore_revision(
+                meal.id,
+                rev,
+                caller,
+                store.clone(),
+                revisions.clone(),
+                users.clone(),
+            )
+        };
+        let status = |r: Box<dyn warp::Reply>| r.into_response().status();
+        assert_eq!(
+            status(restore(1, other).await.unwrap()),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(restore(9, owner).await.unwrap()),
+            StatusCode::NOT_FOUND
+        );
+        // purged, so it comes back from where it was when it went minus the photos
+        assert_eq!(status(restore(1, owner).await.unwrap()), StatusCode::OK);
+        let back = store.get(meal.id).await.unwrap().unwrap();
+        assert_eq!(back.description, "the good one");
+        assert_eq!(back.photos, None);
+        assert_eq!(back.version, 3);
+
+        let latest = revisions.list(meal.id).await.unwrap().remove(0);
+        assert_eq!((latest.rev, latest.action), (4, RevisionAction::Restored));
+
+        let history =
+            |caller: Uuid| list_revisions(meal.id, caller, revisions.clone(), users.clone());
+        assert_eq!(status(history(other).await.unwrap()), StatusCode::FORBIDDEN);
+        assert_eq!(status(history(owner).await.unwrap()), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_history_stays_with_first_owner() {
+        let (store, revisions, users) = stores();
+        let (owner, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let meal = Meal {
+            id: Uuid::new_v4(),
+            name: "Ramen".to_string(),
+            description: "the secret recipe".to_string(),
+            owner: Some(owner),
+            version: 1,
+            ..Default::default()
+        };
+        record_revision(&revisions, meal.clone(), RevisionAction::Created, owner).await;
+
+        // importing an export of it doesn't get around that it's purged and not theirs
+        let taken = Meal {
+            owner: Some(other),
+            ..meal.clone()
+        };
+        let file = serde_json::to_vec(&vec![&taken]).unwrap();
+        let r = import_meals(
+            HashMap::new(),
+            other,
+            file.into(),
+            store.clone(),
+            revisions.clone(),
+            users.clone(),
+        )
+        .await
+        .unwrap()
+        .into_response();
+        let report: ImportReport =
+            serde_json::from_slice(&warp::hyper::body::to_bytes(r.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(report.failed, 1);
+        assert_eq!(store.get(meal.id).await.unwrap(), None);
+
+        // and if someone did save the id again before that was checked, the history and
+        // what's restored from it still go by the first owner
+        record_revision(&revisions, taken.clone(), RevisionAction::Created, other).await;
+        let status = |r: Box<dyn warp::Reply>| r.into_response().status();
+        let history =
+            |caller: Uuid| list_revisions(meal.id, caller, revisions.clone(), users.clone());
+        assert_eq!(status(history(other).await.unwrap()), StatusCode::FORBIDDEN);
+        assert_eq!(status(history(owner).await.unwrap()), StatusCode::OK);
+        let restore = |caller: Uuid| {
+            restore_revision(
+                meal.id,
+                1,
+                caller,
+                store.clone(),
+                revisions.clone(),
+                users.clone(),
+            )
+        };
+        assert_eq!(status(restore(other).await.unwrap()), StatusCode::FORBIDDEN);
+        assert_eq!(status(restore(owner).await.unwrap()), StatusCode::OK);
+        assert_eq!(
+            store.get(meal.id).await.unwrap().unwrap().owner,
+            Some(owner)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trash() {
+        let (store, revisions, users) = stores();
+        let (owner, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let now = token_store::now_secs();
+        let trashed = Meal {
+            id: Uuid::new_v4(),
+            owner: Some(owner),
+            version: 2,
+            deleted_at: Some(now),
+            purge_at: Some(now + 60),
+            ..Default::default()
+        };
+        let due = Meal {
+            id: Uuid::new_v4(),
+            purge_at: Some(now - 1),
+            ..trashed.clone()
+        };
+        let live = Meal {
+            id: Uuid::new_v4(),
+            deleted_at: None,
+            purge_at: None,
+            ..trashed.clone()
+        };
+        for m in &[&trashed, &due, &live] {
+            store.put((*m).clone()).await.unwrap();
+        }
+
+        let restore = |id: Uuid, caller: Uuid| {
+            restore_from_trash(id, caller, store.clone(), revisions.clone(), users.clone())
+        };
+        let status = |r: Box<dyn warp::Reply>| r.into_response().status();
+        assert_eq!(
+            status(restore(trashed.id, other).await.unwrap()),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(restore(live.id, owner).await.unwrap()),
+            StatusCode::NOT_FOUND
+        );
+        // too late, the sweep has dibs
+        assert_eq!(
+            status(restore(due.id, owner).await.unwrap()),
+            StatusCode::NOT_FOUND
+        );
+        record_revision(&revisions, due.clone(), RevisionAction::Created, owner).await;
+        let from_history = restore_revision(
+            due.id,
+            1,
+            owner,
+            store.clone(),
+            revisions.clone(),
+            users.clone(),
+        );
+        assert_eq!(status(from_history.await.unwrap()), StatusCode::NOT_FOUND);
+        assert_eq!(
+            status(restore(trashed.id, owner).await.unwrap()),
+            StatusCode::OK
+        );
+        let back = store.get(trashed.id).await.unwrap().unwrap();
+        assert!(!back.in_trash());
+        assert_eq!((back.purge_at, back.version), (None, 3));
+        assert_eq!(
+            revisions.list(trashed.id).await.unwrap()[0].action,
+            RevisionAction::Restored
+        );
+
+        // none of them have photos so S3 never gets called
+        let s3 = S3Client::new(rusoto_core::Region::UsEast1);
+        assert_eq!(purge_trash(&store, &s3, "bucket", now).await.unwrap(), 1);
+        assert_eq!(store.get(due.id).await.unwrap(), None);
+        assert!(store.get(live.id).await.unwrap().is_some());
+        assert!(store.get(trashed.id).await.unwrap().is_some());
+    }
+
+    #[test]
+    fn test_check_new_password() {
+        assert!(check_new_password("eightchr").is_ok());
+        assert!(check_new_password("short").is_err());
+        // characters, not bytes
+        assert!(check_new_password("ñññññññ").is_err());
+        assert!(check_new_password(&"x".repeat(2000)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_import() {
+        let (store, revisions, users) = stores();
+        let (me, someone) = (Uuid::new_v4(), Uuid::new_v4());
+        let mine = Meal {
+            id: Uuid::new_v4(),
+            name: "Ramen".to_string(),
+            description: "rich".to_string(),
+            owner: Some(me),
+            version: 4,
+            ..Default::default()
+        };
+        let theirs = Meal {
+            id: Uuid::new_v4(),
+            owner: Some(someone),
+            ..mine.clone()
+        };
+        store.put(mine.clone()).await.unwrap();
+        store.put(theirs.clone()).await.unwrap();
+
+        let fresh = Meal {
+            id: Uuid::new_v4(),
+            owner: None,
+            version: 1,
+            ..mine.clone()
+        };
+        let edited = Meal {
+            description: "richer".to_string(),
+            ..mine.clone()
+        };
+        let bad = Meal {
+            id: Uuid::new_v4(),
+            stars: Some(11),
+            ..mine.clone()
+        };
+        let file = serde_json::to_vec(&vec![&fresh, &edited, &theirs, &bad, &fresh]).unwrap();
+
+        let import = |query: &str| {
+            let params = query
+                .split('&')
+                .filter_map(|kv| kv.split_once('='))
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            let store = store.clone();
+            let (revisions, users) = (revisions.clone(), users.clone());
+            let file = file.clone();
+            async move {
+                let r = import_meals(params, me, file.into(), store, revisions, users)
+                    .await
+                    .unwrap()
+                    .into_response();
+                assert_eq!(r.status(), StatusCode::OK);
+                let body = warp::hyper::body::to_bytes(r.into_body()).await.unwrap();
+                serde_json::from_slice::<ImportReport>(&body).unwrap()
+            }
+        };
+        let outcomes = |r: &ImportReport| r.rows.iter().map(|r| r.outcome).collect::<Vec<_>>();
+
+        let report = import("existing=upsert&dry_run=true").await;
+        assert_eq!(
+            outcomes(&report),
+            vec![
+                ImportOutcome::Created,
+                ImportOutcome::Updated,
+                ImportOutcome::Failed,
+                ImportOutcome::Failed,
+                ImportOutcome::Failed,
+            ]
+        );
+        assert_eq!((report.created, report.updated, report.failed), (1, 1, 3));
+        assert_eq!(report.rows[4].row, 5);
+        // nothing written on a dry run
+        assert_eq!(store.get(fresh.id).await.unwrap(), None);
+        assert_eq!(store.get(mine.id).await.unwrap(), Some(mine.clone()));
+
+        let report = import("existing=skip").await;
+        assert_eq!(report.skipped, 1);
+        assert_eq!(report.created, 1);
+        let created = store.get(fresh.id).await.unwrap().unwrap();
+        assert_eq!(created.owner, Some(me));
+        assert_eq!(store.get(mine.id).await.unwrap(), Some(mine.clone()));
+
+        let report = import("existing=upsert").await;
+        assert_eq!((report.created, report.updated), (0, 2));
+        let updated = store.get(mine.id).await.unwrap().unwrap();
+        assert_eq!(
+            (updated.description.as_str(), updated.version),
+            ("richer", 5)
+        );
+        assert_eq!(
+            revisions.list(mine.id).await.unwrap()[0].action,
+            RevisionAction::Updated
+        );
+        assert_eq!(store.get(theirs.id).await.unwrap(), Some(theirs));
+    }
+
+    #[tokio::test]
+    async fn test_import_leaves_photos_alone() {
+        let (store, revisions, users) = stores();
+        let me = Uuid::new_v4();
+        let their_id = Uuid::new_v4();
+        let their_photo = s3_interactions::photo_key(their_id, "a.jpg");
+        let theirs = Meal {
+            id: their_id,
+            name: "Pho".to_string(),
+            description: "theirs".to_string(),
+            photos: Some(their_photo.clone()),
+            owner: Some(Uuid::new_v4()),
+            version: 1,
+            ..Default::default()
+        };
+        let mine = Meal {
+            id: Uuid::new_v4(),
+            description: "mine".to_string(),
+            owner: Some(me),
+            ..theirs.clone()
+        };
+        store.put(theirs.clone()).await.unwrap();
+        store.put(mine.clone()).await.unwrap();
+
+        // a new meal and one of mine, both pointing at their photo and due for purging. The
+        // new one's own photo could be long gone with the meal it was exported from.
+        let now = token_store::now_secs();
+        let id = Uuid::new_v4();
+        let own_photo = s3_interactions::photo_key(id, "b.jpg");
+        let crafted = Meal {
+            id,
+            photos: Some(format!("{}|{}", their_photo, own_photo)),
+            renditions: Some(their_photo.replace(".jpg", "_thumb.jpg")),
+            deleted_at: Some(now - 100),
+            purge_at: Some(now - 1),
+            ..mine.clone()
+        };
+        let sneaky_edit = Meal {
+            id: mine.id,
+            ..crafted.clone()
+        };
+        let file = serde_json::to_vec(&vec![&crafted, &sneaky_edit]).unwrap();
+        let params = vec![("existing".to_string(), "upsert".to_string())]
+            .into_iter()
+            .collect();
+        let r = import_meals(params, me, file.into(), store.clone(), revisions, users)
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(r.status(), StatusCode::OK);
+
+        let created = store.get(id).await.unwrap().unwrap();
+        assert_eq!(created.photos, None);
+        assert_eq!(created.renditions, None);
+        assert!(!created.in_trash());
+        assert_eq!(created.purge_at, None);
+        let edited = store.get(mine.id).await.unwrap().unwrap();
+        assert_eq!(edited.photos, mine.photos);
+        assert!(!edited.in_trash());
+
+        // nothing's due, so their photo is safe and S3 never gets called
+        let s3 = S3Client::new(rusoto_core::Region::UsEast1);
+        assert_eq!(purge_trash(&store, &s3, "bucket", now).await.unwrap(), 0);
+        assert_eq!(store.get(theirs.id).await.unwrap(), Some(theirs));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_photo_changes() {
+        use crate::meal_store::InMemoryMealStore;
+
+        let store: MealDb = Arc::new(InMemoryMealStore::new());
+        let meal = Meal {
+            id: Uuid::new_v4(),
+            version: 1,
+            ..Default::default()
+        };
+        store.put(meal.clone()).await.unwrap();
+
+        // every upload read the same version, all but one have to go again
+        let adds = (0..4).map(|n| {
+            let key = format!("meals/{}/{}.jpg", meal.id, n);
+            let (meal, store) = (meal.clone(), store.clone());
+            async move {
+                save_photo_change(meal, &store, |m| {
+                    let mut keys =
+                        s3_interactions::keys_from_list(m.photos.as_deref().unwrap_or(""));
+                    keys.push(key.clone());
+                    m.photos = s3_interactions::list_from_keys(&keys);
+                })
+                .await
+            }
+        });
+        for saved in futures::future::join_all(adds).await {
+            assert!(saved.unwrap().is_some());
+        }
+        let saved = store.get(meal.id).await.unwrap().unwrap();
+        let mut keys = s3_interactions::keys_from_list(saved.photos.as_deref().unwrap_or(""));
+        keys.sort();
+        assert_eq!(
+            keys,
+            (0..4)
+                .map(|n| format!("meals/{}/{}.jpg", meal.id, n))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(saved.version, 5);
+    }
+}
@@ -0,0 +1,25 @@
+// Everything but the routes, so the tools in src/bin can use the same stores and config
+// as the API server.
+#[macro_use]
+extern crate log;
+
+pub mod backend_types;
+pub mod backup;
+pub mod bulk;
+pub mod config;
+pub mod day_store;
+pub mod errors;
+pub mod handlers;
+pub mod login_throttle;
+pub mod meal_store;
+pub mod metered;
+pub mod metrics;
+pub mod openapi;
+pub mod readiness;
+pub mod revision_store;
+pub mod s3_interactions;
+pub mod startup;
+pub mod tables;
+pub mod thumbnails;
+pub mod token_store;
+pub mod user_store;
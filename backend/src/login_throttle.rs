@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+pub type Throttle = Arc<LoginThrottle>;
+
+// a few typos are free, after that every failure doubles the wait up to this
+static FREE_FAILURES: u32 = 3;
+static FIRST_BACKOFF: Duration = Duration::from_secs(1);
+static MAX_BACKOFF: Duration = Duration::from_secs(60);
+// a whole household can share an IP, give it more room than one username
+static IP_ALLOWANCE: u32 = 5;
+// past this many tracked names or IPs, drop the ones that have gone quiet
+static TRACKED_BEFORE_TIDYING: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    last: Instant,
+    blocked_until: Instant,
+}
+
+// Failure counts for one kind of key, usernames or IPs
+struct Counters<K> {
+    failures: Mutex<HashMap<K, Failures>>,
+    lockout_after: u32,
+    lockout: Duration,
+}
+
+impl<K: Eq + Hash> Counters<K> {
+    fn new(lockout_after: u32, lockout: Duration) -> Counters<K> {
+        Counters {
+            failures: Mutex::new(HashMap::new()),
+            lockout_after,
+            lockout,
+        }
+    }
+
+    // counts are forgotten once it's been quiet for a whole lockout
+    fn stale(&self, f: &Failures, now: Instant) -> bool {
+        now >= f.blocked_until && now.duration_since(f.last) >= self.lockout
+    }
+
+    fn wait(&self, failures: &HashMap<K, Failures>, key: &K, now: Instant) -> Option<Duration> {
+        failures
+            .get(key)
+            .filter(|f| f.blocked_until > now)
+            .map(|f| f.blocked_until - now)
+    }
+
+    fn failed(&self, failures: &mut HashMap<K, Failures>, key: K, now: Instant) {
+        if failures.len() > TRACKED_BEFORE_TIDYING {
+            failures.retain(|_, f| !self.stale(f, now));
+        }
+        let f = failures.entry(key).or_insert(Failures {
+            count: 0,
+            last: now,
+            blocked_until: now,
+        });
+        if self.stale(f, now) {
+            f.count = 0;
+        }
+        f.count += 1;
+        f.last = now;
+        f.blocked_until = now + self.block_for(f.count);
+    }
+
+    // takes back one failure that turned out not to be one
+    fn forgive(&self, failures: &mut HashMap<K, Failures>, key: &K) {
+        if let Some(f) = failures.get_mut(key) {
+            f.count = f.count.saturating_sub(1);
+            f.blocked_until = f.last + self.block_for(f.count);
+        }
+    }
+
+    fn block_for(&self, count: u32) -> Duration {
+        if count >= self.lockout_after {
+            self.lockout
+        } else if count > FREE_FAILURES {
+            let doublings = (count - FREE_FAILURES - 1).min(16);
+            (FIRST_BACKOFF * 2u32.pow(doublings)).min(MAX_BACKOFF)
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+}
+
+// Brute force protection for /login. Failed logins are counted per username and per IP,
+// past a few the next try has to wait longer each time, and enough of them locks the
+// username or IP out for a while. Lives in memory, a restart forgets it all.
+pub struct LoginThrottle {
+    users: Counters<String>,
+    ips: Counters<IpAddr>,
+}
+
+impl LoginThrottle {
+    pub fn new(lockout_after: u32, lockout: Duration) -> LoginThrottle {
+        LoginThrottle {
+            users: Counters::new(lockout_after, lockout),
+            ips: Counters::new(lockout_after.saturating_mul(IP_ALLOWANCE), lockout),
+        }
+    }
+
+    // Every try counts as a failure up front, before the password gets checked, so a pile
+    // of guesses sent at once can't all get past here before any of them has failed. None
+    // if it can go ahead, otherwise how long until this username can try again from there.
+    pub async fn reserve(
+        &self,
+        username: &str,
+        ip: Option<IpAddr>,
+        now: Instant,
+    ) -> Option<Duration> {
+        let username = username.to_string();
+        // always users then IPs, so two of these can't each hold the lock the other wants
+        let mut users = self.users.failures.lock().await;
+        let mut ips = self.ips.failures.lock().await;
+        let user_wait = self.users.wait(&users, &username, now);
+        let ip_wait = ip.and_then(|ip| self.ips.wait(&ips, &ip, now));
+        if user_wait.is_some() || ip_wait.is_some() {
+            return user_wait.max(ip_wait);
+        }
+        self.users.failed(&mut users, username, now);
+        if let Some(ip) = ip {
+            self.ips.failed(&mut ips, ip, now);
+        }
+        None
+    }
+
+    // The username's slate is wiped, the IP only gets this try back: otherwise one working
+    // account would let someone keep guessing at everyone else's.
+    pub async fn succeeded(&self, username: &str, ip: Option<IpAddr>) {
+        self.users.failures.lock().await.remove(username);
+        if let Some(ip) = ip {
+            self.ips.forgive(&mut *self.ips.failures.lock().await, &ip);
+        }
+    }
+}
+
+// With trust_proxy nginx is in front and appends the address it saw to X-Forwarded-For,
+// anything before that last entry came from the client and can't be believed.
+pub fn client_ip(
+    remote: Option<SocketAddr>,
+    forwarded: Option<String>,
+    trust_proxy: bool,
+) -> Option<IpAddr> {
+    if trust_proxy {
+        forwarded
+            .as_deref()
+            .and_then(|f| f.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok())
+    } else {
+        remote.map(|r| r.ip())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[tokio::test]
+    async fn test_backoff_then_lockout() {
+        let t = LoginThrottle::new(6, secs(900));
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(t.reserve("sam", None, start).await, None);
+        }
+        // free ones used up, this one gets in but the next has to wait
+        assert_eq!(t.reserve("sam", None, start).await, None);
+        assert_eq!(t.reserve("sam", None, start).await, Some(secs(1)));
+
+        assert_eq!(t.reserve("sam", None, start + secs(1)).await, None);
+        assert_eq!(t.reserve("sam", None, start + secs(1)).await, Some(secs(2)));
+        assert_eq!(t.reserve("sam", None, start + secs(3)).await, None);
+        assert_eq!(
+            t.reserve("sam", None, start + secs(3)).await,
+            Some(secs(900))
+        );
+
+        // other names aren't affected
+        assert_eq!(t.reserve("al", None, start + secs(3)).await, None);
+        // and it all gets forgotten eventually
+        assert_eq!(t.reserve("sam", None, start + secs(903)).await, None);
+        assert_eq!(t.reserve("sam", None, start + secs(903)).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_ip_counts_across_usernames() {
+        let t = LoginThrottle::new(2, secs(60));
+        let ip: IpAddr = [10, 0, 0, 1].into();
+        let now = Instant::now();
+        // ten tries at ten names, spaced out past each backoff, none of them trip the
+        // per-username limit
+        for i in 0..10 {
+            let at = now + secs(40 * i);
+            assert_eq!(t.reserve(&format!("user{}", i), Some(ip), at).await, None);
+        }
+        let at = now + secs(360);
+        assert_eq!(t.reserve("someone", Some(ip), at).await, Some(secs(60)));
+        assert_eq!(t.reserve("someone", None, at).await, None);
+        assert_eq!(
+            t.reserve("someone", Some([10, 0, 0, 2].into()), at).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_success_clears_username_only() {
+        let t = LoginThrottle::new(3, secs(60));
+        let ip: IpAddr = [10, 0, 0, 1].into();
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(t.reserve("sam", Some(ip), now).await, None);
+        }
+        assert!(t.reserve("sam", None, now).await.is_some());
+
+        // al getting in takes back al's try and nothing more, sam's failures still count
+        assert_eq!(t.reserve("al", Some(ip), now).await, None);
+        t.succeeded("al", Some(ip)).await;
+        assert_eq!(t.reserve("bo", Some(ip), now).await, None);
+        assert_eq!(t.reserve("cy", Some(ip), now).await, Some(secs(1)));
+
+        t.succeeded("sam", None).await;
+        assert_eq!(t.reserve("sam", None, now).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_guesses() {
+        let t = Arc::new(LoginThrottle::new(10, secs(900)));
+        let now = Instant::now();
+        // all sent at once, none of them fails until the password check is done
+        let guesses = (0..20).map(|_| {
+            let t = t.clone();
+            tokio::spawn(async move {
+                let wait = t.reserve("sam", None, now).await;
+                tokio::time::delay_for(Duration::from_millis(20)).await;
+                wait
+            })
+        });
+        let got_in = futures::future::join_all(guesses)
+            .await
+            .into_iter()
+            .filter(|w| matches!(w, Ok(None)))
+            .count();
+        assert_eq!(got_in, FREE_FAILURES as usize + 1);
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let remote = Some(([127, 0, 0, 1], 5555).into());
+        let forwarded = Some("6.6.6.6, 10.1.2.3".to_string());
+        assert_eq!(
+            client_ip(remote, forwarded.clone(), false),
+            Some([127, 0, 0, 1].into())
+        );
+        assert_eq!(
+            client_ip(remote, forwarded, true),
+            Some([10, 1, 2, 3].into())
+        );
+        assert_eq!(client_ip(remote, None, true), None);
+        assert_eq!(client_ip(remote, Some("junk".to_string()), true), None);
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let c: Counters<String> = Counters::new(1000, secs(900));
+        assert_eq!(c.block_for(3), secs(0));
+        assert_eq!(c.block_for(4), secs(1));
+        assert_eq!(c.block_for(6), secs(4));
+        assert_eq!(c.block_for(500), MAX_BACKOFF);
+        assert_eq!(c.block_for(1000), secs(900));
+    }
+}
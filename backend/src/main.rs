@@ -1,23 +1,28 @@
-mod backend_types;
-mod handlers;
-mod s3_interactions;
-
-use dynomite::{
-    dynamodb::{
-        AttributeDefinition, CreateTableInput, DeleteItemInput, DynamoDb, DynamoDbClient,
-        KeySchemaElement, ProvisionedThroughput, PutItemInput, ScanInput,
-    },
-    retry::Policy,
-    FromAttributes, Item, Retries,
+use backend::{
+    backend_types, config, day_store, errors, handlers, login_throttle, meal_store, metrics,
+    openapi, readiness, revision_store, s3_interactions, startup, tables, token_store, user_store,
 };
-
+use config::{Config, StoreKind};
+use day_store::{DayDb, DynamoDayStore, InMemoryDayStore};
+use errors::ApiError;
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
-use rusoto_core::{credential::ProfileProvider, HttpClient, Region};
-use shared::Meal;
+use login_throttle::{LoginThrottle, Throttle};
+use meal_store::{DynamoMealStore, InMemoryMealStore, MealDb, MealQuery, StoreError};
+use metrics::{metrics, LoginOutcome, Routes};
+use rand::Rng;
+use readiness::Dependencies;
+use revision_store::{DynamoRevisionStore, InMemoryRevisionStore, RevisionDb};
+use rusoto_s3::S3Client;
+use s3_interactions::{BucketStatus, Presigner};
+use shared::{Meal, Page, RefeedDay, RevisionAction, REFRESH_COOKIE};
 use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use tables::DynamoClient;
+use token_store::{DynamoTokenStore, InMemoryTokenStore, JwtDb, Refresh};
+use tokio::signal::unix::{signal, SignalKind};
+use user_store::{DynamoUserStore, InMemoryUserStore, User, UserDb};
 use uuid::Uuid;
 use warp::http::StatusCode;
 use warp::Filter;
@@ -26,63 +31,327 @@ extern crate pretty_env_logger;
 #[macro_use]
 extern crate log;
 
-static BUCKET_NAME: &str = "refeed-rampage";
-static DYNAMODB_LOC: &str = include_str!("ddb_loc.txt");
-static S3_LOC: &str = include_str!("s3_loc.txt");
-static SECUREPW: &str = include_str!("password.txt"); // this is for testing purposes
-static JWT_SECRET: &str = include_str!("jwtsecret.txt");
-static GITBITS: &str = include_str!("gitbits.txt");
-
-// store jwts in memory for now
-pub type JwtDb = Arc<Mutex<HashMap<String, i32>>>;
-
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init();
-    let version_txt = match GITBITS.len() {
-        0 => "dev",
-        _ => GITBITS,
-    };
-    info!("Firing up. Version {}.", version_txt);
-    let c = get_dynamodb_client();
+    let config = Arc::new(or_exit(Config::load()));
+    info!("Firing up. Version {}.", config.version);
     // a bunch from https://github.com/seanmonstar/warp/blob/master/examples/todos.rs
-    prepopulate_db(c.clone()).await;
+    // the in-memory store runs without DynamoDB at all, handy for local work and the cukes
+    let (store, days, users, jwtdb, revisions, dynamodb): (
+        MealDb,
+        DayDb,
+        UserDb,
+        JwtDb,
+        RevisionDb,
+        Option<DynamoClient>,
+    ) = match config.meal_store {
+        StoreKind::Memory => {
+            info!("Using the in-memory meal store");
+            (
+                Arc::new(InMemoryMealStore::new()),
+                Arc::new(InMemoryDayStore::new()),
+                Arc::new(InMemoryUserStore::new()),
+                Arc::new(InMemoryTokenStore::new()),
+                Arc::new(InMemoryRevisionStore::new()),
+                None,
+            )
+        }
+        StoreKind::Dynamodb => {
+            let c = or_exit(tables::get_dynamodb_client(&config));
+            or_exit(tables::prepopulate_db(c.clone(), &config).await);
+            (
+                Arc::new(DynamoMealStore::new(c.clone(), &config.meals_table)),
+                Arc::new(DynamoDayStore::new(c.clone(), &config.days_table)),
+                Arc::new(DynamoUserStore::new(c.clone(), &config.users_table)),
+                Arc::new(DynamoTokenStore::new(c.clone(), &config.tokens_table)),
+                Arc::new(DynamoRevisionStore::new(c.clone(), &config.revisions_table)),
+                Some(c),
+            )
+        }
+    };
+    seed_meals(store.clone()).await;
+    ensure_admin(users.clone(), &config).await;
+    // hash it now rather than during someone's login
+    let _ = tokio::task::spawn_blocking(user_store::dummy_hash).await;
 
-    s3_interactions::create_bucket_if_needed(S3_LOC, BUCKET_NAME).await;
+    let s3 = or_exit(s3_interactions::get_s3_client(&config));
+    let bucket = watch_bucket(s3.clone(), config.bucket_name.clone());
+    let presigner = or_exit(s3_interactions::Presigner::new(&config));
+    let dependencies = Dependencies::new(&config, dynamodb, s3.clone());
 
-    let jwtdb: JwtDb = Arc::new(Mutex::new(HashMap::new()));
+    sweep_tokens(jwtdb.clone(), Duration::from_secs(config.token_sweep_secs));
+    sweep_trash(
+        store.clone(),
+        s3.clone(),
+        bucket.clone(),
+        config.bucket_name.clone(),
+        Duration::from_secs(config.trash_sweep_secs),
+    );
+    let throttle = Arc::new(LoginThrottle::new(
+        config.login_lockout_failures,
+        Duration::from_secs(config.login_lockout_secs),
+    ));
 
     let cors = warp::cors()
-        .allow_origin("http://localhost:8080")
-        .allow_origin("http://127.0.0.1:8080")
-        .allow_origin("http://refeed.local:8080")
-        .allow_origin("https://rampage.screaming3d.com")
-        .allow_methods(vec!["GET", "POST", "DELETE", "PUT"])
-        .allow_headers(vec!["content-type", "Authorization"]);
-
-    let routes = meal_filters(jwtdb, c)
+        .allow_origins(config.cors_origins.iter().map(|o| o.as_str()))
+        .allow_methods(vec!["GET", "POST", "DELETE", "PUT", "PATCH"])
+        .allow_headers(vec!["content-type", "Authorization"])
+        // the refresh token cookie
+        .allow_credentials(true);
+
+    let routes = day_filters(days, store.clone(), jwtdb.clone(), config.clone())
+        .or(user_filters(users.clone(), jwtdb.clone(), config.clone()))
+        .or(auth_filters(
+            jwtdb.clone(),
+            users.clone(),
+            throttle,
+            dependencies,
+            config.clone(),
+        ))
+        .or(photo_filters(
+            store.clone(),
+            users.clone(),
+            s3,
+            bucket,
+            jwtdb.clone(),
+            config.clone(),
+        ))
+        .or(meal_filters(
+            jwtdb,
+            store,
+            revisions,
+            users,
+            presigner,
+            config.clone(),
+        ))
+        .recover(errors::handle_rejection)
         .with(&cors)
-        .with(warp::log("backend"));
+        .with(warp::log("backend"))
+        .with(request_metrics(&config));
+
+    // stops taking new connections on the signal and lets the ones it has finish
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let (addr, server) =
+        match warp::serve(routes).try_bind_with_graceful_shutdown(config.bind_addr, async {
+            let _ = stopped.await;
+        }) {
+            Ok(bound) => bound,
+            Err(e) => {
+                error!("couldn't listen on {}: {}", config.bind_addr, e);
+                std::process::exit(1);
+            }
+        };
+    info!("Listening on {}", addr);
+    let server = tokio::spawn(server);
+    shutdown_signal().await;
+    info!("Shutting down, finishing the requests in flight");
+    let _ = stop.send(());
+    let grace = Duration::from_secs(config.shutdown_grace_secs);
+    match tokio::time::timeout(grace, server).await {
+        Ok(_) => info!("All done, bye"),
+        Err(_) => warn!("Gave up on requests still going after {:?}", grace),
+    }
+}
+
+// nothing to be done about it at startup but say why and stop, without a panic's backtrace
+fn or_exit<T, E: std::fmt::Display>(r: Result<T, E>) -> T {
+    match r {
+        Ok(t) => t,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// SIGTERM from systemd, or ctrl-c
+async fn shutdown_signal() {
+    let term = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut s) => {
+                s.recv().await;
+            }
+            Err(e) => {
+                warn!("can't listen for SIGTERM, only ctrl-c: {}", e);
+                futures::future::pending::<()>().await
+            }
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Got SIGINT"),
+        _ = term => info!("Got SIGTERM"),
+    }
+}
+
+// Keeps trying for the photo bucket in the background so the backend can start without S3.
+// Until it's there photo uploads and deletes get a 503, everything else works as usual.
+fn watch_bucket(s3: S3Client, bucket_name: String) -> BucketStatus {
+    let bucket = BucketStatus::default();
+    let status = bucket.clone();
+    tokio::spawn(async move {
+        loop {
+            let found = startup::retry("making the photo bucket", startup::STARTUP, || {
+                s3_interactions::create_bucket_if_needed(&s3, &bucket_name)
+            })
+            .await;
+            match found {
+                Ok(()) => {
+                    status.set_ready();
+                    return;
+                }
+                Err(e) => warn!("Still no photo bucket, photos stay off: {}", e),
+            }
+        }
+    });
+    bucket
+}
 
-    warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
+// counts and times every request for /metrics, by the route it matched
+fn request_metrics(config: &Config) -> warp::log::Log<impl Fn(warp::log::Info) + Clone> {
+    let routes = Arc::new(Routes::from_openapi(&openapi::document(&config.version)));
+    warp::log::custom(move |info| {
+        metrics().http_request(
+            info.method().as_str(),
+            routes.route(info.path()),
+            info.status().as_u16(),
+            info.elapsed(),
+        )
+    })
 }
 
 fn meal_filters(
     jwtdb: JwtDb,
-    ddb_client: dynomite::retry::RetryingDynamoDb<DynamoDbClient>,
+    store: MealDb,
+    revisions: RevisionDb,
+    users: UserDb,
+    presigner: Presigner,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    a_meal_filter(store.clone(), presigner.clone())
+        .or(all_meal_filter(store.clone(), presigner))
+        .or(meal_create(
+            store.clone(),
+            revisions.clone(),
+            jwtdb.clone(),
+            config.clone(),
+        ))
+        .or(meal_delete(
+            store.clone(),
+            revisions.clone(),
+            users.clone(),
+            jwtdb.clone(),
+            config.clone(),
+        ))
+        .or(meal_update(
+            store.clone(),
+            revisions.clone(),
+            users.clone(),
+            jwtdb.clone(),
+            config.clone(),
+        ))
+        .or(meal_patch(
+            store.clone(),
+            revisions.clone(),
+            users.clone(),
+            jwtdb.clone(),
+            config.clone(),
+        ))
+        .or(revision_list(
+            revisions.clone(),
+            users.clone(),
+            jwtdb.clone(),
+            config.clone(),
+        ))
+        .or(revision_restore(
+            store.clone(),
+            revisions.clone(),
+            users.clone(),
+            jwtdb.clone(),
+            config.clone(),
+        ))
+        .or(trash_list(
+            store.clone(),
+            users.clone(),
+            jwtdb.clone(),
+            config.clone(),
+        ))
+        .or(trash_restore(
+            store.clone(),
+            revisions.clone(),
+            users.clone(),
+            jwtdb.clone(),
+            config.clone(),
+        ))
+        .or(export_filter(store.clone()))
+        .or(meal_import(store, revisions, users, jwtdb, config))
+}
+
+fn photo_filters(
+    store: MealDb,
+    users: UserDb,
+    s3: S3Client,
+    bucket: BucketStatus,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    photo_upload(
+        store.clone(),
+        users.clone(),
+        s3.clone(),
+        bucket.clone(),
+        jwtdb.clone(),
+        config.clone(),
+    )
+    .or(photo_delete(store, users, s3, bucket, jwtdb, config))
+}
+
+fn auth_filters(
+    jwtdb: JwtDb,
+    users: UserDb,
+    throttle: Throttle,
+    dependencies: Dependencies,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    live_filter(config.clone())
+        .or(ready_filter(dependencies, config.clone()))
+        .or(openapi_filter(config.clone()))
+        .or(metrics_filter())
+        .or(login_filter(jwtdb.clone(), users, throttle, config.clone()))
+        .or(logout_filter(jwtdb.clone(), config.clone()))
+        .or(refresh_filter(jwtdb, config))
+}
+
+fn day_filters(
+    days: DayDb,
+    store: MealDb,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    a_meal_filter(ddb_client.clone())
-        .or(all_meal_filter(ddb_client.clone()))
-        .or(meal_create(ddb_client, jwtdb.clone()))
-        .or(meal_delete(jwtdb.clone()))
-        .or(meal_update(jwtdb.clone()))
-        .or(status_filter())
-        .or(login_filter(jwtdb))
-        .or(unauthed()) // if something rejected it, toss an unauthorized at it
+    a_day_filter(days.clone())
+        .or(all_day_filter(days.clone()))
+        .or(day_create(
+            days.clone(),
+            store.clone(),
+            jwtdb.clone(),
+            config.clone(),
+        ))
+        .or(day_update(
+            days.clone(),
+            store,
+            jwtdb.clone(),
+            config.clone(),
+        ))
+        .or(day_delete(days, jwtdb, config))
 }
 
-fn unauthed() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    warp::any().and_then(unauthed_resp)
+fn user_filters(
+    users: UserDb,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    user_create(users.clone(), jwtdb.clone(), config.clone())
+        .or(password_change(users, jwtdb, config))
 }
 
 fn with_jwtdb(
@@ -91,270 +360,912 @@ fn with_jwtdb(
     warp::any().map(move || db.clone())
 }
 
-fn with_ddb(
-    ddb_client: dynomite::retry::RetryingDynamoDb<DynamoDbClient>,
-) -> impl Filter<
-    Extract = (dynomite::retry::RetryingDynamoDb<DynamoDbClient>,),
-    Error = std::convert::Infallible,
-> + Clone {
-    warp::any().map(move || ddb_client.clone())
+fn with_config(
+    config: Arc<Config>,
+) -> impl Filter<Extract = (Arc<Config>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || config.clone())
+}
+
+fn with_s3(
+    s3: S3Client,
+) -> impl Filter<Extract = (S3Client,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || s3.clone())
+}
+
+// a 503 while the photo bucket hasn't turned up, see watch_bucket
+fn photos_available(
+    bucket: BucketStatus,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let ready = bucket.ready();
+            async move {
+                if ready {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(ApiError::Unavailable(
+                        "photo storage isn't available right now, try again later".to_string(),
+                    )))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+fn with_presigner(
+    presigner: Presigner,
+) -> impl Filter<Extract = (Presigner,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || presigner.clone())
+}
+
+fn with_store(
+    store: MealDb,
+) -> impl Filter<Extract = (MealDb,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || store.clone())
+}
+
+fn with_revisions(
+    revisions: RevisionDb,
+) -> impl Filter<Extract = (RevisionDb,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || revisions.clone())
+}
+
+fn with_days(
+    days: DayDb,
+) -> impl Filter<Extract = (DayDb,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || days.clone())
+}
+
+fn with_users(
+    users: UserDb,
+) -> impl Filter<Extract = (UserDb,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || users.clone())
+}
+
+fn with_throttle(
+    throttle: Throttle,
+) -> impl Filter<Extract = (Throttle,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || throttle.clone())
+}
+
+fn with_dependencies(
+    dependencies: Dependencies,
+) -> impl Filter<Extract = (Dependencies,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || dependencies.clone())
+}
+
+// where the request came from, see login_throttle::client_ip
+fn with_client_ip(
+    config: Arc<Config>,
+) -> impl Filter<Extract = (Option<IpAddr>,), Error = warp::Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .map(
+            move |remote: Option<SocketAddr>, forwarded: Option<String>| {
+                login_throttle::client_ip(remote, forwarded, config.trust_proxy)
+            },
+        )
 }
 
 fn login_filter(
     jwtdb: JwtDb,
+    users: UserDb,
+    throttle: Throttle,
+    config: Arc<Config>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("login")
         .and(warp::post())
         .and(with_jwtdb(jwtdb))
+        .and(with_users(users))
+        .and(with_throttle(throttle))
+        .and(with_client_ip(config.clone()))
+        .and(with_config(config))
         .and(json_login_body())
         .and_then(login)
 }
 
-fn status_filter() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    warp::path!("health").and(warp::get()).and_then(healthy)
+fn logout_filter(
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("logout")
+        .and(warp::post())
+        .and(warp::header::<String>("Authorization"))
+        .and(with_jwtdb(jwtdb))
+        .and(with_config(config))
+        .and_then(logout)
+}
+
+fn refresh_filter(
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("token" / "refresh")
+        .and(warp::post())
+        .and(warp::cookie::optional(REFRESH_COOKIE))
+        .and(with_jwtdb(jwtdb))
+        .and(with_config(config))
+        .and_then(refresh)
+}
+
+// the process is up and answering, says nothing about DynamoDB or S3
+fn live_filter(
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("health" / "live")
+        .and(warp::get())
+        .and(with_config(config))
+        .and_then(healthy)
+}
+
+// curl -i http://127.0.0.1:3030/health/ready
+fn ready_filter(
+    dependencies: Dependencies,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("health" / "ready")
+        .and(warp::get())
+        .and(with_dependencies(dependencies))
+        .and(with_config(config))
+        .and_then(ready)
+}
+
+// curl http://127.0.0.1:3030/openapi.json
+fn openapi_filter(
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("openapi.json")
+        .and(warp::get())
+        .and(with_config(config))
+        .map(|config: Arc<Config>| warp::reply::json(&openapi::document(&config.version)))
+}
+
+// curl http://127.0.0.1:3030/metrics
+fn metrics_filter() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("metrics").and(warp::get()).map(|| {
+        warp::reply::with_header(metrics().render(), "content-type", prometheus::TEXT_FORMAT)
+    })
 }
 
 fn a_meal_filter(
-    ddb_client: dynomite::retry::RetryingDynamoDb<DynamoDbClient>,
+    store: MealDb,
+    presigner: Presigner,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("meals" / Uuid)
         .and(warp::get())
-        .and(with_ddb(ddb_client))
+        .and(with_store(store))
+        .and(with_presigner(presigner))
         .and_then(handlers::specific_meal)
 }
 
 fn all_meal_filter(
-    ddb_client: dynomite::retry::RetryingDynamoDb<DynamoDbClient>,
+    store: MealDb,
+    presigner: Presigner,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("meals")
         .and(warp::get())
-        .and(with_ddb(ddb_client))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_store(store))
+        .and(with_presigner(presigner))
         .and_then(all_meals)
 }
 
 fn meal_create(
-    ddb_client: dynomite::retry::RetryingDynamoDb<DynamoDbClient>,
+    store: MealDb,
+    revisions: RevisionDb,
     jwtdb: JwtDb,
+    config: Arc<Config>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("meals")
         .and(warp::post())
         .and(warp::header::<String>("Authorization"))
         .and(with_jwtdb(jwtdb))
-        .and_then(|auth: String, jwtdb: JwtDb| async move {
-            if is_authed(auth, jwtdb).await {
-                Ok(())
-            } else {
-                Err(warp::reject::not_found())
-            }
-        })
+        .and(with_config(config))
+        .and_then(
+            |auth: String, jwtdb: JwtDb, config: Arc<Config>| async move {
+                match authed_user(auth, jwtdb, &config).await {
+                    Some(user_id) => Ok(user_id),
+                    None => Err(errors::unauthorized()),
+                }
+            },
+        )
         .and(json_meal_body())
-        .and(with_ddb(ddb_client))
+        .and(with_store(store))
+        .and(with_revisions(revisions))
         .and_then(create_meal)
 }
 
 fn meal_delete(
+    store: MealDb,
+    revisions: RevisionDb,
+    users: UserDb,
     jwtdb: JwtDb,
+    config: Arc<Config>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("meals" / Uuid)
         .and(warp::delete())
         .and(warp::header::<String>("Authorization"))
         .and(with_jwtdb(jwtdb))
-        .and_then(|id: Uuid, auth: String, jwtdb: JwtDb| async move {
-            if is_authed(auth, jwtdb).await {
-                Ok(id)
-            } else {
-                Err(warp::reject::not_found())
-            }
-        })
+        .and(with_config(config.clone()))
+        .and_then(
+            |id: Uuid, auth: String, jwtdb: JwtDb, config: Arc<Config>| async move {
+                match authed_user(auth, jwtdb, &config).await {
+                    Some(user_id) => Ok((id, user_id)),
+                    None => Err(errors::unauthorized()),
+                }
+            },
+        )
+        .untuple_one()
+        .and(with_store(store))
+        .and(with_revisions(revisions))
+        .and(with_users(users))
+        .and(with_config(config))
         .and_then(delete_meal)
 }
 
 fn meal_update(
+    store: MealDb,
+    revisions: RevisionDb,
+    users: UserDb,
     jwtdb: JwtDb,
+    config: Arc<Config>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("meals" / Uuid)
         .and(warp::put())
         .and(warp::header::<String>("Authorization"))
         .and(with_jwtdb(jwtdb))
-        .and_then(|id: Uuid, auth: String, jwtdb: JwtDb| async move {
-            if is_authed(auth, jwtdb).await {
-                Ok(id)
-            } else {
-                Err(warp::reject::not_found())
-            }
-        })
+        .and(with_config(config))
+        .and_then(
+            |id: Uuid, auth: String, jwtdb: JwtDb, config: Arc<Config>| async move {
+                match authed_user(auth, jwtdb, &config).await {
+                    Some(user_id) => Ok((id, user_id)),
+                    None => Err(errors::unauthorized()),
+                }
+            },
+        )
+        .untuple_one()
         .and(json_meal_body())
+        .and(with_store(store))
+        .and(with_revisions(revisions))
+        .and(with_users(users))
         .and_then(update_meal)
 }
 
-// curl -i -X DELETE http://localhost:3030/meals/1
-async fn delete_meal(i: Uuid) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
-    let client = get_dynamodb_client();
-    let m = Meal {
-        id: i,
-        ..Default::default()
-    };
+fn meal_patch(
+    store: MealDb,
+    revisions: RevisionDb,
+    users: UserDb,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("meals" / Uuid)
+        .and(warp::patch())
+        .and(warp::header::<String>("Authorization"))
+        .and(with_jwtdb(jwtdb))
+        .and(with_config(config))
+        .and_then(
+            |id: Uuid, auth: String, jwtdb: JwtDb, config: Arc<Config>| async move {
+                match authed_user(auth, jwtdb, &config).await {
+                    Some(user_id) => Ok((id, user_id)),
+                    None => Err(errors::unauthorized()),
+                }
+            },
+        )
+        .untuple_one()
+        .and(merge_patch_body())
+        .and(with_store(store))
+        .and(with_revisions(revisions))
+        .and(with_users(users))
+        .and_then(handlers::patch_meal)
+}
 
-    let del = client
-        .delete_item(DeleteItemInput {
-            table_name: "meals".to_string(),
-            key: m.key(),
-            ..DeleteItemInput::default()
-        })
-        .await;
+fn revision_list(
+    revisions: RevisionDb,
+    users: UserDb,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("meals" / Uuid / "revisions")
+        .and(warp::get())
+        .and(warp::header::<String>("Authorization"))
+        .and(with_jwtdb(jwtdb))
+        .and(with_config(config))
+        .and_then(
+            |id: Uuid, auth: String, jwtdb: JwtDb, config: Arc<Config>| async move {
+                match authed_user(auth, jwtdb, &config).await {
+                    Some(user_id) => Ok((id, user_id)),
+                    None => Err(errors::unauthorized()),
+                }
+            },
+        )
+        .untuple_one()
+        .and(with_revisions(revisions))
+        .and(with_users(users))
+        .and_then(handlers::list_revisions)
+}
+
+fn revision_restore(
+    store: MealDb,
+    revisions: RevisionDb,
+    users: UserDb,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("meals" / Uuid / "revisions" / u64 / "restore")
+        .and(warp::post())
+        .and(warp::header::<String>("Authorization"))
+        .and(with_jwtdb(jwtdb))
+        .and(with_config(config))
+        .and_then(
+            |id: Uuid, rev: u64, auth: String, jwtdb: JwtDb, config: Arc<Config>| async move {
+                match authed_user(auth, jwtdb, &config).await {
+                    Some(user_id) => Ok((id, rev, user_id)),
+                    None => Err(errors::unauthorized()),
+                }
+            },
+        )
+        .untuple_one()
+        .and(with_store(store))
+        .and(with_revisions(revisions))
+        .and(with_users(users))
+        .and_then(handlers::restore_revision)
+}
+
+fn trash_list(
+    store: MealDb,
+    users: UserDb,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("trash")
+        .and(warp::get())
+        .and(warp::header::<String>("Authorization"))
+        .and(with_jwtdb(jwtdb))
+        .and(with_config(config))
+        .and_then(
+            |auth: String, jwtdb: JwtDb, config: Arc<Config>| async move {
+                match authed_user(auth, jwtdb, &config).await {
+                    Some(user_id) => Ok(user_id),
+                    None => Err(errors::unauthorized()),
+                }
+            },
+        )
+        .and(with_store(store))
+        .and(with_users(users))
+        .and_then(handlers::list_trash)
+}
+
+fn trash_restore(
+    store: MealDb,
+    revisions: RevisionDb,
+    users: UserDb,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("trash" / Uuid / "restore")
+        .and(warp::post())
+        .and(warp::header::<String>("Authorization"))
+        .and(with_jwtdb(jwtdb))
+        .and(with_config(config))
+        .and_then(
+            |id: Uuid, auth: String, jwtdb: JwtDb, config: Arc<Config>| async move {
+                match authed_user(auth, jwtdb, &config).await {
+                    Some(user_id) => Ok((id, user_id)),
+                    None => Err(errors::unauthorized()),
+                }
+            },
+        )
+        .untuple_one()
+        .and(with_store(store))
+        .and(with_revisions(revisions))
+        .and(with_users(users))
+        .and_then(handlers::restore_from_trash)
+}
+
+fn export_filter(
+    store: MealDb,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("export")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_store(store))
+        .and_then(handlers::export_meals)
+}
+
+fn meal_import(
+    store: MealDb,
+    revisions: RevisionDb,
+    users: UserDb,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("import")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::<String>("Authorization"))
+        .and(with_jwtdb(jwtdb))
+        .and(with_config(config))
+        .and_then(
+            |params: HashMap<String, String>,
+             auth: String,
+             jwtdb: JwtDb,
+             config: Arc<Config>| async move {
+                match authed_user(auth, jwtdb, &config).await {
+                    Some(user_id) => Ok((params, user_id)),
+                    None => Err(errors::unauthorized()),
+                }
+            },
+        )
+        .untuple_one()
+        // a few thousand meals
+        .and(warp::body::content_length_limit(1024 * 1024 * 8).and(warp::body::bytes()))
+        .and(with_store(store))
+        .and(with_revisions(revisions))
+        .and(with_users(users))
+        .and_then(handlers::import_meals)
+}
+
+fn a_day_filter(
+    days: DayDb,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("days" / Uuid)
+        .and(warp::get())
+        .and(with_days(days))
+        .and_then(handlers::specific_day)
+}
+
+fn all_day_filter(
+    days: DayDb,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("days")
+        .and(warp::get())
+        .and(with_days(days))
+        .and_then(handlers::all_days)
+}
+
+fn day_create(
+    days: DayDb,
+    store: MealDb,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("days")
+        .and(warp::post())
+        .and(warp::header::<String>("Authorization"))
+        .and(with_jwtdb(jwtdb))
+        .and(with_config(config))
+        .and_then(
+            |auth: String, jwtdb: JwtDb, config: Arc<Config>| async move {
+                if is_authed(auth, jwtdb, &config).await {
+                    Ok(())
+                } else {
+                    Err(errors::unauthorized())
+                }
+            },
+        )
+        .untuple_one()
+        .and(json_day_body())
+        .and(with_days(days))
+        .and(with_store(store))
+        .and_then(handlers::create_day)
+}
+
+fn day_update(
+    days: DayDb,
+    store: MealDb,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("days" / Uuid)
+        .and(warp::put())
+        .and(warp::header::<String>("Authorization"))
+        .and(with_jwtdb(jwtdb))
+        .and(with_config(config))
+        .and_then(
+            |id: Uuid, auth: String, jwtdb: JwtDb, config: Arc<Config>| async move {
+                if is_authed(auth, jwtdb, &config).await {
+                    Ok(id)
+                } else {
+                    Err(errors::unauthorized())
+                }
+            },
+        )
+        .and(json_day_body())
+        .and(with_days(days))
+        .and(with_store(store))
+        .and_then(handlers::update_day)
+}
+
+fn day_delete(
+    days: DayDb,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("days" / Uuid)
+        .and(warp::delete())
+        .and(warp::header::<String>("Authorization"))
+        .and(with_jwtdb(jwtdb))
+        .and(with_config(config))
+        .and_then(
+            |id: Uuid, auth: String, jwtdb: JwtDb, config: Arc<Config>| async move {
+                if is_authed(auth, jwtdb, &config).await {
+                    Ok(id)
+                } else {
+                    Err(errors::unauthorized())
+                }
+            },
+        )
+        .and(with_days(days))
+        .and_then(handlers::delete_day)
+}
+
+fn user_create(
+    users: UserDb,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("users")
+        .and(warp::post())
+        .and(warp::header::<String>("Authorization"))
+        .and(with_jwtdb(jwtdb))
+        .and(with_config(config))
+        .and_then(
+            |auth: String, jwtdb: JwtDb, config: Arc<Config>| async move {
+                match authed_user(auth, jwtdb, &config).await {
+                    Some(user_id) => Ok(user_id),
+                    None => Err(errors::unauthorized()),
+                }
+            },
+        )
+        .and(warp::body::content_length_limit(1024 * 16).and(warp::body::json()))
+        .and(with_users(users))
+        .and_then(handlers::create_user)
+}
+
+fn password_change(
+    users: UserDb,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("users" / "me" / "password")
+        .and(warp::post())
+        .and(warp::header::<String>("Authorization"))
+        .and(with_jwtdb(jwtdb.clone()))
+        .and(with_config(config))
+        .and_then(
+            |auth: String, jwtdb: JwtDb, config: Arc<Config>| async move {
+                match authed_user(auth, jwtdb, &config).await {
+                    Some(user_id) => Ok(user_id),
+                    None => Err(errors::unauthorized()),
+                }
+            },
+        )
+        .and(warp::body::content_length_limit(1024 * 16).and(warp::body::json()))
+        .and(with_users(users))
+        .and(with_jwtdb(jwtdb))
+        .and_then(handlers::change_password)
+}
+
+fn photo_upload(
+    store: MealDb,
+    users: UserDb,
+    s3: S3Client,
+    bucket: BucketStatus,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // leave some room on top of the photo itself for the multipart framing
+    let max_form = config.max_photo_bytes + 64 * 1024;
+    warp::path!("meals" / Uuid / "photos")
+        .and(warp::post())
+        .and(warp::header::<String>("Authorization"))
+        .and(with_jwtdb(jwtdb))
+        .and(with_config(config.clone()))
+        .and_then(
+            |id: Uuid, auth: String, jwtdb: JwtDb, config: Arc<Config>| async move {
+                match authed_user(auth, jwtdb, &config).await {
+                    Some(user_id) => Ok((id, user_id)),
+                    None => Err(errors::unauthorized()),
+                }
+            },
+        )
+        .untuple_one()
+        .and(photos_available(bucket))
+        .and(warp::multipart::form().max_length(max_form))
+        .and(with_store(store))
+        .and(with_users(users))
+        .and(with_s3(s3))
+        .and(with_config(config))
+        .and_then(handlers::upload_photo)
+}
+
+fn photo_delete(
+    store: MealDb,
+    users: UserDb,
+    s3: S3Client,
+    bucket: BucketStatus,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("meals" / Uuid / "photos" / String)
+        .and(warp::delete())
+        .and(warp::header::<String>("Authorization"))
+        .and(with_jwtdb(jwtdb))
+        .and(with_config(config.clone()))
+        .and_then(
+            |id: Uuid, file_name: String, auth: String, jwtdb: JwtDb, config: Arc<Config>| async move {
+                match authed_user(auth, jwtdb, &config).await {
+                    Some(user_id) => Ok((id, file_name, user_id)),
+                    None => Err(errors::unauthorized()),
+                }
+            },
+        )
+        .untuple_one()
+        .and(photos_available(bucket))
+        .and(with_store(store))
+        .and(with_users(users))
+        .and(with_s3(s3))
+        .and(with_config(config))
+        .and_then(handlers::delete_photo)
+}
 
-    match del {
-        Ok(deleted_item) => {
-            info!("item got deleted {:?}", deleted_item);
+// Only moves it to the trash, see GET /trash. It's purged for good trash_retention_secs later.
+// curl -i -X DELETE http://localhost:3030/meals/1
+async fn delete_meal(
+    i: Uuid,
+    caller: Uuid,
+    store: MealDb,
+    revisions: RevisionDb,
+    users: UserDb,
+    config: Arc<Config>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let existing = match store.get(i).await {
+        Ok(Some(existing)) if !existing.in_trash() => {
+            match handlers::can_change_meal(&existing, caller, &users).await {
+                Ok(true) => existing,
+                Ok(false) => return Ok(forbidden_resp()),
+                Err(e) => {
+                    info!("couldn't check who can delete {}: {:?}", i, e);
+                    return Ok(Box::new(ApiError::from(e)));
+                }
+            }
+        }
+        // nothing to delete is fine, same as before, and so is deleting it twice
+        Ok(_) => return Ok(Box::new(StatusCode::NO_CONTENT)),
+        Err(e) => {
+            info!("couldn't look up meal {}: {:?}", i, e);
+            return Ok(Box::new(ApiError::from(e)));
+        }
+    };
+    let now = token_store::now_secs();
+    let trashed = Meal {
+        deleted_at: Some(now),
+        purge_at: Some(now + config.trash_retention_secs),
+        version: existing.version + 1,
+        ..existing.clone()
+    };
+    match store
+        .put_if_version(trashed.clone(), existing.version)
+        .await
+    {
+        Ok(_) => {
+            info!("meal {} went in the trash", i);
+            handlers::record_revision(&revisions, trashed, RevisionAction::Deleted, caller).await;
             Ok(Box::new(StatusCode::NO_CONTENT))
         }
         Err(e) => {
             info!("item couldn't be deleted: {:?}", e);
-            Ok(Box::new(StatusCode::BAD_REQUEST))
+            Ok(Box::new(ApiError::from(e)))
         }
     }
 }
 
-async fn healthy() -> Result<Box<dyn warp::Reply>, warp::Rejection> {
-    let version_txt = match GITBITS.len() {
-        0 => "dev".to_string(),
-        _ => GITBITS.to_string().replace('\n', ""),
-    };
+async fn healthy(config: Arc<Config>) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
     let h = backend_types::Health {
         healthy: true,
-        version: version_txt,
+        version: config.version.clone(),
     };
     let r = warp::reply::json(&h);
     Ok(Box::new(warp::reply::with_status(r, StatusCode::OK)))
 }
 
-// handle local vs "real" dynamodb
-fn get_dynamodb_client() -> dynomite::retry::RetryingDynamoDb<DynamoDbClient> {
-    // be nice to not have to do this all the time. Use lazy_static?
-    match DYNAMODB_LOC.replace("\n", "").len() {
-        0 => {
-            info!("Using real Dynamodb with a new client");
-            // use profile provider only
-            let profile_creds =
-                ProfileProvider::new().expect("Couldn't make new Profile credential provider");
-            let http_client = HttpClient::new().expect("Couldn't make new HTTP client");
-            DynamoDbClient::new_with(http_client, profile_creds, Region::UsWest2)
-                .with_retries(Policy::default())
-        }
-        _ => {
-            info!("Using local Dynamodb with a new client");
-            DynamoDbClient::new(Region::Custom {
-                name: "us-east-1".into(), // local testing only
-                endpoint: DYNAMODB_LOC.into(),
-            })
-            .with_retries(Policy::default())
-        }
-    }
-}
-
-async fn unauthed_resp() -> Result<Box<dyn warp::Reply>, warp::Rejection> {
-    let r = warp::reply::json(&());
+// a 503 when DynamoDB didn't answer, so whatever's in front stops sending traffic
+async fn ready(
+    dependencies: Dependencies,
+    config: Arc<Config>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let checks = dependencies.check().await;
+    let r = backend_types::Readiness {
+        ready: checks.iter().all(|c| c.ok || !c.required),
+        degraded: checks.iter().any(|c| !c.ok),
+        version: config.version.clone(),
+        checks,
+    };
+    let status = if r.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
     Ok(Box::new(warp::reply::with_status(
-        r,
-        StatusCode::UNAUTHORIZED,
+        warp::reply::json(&r),
+        status,
     )))
 }
 
-async fn update_meal(_id: Uuid, create: Meal) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
-    // make sure _id matches create.id
-    let client = get_dynamodb_client();
+fn forbidden_resp() -> Box<dyn warp::Reply> {
+    Box::new(handlers::not_yours())
+}
 
-    let d_result = client
-        .put_item(PutItemInput {
-            table_name: "meals".to_string(),
-            item: create.clone().into(),
-            ..PutItemInput::default()
-        })
-        .await;
-    match d_result {
+// Send back the version you started editing. If someone else saved in the meantime it's
+// a 409 with what's there now, so the frontend can show it instead of clobbering it.
+async fn update_meal(
+    id: Uuid,
+    caller: Uuid,
+    update: Meal,
+    store: MealDb,
+    revisions: RevisionDb,
+    users: UserDb,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    if id != update.id {
+        return Ok(Box::new(ApiError::BadRequest(
+            "the meal id in the body doesn't match the one in the path".to_string(),
+        )));
+    }
+    let existing = match store.get(id).await {
+        Ok(existing) => existing,
+        Err(e) => {
+            info!("couldn't look up meal {}: {:?}", id, e);
+            return Ok(Box::new(ApiError::from(e)));
+        }
+    };
+    // photos only change through their own endpoints, the frontend never sees the raw keys
+    let (expected, meal) = match existing {
+        Some(existing) if existing.in_trash() => {
+            return Ok(Box::new(ApiError::NotFound(
+                "that meal is in the trash, restore it first".to_string(),
+            )))
+        }
+        Some(existing) => {
+            match handlers::can_change_meal(&existing, caller, &users).await {
+                Ok(true) => (),
+                Ok(false) => return Ok(forbidden_resp()),
+                Err(e) => {
+                    info!("couldn't check who can update {}: {:?}", existing.id, e);
+                    return Ok(Box::new(ApiError::from(e)));
+                }
+            }
+            if update.version != existing.version {
+                return Ok(conflict_resp(existing));
+            }
+            // and ownership doesn't change hands by editing
+            let meal = Meal {
+                photos: existing.photos,
+                renditions: existing.renditions,
+                owner: existing.owner,
+                version: existing.version + 1,
+                deleted_at: None,
+                purge_at: None,
+                ..update
+            };
+            (existing.version, meal)
+        }
+        None => {
+            match handlers::can_recreate(id, caller, &revisions, &users).await {
+                Ok(true) => (),
+                Ok(false) => return Ok(forbidden_resp()),
+                Err(e) => {
+                    info!("couldn't check who can save {}: {:?}", id, e);
+                    return Ok(Box::new(ApiError::from(e)));
+                }
+            }
+            (
+                0,
+                Meal {
+                    photos: None,
+                    renditions: None,
+                    owner: Some(caller),
+                    version: 1,
+                    deleted_at: None,
+                    purge_at: None,
+                    ..update
+                },
+            )
+        }
+    };
+    match store.put_if_version(meal.clone(), expected).await {
         Ok(_) => {
-            let r = warp::reply::json(&create);
+            let action = if expected == 0 {
+                RevisionAction::Created
+            } else {
+                RevisionAction::Updated
+            };
+            handlers::record_revision(&revisions, meal.clone(), action, caller).await;
+            let r = warp::reply::json(&meal);
             Ok(Box::new(warp::reply::with_status(r, StatusCode::ACCEPTED)))
         }
+        // beaten to it between the get and the put
+        Err(StoreError::Conflict) => match store.get(id).await {
+            Ok(Some(current)) => Ok(conflict_resp(current)),
+            _ => Ok(Box::new(ApiError::from(StoreError::Conflict))),
+        },
         Err(e) => {
             info!("blew up: {:?}", e);
-            let r = warp::reply::json(&());
-            Ok(Box::new(warp::reply::with_status(
-                r,
-                StatusCode::BAD_REQUEST,
-            )))
+            Ok(Box::new(ApiError::from(e)))
         }
     }
 }
 
+// the server's copy, so whoever lost can see what changed
+fn conflict_resp(current: Meal) -> Box<dyn warp::Reply> {
+    let r = warp::reply::json(&current);
+    Box::new(warp::reply::with_status(r, StatusCode::CONFLICT))
+}
+
+// curl -i "http://127.0.0.1:3030/meals?limit=10&min_stars=4&name=Burr"
 async fn all_meals(
-    client: dynomite::retry::RetryingDynamoDb<DynamoDbClient>,
+    params: HashMap<String, String>,
+    store: MealDb,
+    presigner: Presigner,
 ) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
-    let scan_all_things = client
-        .scan(ScanInput {
-            table_name: "meals".to_string(),
-            ..ScanInput::default()
-        })
-        .await;
-    match scan_all_things {
-        Ok(s) => {
-            let doot: Vec<Meal> = s
-                .items
-                .unwrap()
-                .iter()
-                .map(|result| Meal::from_attrs(result.clone()).unwrap())
-                .collect();
-            let r = warp::reply::json(&doot);
+    let q = match MealQuery::from_params(&params) {
+        Ok(q) => q,
+        Err(e) => return Ok(Box::new(ApiError::BadRequest(e))),
+    };
+    match store.query(&q).await {
+        Ok(doot) => {
+            let items = match presigner.summaries(doot.items).await {
+                Ok(items) => items,
+                Err(e) => {
+                    info!("couldn't presign thumbnail URLs: {:?}", e);
+                    return Ok(Box::new(ApiError::Internal(e.to_string())));
+                }
+            };
+            let page = Page {
+                items,
+                next_cursor: doot.next_cursor,
+            };
+            let r = warp::reply::json(&page);
             Ok(Box::new(warp::reply::with_status(r, StatusCode::OK)))
         }
         Err(e) => {
             info!("nope: {:?}", e);
-            let r = warp::reply::json(&backend_types::ErrorResp {
-                error: e.to_string(),
-            });
-            Ok(Box::new(warp::reply::with_status(
-                r,
-                StatusCode::INTERNAL_SERVER_ERROR,
-            )))
+            Ok(Box::new(ApiError::from(e)))
         }
     }
 }
 
 // should work with curl -i -X POST -H "content-type: application/json" -d '{"name":"Wings","id":3,"description":"mmm"}'  http://127.0.0.1:3030/meals/
 pub async fn create_meal(
-    _: (),
+    owner: Uuid,
     create: Meal,
-    client: dynomite::retry::RetryingDynamoDb<DynamoDbClient>,
+    store: MealDb,
+    revisions: RevisionDb,
 ) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
     log::debug!("create_meal: {:?}", create);
 
     let newone = Meal {
         id: Uuid::new_v4(),
+        // added later through the photo endpoints
+        photos: None,
+        renditions: None,
+        owner: Some(owner),
+        version: 1,
+        deleted_at: None,
+        purge_at: None,
         ..create
     };
 
-    let d_result = client
-        .put_item(PutItemInput {
-            table_name: "meals".to_string(),
-            item: newone.clone().into(),
-            ..PutItemInput::default()
-        })
-        .await;
-    match d_result {
+    match store.put(newone.clone()).await {
         Ok(_) => {
             info!("aww yiss added it");
+            handlers::record_revision(&revisions, newone.clone(), RevisionAction::Created, owner)
+                .await;
             let r = warp::reply::json(&newone);
             Ok(Box::new(warp::reply::with_status(r, StatusCode::CREATED)))
         }
         Err(e) => {
             info!("blew up: {:?}", e);
-            let r = warp::reply::json(&backend_types::ErrorResp {
-                error: e.to_string(),
-            });
-            Ok(Box::new(warp::reply::with_status(
-                r,
-                StatusCode::INTERNAL_SERVER_ERROR,
-            )))
+            Ok(Box::new(ApiError::from(e)))
         }
     }
 }
@@ -362,57 +1273,250 @@ pub async fn create_meal(
 // curl -i -X POST -d '{"user": "foo", "pw": "bar"}' -H "Content-type: application/json" localhost:3030/login
 pub async fn login(
     jwtdb: JwtDb,
+    users: UserDb,
+    throttle: Throttle,
+    ip: Option<IpAddr>,
+    config: Arc<Config>,
     login: backend_types::Login,
 ) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
-    // why with the newlines?
-    if login.user == "matthew" && login.pw == SECUREPW.replace('\n', "") {
+    // don't even look at the password while it's backing off, and count this one as a
+    // failure until it turns out not to be
+    if let Some(wait) = throttle.reserve(&login.user, ip, Instant::now()).await {
+        info!(
+            "login for {} from {:?} throttled for {:?}",
+            login.user, ip, wait
+        );
+        metrics().login(LoginOutcome::Throttled);
+        return Ok(throttled_resp(wait));
+    }
+    let found = match users.find_by_username(&login.user).await {
+        Ok(found) => found,
+        Err(e) => {
+            info!("couldn't look up {}: {:?}", login.user, e);
+            return Ok(Box::new(ApiError::Internal(e.to_string())));
+        }
+    };
+    let hash = match &found {
+        Some(u) => u.password_hash.clone(),
+        None => user_store::dummy_hash().to_string(),
+    };
+    let pw = login.pw;
+    let good = tokio::task::spawn_blocking(move || user_store::verify_password(&hash, &pw))
+        .await
+        .unwrap_or(false);
+    let user = found.filter(|_| good);
+    if let Some(user) = user {
         debug!("Successful login");
-        // yeah should probably handle errors:
-        let in_future = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            + 10_000_000; // forever-ish
-        let claims = backend_types::Claims {
-            exp: in_future as u32,
-            sub: login.user,
-        };
-        let token = encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(JWT_SECRET.as_ref()),
-        )
-        .unwrap(); // TODO: handle failure
-
-        debug!("Made this jwt: {:?}", token);
-
-        // store jwt to data store
-        let mut jwts = jwtdb.lock().await;
-        jwts.insert(token.clone(), 0); // should we use the value for something?
-        debug!("inserted token into db: {:?}", token);
+        throttle.succeeded(&login.user, ip).await;
+        metrics().login(LoginOutcome::Success);
+        // every login starts a new family of tokens, see token_store
+        match issue_tokens(user.id, Uuid::new_v4(), &jwtdb, &config).await {
+            Ok((resp, cookie)) => Ok(tokens_resp(resp, cookie)),
+            Err(e) => {
+                info!("couldn't make tokens: {}", e);
+                Ok(Box::new(ApiError::Internal(e)))
+            }
+        }
+    } else {
+        debug!("Incorrect username/pw");
+        metrics().login(LoginOutcome::Failure);
+        Ok(Box::new(ApiError::Unauthorized))
+    }
+}
 
-        // return jwt
-        let resp = backend_types::LoginResp { jwt: token };
-        let r = warp::reply::json(&resp);
+fn throttled_resp(wait: Duration) -> Box<dyn warp::Reply> {
+    // round up, a Retry-After of 0 would just get hammered
+    let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    let e = ApiError::TooManyRequests(format!(
+        "too many failed logins, try again in {} seconds",
+        secs
+    ));
+    Box::new(warp::reply::with_header(
+        e,
+        warp::http::header::RETRY_AFTER,
+        secs.to_string(),
+    ))
+}
 
-        let r2 = warp::reply::with_header(
-            r,
+// curl -i -X POST -H "Authorization: bearer: $JWT" localhost:3030/logout
+async fn logout(
+    auth: String,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    if authed_user(auth.clone(), jwtdb.clone(), &config)
+        .await
+        .is_none()
+    {
+        return Err(errors::unauthorized());
+    }
+    // takes the refresh token from the same login with it
+    match jwtdb.revoke(&auth.replace("bearer: ", "")).await {
+        Ok(_) => Ok(Box::new(warp::reply::with_header(
+            StatusCode::NO_CONTENT,
             warp::http::header::SET_COOKIE,
-            format!("rtoken=\"{}\"", resp.jwt),
-        );
-        Ok(Box::new(warp::reply::with_status(r2, StatusCode::OK)))
-    } else {
-        debug!("Incorrect username/pw");
-        // we should see about leveraging nginx to also help with throttling to prevent brute force attempts
-        std::thread::sleep(std::time::Duration::from_millis(1_000));
-        let r = warp::reply::json(&());
-        Ok(Box::new(warp::reply::with_status(
-            r,
-            StatusCode::UNAUTHORIZED,
-        )))
+            refresh_cookie("", 0, &config),
+        ))),
+        Err(e) => {
+            info!("couldn't revoke a token: {:?}", e);
+            Ok(Box::new(ApiError::Internal(e.to_string())))
+        }
+    }
+}
+
+// curl -i -X POST -b "rr_refresh=$REFRESH" localhost:3030/token/refresh
+async fn refresh(
+    cookie: Option<String>,
+    jwtdb: JwtDb,
+    config: Arc<Config>,
+) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    let token = match cookie {
+        Some(t) => t,
+        None => return Ok(refresh_failed_resp(&config)),
+    };
+    let (user_id, family) = match jwtdb.use_refresh(&token, config.refresh_grace_secs).await {
+        Ok(Refresh::Fresh { user_id, family }) => (user_id, family),
+        Ok(Refresh::Reused { user_id, family }) => {
+            // there's a copy of it out there and no telling which of us is the real one,
+            // so that whole login goes
+            warn!(
+                "refresh token reused for user {}, revoking that login",
+                user_id
+            );
+            if let Err(e) = jwtdb.revoke_family(family).await {
+                info!("couldn't revoke token family {}: {:?}", family, e);
+            }
+            return Ok(refresh_failed_resp(&config));
+        }
+        Ok(Refresh::Unknown) => return Ok(refresh_failed_resp(&config)),
+        Err(e) => {
+            info!("couldn't check the refresh token: {:?}", e);
+            return Ok(Box::new(ApiError::Internal(e.to_string())));
+        }
+    };
+    match issue_tokens(user_id, family, &jwtdb, &config).await {
+        Ok((resp, cookie)) => Ok(tokens_resp(resp, cookie)),
+        Err(e) => {
+            info!("couldn't make tokens: {}", e);
+            Ok(Box::new(ApiError::Internal(e)))
+        }
     }
 }
 
+// A short lived access token and a refresh token to get the next one with, both stored
+// under the login's family. Gives back the response body and the cookie to set.
+async fn issue_tokens(
+    user_id: Uuid,
+    family: Uuid,
+    jwtdb: &JwtDb,
+    config: &Config,
+) -> Result<(backend_types::LoginResp, String), String> {
+    let now = token_store::now_secs();
+    let claims = backend_types::Claims {
+        exp: now + config.access_token_secs,
+        sub: user_id.to_string(),
+        jti: Uuid::new_v4().to_string(),
+    };
+    let jwt = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(config.jwt_secret.as_ref()),
+    )
+    .map_err(|e| e.to_string())?;
+    jwtdb
+        .insert(&jwt, user_id, family, claims.exp)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    let refresh = base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+    jwtdb
+        .insert_refresh(&refresh, user_id, family, now + config.refresh_token_secs)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let resp = backend_types::LoginResp {
+        jwt,
+        expires_in: config.access_token_secs,
+    };
+    Ok((
+        resp,
+        refresh_cookie(&refresh, config.refresh_token_secs, config),
+    ))
+}
+
+// HttpOnly so scripts can't read it, and only sent along to /token/*. Behind nginx that's
+// /api/token, see refresh_cookie_path. Logout goes by the access token and just clears it.
+fn refresh_cookie(token: &str, max_age: u64, config: &Config) -> String {
+    format!(
+        "{}={}; Max-Age={}; Path={}; HttpOnly; SameSite=Strict{}",
+        REFRESH_COOKIE,
+        token,
+        max_age,
+        config.refresh_cookie_path,
+        if config.secure_cookies {
+            "; Secure"
+        } else {
+            ""
+        }
+    )
+}
+
+fn tokens_resp(resp: backend_types::LoginResp, cookie: String) -> Box<dyn warp::Reply> {
+    let r = warp::reply::json(&resp);
+    let r = warp::reply::with_header(r, warp::http::header::SET_COOKIE, cookie);
+    Box::new(warp::reply::with_status(r, StatusCode::OK))
+}
+
+// clear the cookie too, it's no good to anyone now
+fn refresh_failed_resp(config: &Config) -> Box<dyn warp::Reply> {
+    Box::new(warp::reply::with_header(
+        ApiError::Unauthorized,
+        warp::http::header::SET_COOKIE,
+        refresh_cookie("", 0, config),
+    ))
+}
+
+// clear out expired tokens every so often so the store doesn't grow forever
+fn sweep_tokens(jwtdb: JwtDb, every: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            match jwtdb.purge_expired(token_store::now_secs()).await {
+                Ok(0) => (),
+                Ok(n) => info!("swept {} expired tokens", n),
+                Err(e) => info!("couldn't sweep expired tokens: {:?}", e),
+            }
+        }
+    });
+}
+
+// purge what's been in the trash long enough, photos and all
+fn sweep_trash(
+    store: MealDb,
+    s3: S3Client,
+    bucket: BucketStatus,
+    bucket_name: String,
+    every: Duration,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            // the photos have to go first, so wait for the bucket
+            if !bucket.ready() {
+                continue;
+            }
+            match handlers::purge_trash(&store, &s3, &bucket_name, token_store::now_secs()).await {
+                Ok(0) => (),
+                Ok(n) => info!("purged {} meals from the trash", n),
+                Err(e) => info!("couldn't purge the trash: {:?}", e),
+            }
+        }
+    });
+}
+
 fn json_login_body(
 ) -> impl Filter<Extract = (backend_types::Login,), Error = warp::Rejection> + Clone {
     warp::body::content_length_limit(1024 * 16).and(warp::body::json())
@@ -422,140 +1526,192 @@ fn json_meal_body() -> impl Filter<Extract = (Meal,), Error = warp::Rejection> +
     warp::body::content_length_limit(1024 * 16).and(warp::body::json())
 }
 
-async fn is_db_avail(
-    client: dynomite::retry::RetryingDynamoDb<dynomite::dynamodb::DynamoDbClient>,
-) -> bool {
-    let table_name = "meals".to_string();
-    let create_table_req = client.create_table(CreateTableInput {
-        table_name,
-        key_schema: vec![KeySchemaElement {
-            attribute_name: "id".into(),
-            key_type: "HASH".into(),
-        }],
-        attribute_definitions: vec![AttributeDefinition {
-            attribute_name: "id".into(),
-            attribute_type: "S".into(),
-        }],
-        provisioned_throughput: Some(ProvisionedThroughput {
-            read_capacity_units: 10,  // 25 max for free tier
-            write_capacity_units: 10, // 25 max for free tier
-        }),
-        ..CreateTableInput::default()
-    });
-    let f = create_table_req.await;
-    match f {
-        Ok(_) => {
-            debug!("All good making table");
-            true
-        }
-        Err(e) => {
-            // table may not be ready yet, wait and retry
-            // Also, local dynamo returns a different string than real:
-            let e_msg = e.to_string();
-            debug!("error message is '{}'. Checking if that contains the string 'Table already exists'.", e_msg);
-            if e_msg.contains("preexisting table") || e_msg.contains("Table already exists") {
-                return true;
+// the raw patch, it only gets parsed once we know which meal it's for
+fn merge_patch_body() -> impl Filter<Extract = (bytes::Bytes,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("content-type")
+        .and_then(|ct: Option<String>| async move {
+            // a charset or anything else after the ; is fine
+            match ct.as_deref().and_then(|c| c.split(';').next()) {
+                Some(c)
+                    if c.trim()
+                        .eq_ignore_ascii_case("application/merge-patch+json") =>
+                {
+                    Ok(())
+                }
+                _ => Err(warp::reject::custom(ApiError::UnsupportedMediaType(
+                    "patches have to be application/merge-patch+json".to_string(),
+                ))),
             }
-            false
-        }
-    }
+        })
+        .untuple_one()
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(warp::body::bytes())
 }
 
-async fn prepopulate_db(
-    client: dynomite::retry::RetryingDynamoDb<dynomite::dynamodb::DynamoDbClient>,
-) {
-    let mut attempts: i32 = 0;
-    loop {
-        debug!("Waiting for the db to be available");
-        if is_db_avail(client.clone()).await {
-            debug!("DB is available");
-            break;
-        }
-        if attempts > 10 {
-            debug!("DB is not available after 10 attempts, we're out");
-            panic!("Stopped waiting for the DB to become available");
-        }
-        attempts += 1;
-        debug!("sleeping for a minute and retrying");
-        std::thread::sleep(std::time::Duration::from_millis(5_000));
-    }
-    let table_name = "meals".to_string();
-    let create_table_req = client.create_table(CreateTableInput {
-        table_name: table_name.clone(),
-        key_schema: vec![KeySchemaElement {
-            attribute_name: "id".into(),
-            key_type: "HASH".into(),
-        }],
-        attribute_definitions: vec![AttributeDefinition {
-            attribute_name: "id".into(),
-            attribute_type: "S".into(),
-        }],
-        provisioned_throughput: Some(ProvisionedThroughput {
-            read_capacity_units: 10,  // 25 max for free tier
-            write_capacity_units: 10, // 25 max for free tier
-        }),
-        ..CreateTableInput::default()
-    });
-    let f = create_table_req.await;
-    match f {
-        Ok(_) => debug!("All good making table"),
-        Err(e) => {
-            debug!("Issue creating table: {:?}. Forging ahead anyways.", e);
-        }
-    }
+fn json_day_body() -> impl Filter<Extract = (RefeedDay,), Error = warp::Rejection> + Clone {
+    warp::body::content_length_limit(1024 * 16).and(warp::body::json())
+}
+
+// put a couple of well known meals in place, the cukes rely on the first one
+async fn seed_meals(store: MealDb) {
     let id = Uuid::parse_str("f11b1c5e-d6d8-4dce-8a9d-9e05d870b881").unwrap();
     let mut m = Meal {
         id,
         name: "Burritos".to_string(),
         photos: None,
+        renditions: None,
         description: "Amazing burritos".to_string(),
         stars: Some(4),
+        // from before accounts, admins look after these
+        owner: None,
+        version: 1,
+        deleted_at: None,
+        purge_at: None,
     };
 
-    let _ = client
-        .put_item(PutItemInput {
-            table_name: table_name.clone(),
-            item: m.clone().into(),
-            ..PutItemInput::default()
-        })
-        .await;
+    let _ = store.put(m.clone()).await;
 
     m.id = Uuid::parse_str("936DA01F9ABD4d9d80C702AF85C822A8").unwrap();
     m.name = "Pizza".to_string();
     m.description = "Delicious pizza".to_string();
     m.stars = Some(5);
 
-    let _ = client
-        .put_item(PutItemInput {
-            table_name,
-            item: m.clone().into(),
-            ..PutItemInput::default()
-        })
-        .await;
+    let _ = store.put(m).await;
 }
 
-async fn is_authed(auth: String, jwtdb: JwtDb) -> bool {
+// make the admin account from the config the first time we run against a users table
+async fn ensure_admin(users: UserDb, config: &Config) {
+    match users.find_by_username(&config.admin_user).await {
+        Ok(Some(_)) => return,
+        Ok(None) => (),
+        Err(e) => {
+            error!("couldn't check for the admin user: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let password = config.login_password.clone();
+    let hash = match tokio::task::spawn_blocking(move || user_store::hash_password(&password)).await
+    {
+        Ok(Ok(h)) => h,
+        _ => {
+            error!("couldn't hash the admin password");
+            std::process::exit(1);
+        }
+    };
+    let admin = User {
+        id: Uuid::new_v4(),
+        username: config.admin_user.clone(),
+        password_hash: hash,
+        admin: true,
+    };
+    match users.put(admin).await {
+        Ok(_) => info!("Created admin user {}", config.admin_user),
+        Err(e) => {
+            error!("couldn't create the admin user: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn is_authed(auth: String, jwtdb: JwtDb, config: &Config) -> bool {
+    authed_user(auth, jwtdb, config).await.is_some()
+}
+
+// the id of the user the token belongs to, if it's one of ours and still good
+async fn authed_user(auth: String, jwtdb: JwtDb, config: &Config) -> Option<Uuid> {
     debug!("Checking this jwt: {}", auth);
     let a = auth.replace("bearer: ", "");
     let token = decode::<backend_types::Claims>(
         &a,
-        &DecodingKey::from_secret(JWT_SECRET.as_ref()),
+        &DecodingKey::from_secret(config.jwt_secret.as_ref()),
         &Validation::default(),
     );
     match token {
         Ok(_) => {
-            // use what's returned in the Ok field to inspect token contents like the claims subject (account)
             debug!("Token is decodable");
-            let d = jwtdb.lock().await;
-            let c = d.contains_key(&a);
-            if !c {
-                debug!("JWT isn't one we know about, rejecting it");
+            match jwtdb.owner(&a).await {
+                Ok(Some(user_id)) => Some(user_id),
+                Ok(None) => {
+                    debug!("JWT isn't one we know about, rejecting it");
+                    None
+                }
+                Err(e) => {
+                    info!("couldn't check the token store: {:?}", e);
+                    None
+                }
             }
-            c
         }
         Err(e) => {
             debug!("Token no good: {:?}", e);
-            false
+            None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::Reply;
+
+    #[tokio::test]
+    async fn test_put_after_purge() {
+        let store: MealDb = Arc::new(InMemoryMealStore::new());
+        let revisions: RevisionDb = Arc::new(InMemoryRevisionStore::new());
+        let users: UserDb = Arc::new(InMemoryUserStore::new());
+        let (owner, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let meal = Meal {
+            id: Uuid::new_v4(),
+            name: "Ramen".to_string(),
+            description: "the secret recipe".to_string(),
+            owner: Some(owner),
+            version: 1,
+            ..Meal::default()
+        };
+        // made and purged, so only the history is left
+        handlers::record_revision(&revisions, meal.clone(), RevisionAction::Created, owner).await;
+
+        let put = |caller: Uuid| {
+            let update = Meal {
+                description: "mine now".to_string(),
+                version: 0,
+                ..meal.clone()
+            };
+            update_meal(
+                meal.id,
+                caller,
+                update,
+                store.clone(),
+                revisions.clone(),
+                users.clone(),
+            )
+        };
+        let status = |r: Box<dyn warp::Reply>| r.into_response().status();
+        assert_eq!(status(put(other).await.unwrap()), StatusCode::FORBIDDEN);
+        assert_eq!(store.get(meal.id).await.unwrap(), None);
+        let history = |caller: Uuid| {
+            handlers::list_revisions(meal.id, caller, revisions.clone(), users.clone())
+        };
+        assert_eq!(status(history(other).await.unwrap()), StatusCode::FORBIDDEN);
+
+        // whoever it belonged to can still have it back
+        assert_eq!(status(put(owner).await.unwrap()), StatusCode::ACCEPTED);
+        let back = store.get(meal.id).await.unwrap().unwrap();
+        assert_eq!(back.owner, Some(owner));
+        assert_eq!(status(history(owner).await.unwrap()), StatusCode::OK);
+
+        // and an id nobody has used is anyone's
+        let fresh = Meal {
+            id: Uuid::new_v4(),
+            ..meal.clone()
+        };
+        let r = update_meal(
+            fresh.id,
+            other,
+            fresh.clone(),
+            store.clone(),
+            revisions.clone(),
+            users.clone(),
+        );
+        assert_eq!(status(r.await.unwrap()), StatusCode::ACCEPTED);
+    }
+}
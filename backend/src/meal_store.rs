@@ -0,0 +1,824 @@
+use crate::tables::DynamoClient;
+use async_trait::async_trait;
+use dynomite::{
+    dynamodb::{
+        AttributeValue, DeleteItemError, DeleteItemInput, DynamoDb, GetItemInput, PutItemError,
+        PutItemInput, ScanInput, UpdateItemError, UpdateItemInput,
+    },
+    Attribute, FromAttributes, Item,
+};
+use rusoto_core::RusotoError;
+use shared::{Meal, Page};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+// shared handle to whichever store we're running with
+pub type MealDb = Arc<dyn MealStore>;
+
+#[derive(Debug)]
+pub enum StoreError {
+    // the backing store couldn't do what we asked
+    Backend(String),
+    // we got something back we couldn't turn into a Meal or RefeedDay
+    Malformed(String),
+    // a conditional write lost, someone else changed it first
+    Conflict,
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Backend(e) => write!(f, "store error: {}", e),
+            StoreError::Malformed(e) => write!(f, "malformed item: {}", e),
+            StoreError::Conflict => write!(f, "changed by someone else in the meantime"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+// GET /meals page size when none is asked for, and the most you can ask for
+pub static DEFAULT_PAGE_SIZE: usize = 50;
+pub static MAX_PAGE_SIZE: usize = 100;
+
+// One page of meals, filtered server side
+#[derive(Debug, Clone, PartialEq)]
+pub struct MealQuery {
+    pub limit: usize,
+    // carry on after this meal, comes from the previous page's cursor
+    pub start_after: Option<Uuid>,
+    pub min_stars: Option<i32>,
+    // case sensitive, that's all DynamoDB's contains() does
+    pub name: Option<String>,
+}
+
+impl Default for MealQuery {
+    fn default() -> Self {
+        MealQuery {
+            limit: DEFAULT_PAGE_SIZE,
+            start_after: None,
+            min_stars: None,
+            name: None,
+        }
+    }
+}
+
+impl MealQuery {
+    // from the query string, eg ?limit=10&cursor=...&min_stars=3&name=Burr
+    pub fn from_params(params: &HashMap<String, String>) -> Result<MealQuery, String> {
+        let mut q = MealQuery::default();
+        for (k, v) in params {
+            match k.as_str() {
+                "limit" => {
+                    q.limit = v
+                        .parse()
+                        .ok()
+                        .filter(|l| *l > 0 && *l <= MAX_PAGE_SIZE)
+                        .ok_or_else(|| {
+                            format!("limit has to be a number from 1 to {}", MAX_PAGE_SIZE)
+                        })?
+                }
+                "cursor" => q.start_after = Some(decode_cursor(v)?),
+                "min_stars" => {
+                    q.min_stars = Some(
+                        v.parse()
+                            .map_err(|_| "min_stars has to be a number".to_string())?,
+                    )
+                }
+                "name" if !v.is_empty() => q.name = Some(v.clone()),
+                "name" => (),
+                other => return Err(format!("unknown query parameter '{}'", other)),
+            }
+        }
+        Ok(q)
+    }
+
+    fn matches(&self, meal: &Meal) -> bool {
+        if meal.in_trash() {
+            return false;
+        }
+        let stars_ok = match self.min_stars {
+            Some(min) => meal.stars.is_some_and(|s| s >= min),
+            None => true,
+        };
+        let name_ok = match &self.name {
+            Some(n) => meal.name.contains(n.as_str()),
+            None => true,
+        };
+        stars_ok && name_ok
+    }
+}
+
+// What a PATCH /meals/{id} changes, None leaves a field as it is
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MealPatch {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    // Some(None) clears the stars
+    pub stars: Option<Option<i32>>,
+}
+
+impl MealPatch {
+    // A JSON merge patch (RFC 7386): fields that aren't there stay as they are and null
+    // removes one. Only the fields people edit can be patched, photos have their own
+    // endpoints and the rest are the backend's to look after.
+    pub fn from_json(patch: &serde_json::Value) -> Result<MealPatch, String> {
+        let fields = patch
+            .as_object()
+            .ok_or_else(|| "a merge patch has to be a JSON object".to_string())?;
+        let mut p = MealPatch::default();
+        for (k, v) in fields {
+            match (k.as_str(), v) {
+                ("name", serde_json::Value::String(n)) => p.name = Some(n.clone()),
+                ("description", serde_json::Value::String(d)) => p.description = Some(d.clone()),
+                ("name", _) | ("description", _) => {
+                    return Err(format!("{} has to be a string", k))
+                }
+                ("stars", serde_json::Value::Null) => p.stars = Some(None),
+                ("stars", v) => {
+                    let stars = v
+                        .as_i64()
+                        .and_then(|s| i32::try_from(s).ok())
+                        .ok_or_else(|| "stars has to be a whole number".to_string())?;
+                    p.stars = Some(Some(stars));
+                }
+                ("id", _)
+                | ("photos", _)
+                | ("renditions", _)
+                | ("owner", _)
+                | ("version", _)
+                | ("deleted_at", _)
+                | ("purge_at", _) => return Err(format!("{} can't be changed with a patch", k)),
+                (other, _) => return Err(format!("unknown field '{}'", other)),
+            }
+        }
+        Ok(p)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == MealPatch::default()
+    }
+
+    pub fn apply(&self, meal: &mut Meal) {
+        if let Some(n) = &self.name {
+            meal.name = n.clone();
+        }
+        if let Some(d) = &self.description {
+            meal.description = d.clone();
+        }
+        if let Some(s) = self.stars {
+            meal.stars = s;
+        }
+    }
+}
+
+// cursors are opaque to clients but they're only the last meal id we looked at
+pub fn encode_cursor(id: Uuid) -> String {
+    base64::encode_config(id.to_string(), base64::URL_SAFE_NO_PAD)
+}
+
+pub fn decode_cursor(cursor: &str) -> Result<Uuid, String> {
+    base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|b| String::from_utf8(b).ok())
+        .and_then(|s| Uuid::parse_str(&s).ok())
+        .ok_or_else(|| "cursor isn't one we handed out".to_string())
+}
+
+#[async_trait]
+pub trait MealStore: Send + Sync {
+    async fn get(&self, id: Uuid) -> Result<Option<Meal>, StoreError>;
+    // everything, however many pages that takes
+    async fn list(&self) -> Result<Vec<Meal>, StoreError>;
+    async fn query(&self, q: &MealQuery) -> Result<Page<Meal>, StoreError>;
+    async fn put(&self, meal: Meal) -> Result<(), StoreError>;
+    // Put only if the stored meal is still at the expected version, StoreError::Conflict
+    // if not. A meal that isn't there yet counts as version 0.
+    async fn put_if_version(&self, meal: Meal, expected: u64) -> Result<(), StoreError>;
+    // only touches the patched fields, if the meal's still at that version. Bumps the version
+    // and gives back the whole meal as it is now.
+    async fn patch(&self, id: Uuid, patch: &MealPatch, expected: u64) -> Result<Meal, StoreError>;
+    async fn delete(&self, id: Uuid) -> Result<(), StoreError>;
+    // delete only if it's still at the expected version, same as put_if_version
+    async fn delete_if_version(&self, id: Uuid, expected: u64) -> Result<(), StoreError>;
+}
+
+pub struct DynamoMealStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoMealStore {
+    pub fn new(client: DynamoClient, table_name: &str) -> DynamoMealStore {
+        DynamoMealStore {
+            client,
+            table_name: table_name.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl MealStore for DynamoMealStore {
+    async fn get(&self, id: Uuid) -> Result<Option<Meal>, StoreError> {
+        let m = Meal {
+            id,
+            ..Default::default()
+        };
+        let item = self
+            .client
+            .get_item(GetItemInput {
+                table_name: self.table_name.clone(),
+                key: m.key(),
+                ..GetItemInput::default()
+            })
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+        match item.item {
+            Some(attrs) => Meal::from_attrs(attrs)
+                .map(Some)
+                .map_err(|e| StoreError::Malformed(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<Meal>, StoreError> {
+        let mut meals = Vec::new();
+        let mut start_key = None;
+        // a scan stops at 1MB, keep going until there's nothing left
+        loop {
+            let scanned = self
+                .client
+                .scan(ScanInput {
+                    table_name: self.table_name.clone(),
+                    exclusive_start_key: start_key,
+                    ..ScanInput::default()
+                })
+                .await
+                .map_err(|e| StoreError::Backend(e.to_string()))?;
+            for attrs in scanned.items.unwrap_or_default() {
+                meals.push(
+                    Meal::from_attrs(attrs).map_err(|e| StoreError::Malformed(e.to_string()))?,
+                );
+            }
+            start_key = scanned.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(meals);
+            }
+        }
+    }
+
+    async fn query(&self, q: &MealQuery) -> Result<Page<Meal>, StoreError> {
+        // nothing from the trash. Meals that were never deleted don't have the attribute,
+        // restored ones have it as NULL.
+        let mut filters =
+            vec!["(attribute_not_exists(#deleted_at) OR attribute_type(#deleted_at, :null))"];
+        let mut names = HashMap::new();
+        names.insert("#deleted_at".to_string(), "deleted_at".to_string());
+        let mut values = HashMap::new();
+        values.insert(":null".to_string(), "NULL".to_string().into_attr());
+        if let Some(min) = q.min_stars {
+            filters.push("#stars >= :min_stars");
+            names.insert("#stars".to_string(), "stars".to_string());
+            values.insert(":min_stars".to_string(), min.into_attr());
+        }
+        if let Some(name) = &q.name {
+            filters.push("contains(#name, :name)");
+            names.insert("#name".to_string(), "mealName".to_string());
+            values.insert(":name".to_string(), name.clone().into_attr());
+        }
+
+        let mut items = Vec::new();
+        let mut start_key = q.start_after.map(|id| {
+            Meal {
+                id,
+                ..Default::default()
+            }
+            .key()
+        });
+        // Limit is how many items get looked at, before the filter. Only ever ask to look
+        // at as many as we still need so the last evaluated key is a good place to resume.
+        loop {
+            let scanned = self
+                .client
+                .scan(ScanInput {
+                    table_name: self.table_name.clone(),
+                    limit: Some((q.limit - items.len()) as i64),
+                    exclusive_start_key: start_key,
+                    filter_expression: Some(filters.join(" AND ")),
+                    expression_attribute_names: Some(names.clone()),
+                    expression_attribute_values: Some(values.clone()),
+                    ..ScanInput::default()
+                })
+                .await
+                .map_err(|e| StoreError::Backend(e.to_string()))?;
+            for attrs in scanned.items.unwrap_or_default() {
+                items.push(
+                    Meal::from_attrs(attrs).map_err(|e| StoreError::Malformed(e.to_string()))?,
+                );
+            }
+            start_key = scanned.last_evaluated_key;
+            if start_key.is_none() || items.len() >= q.limit {
+                break;
+            }
+        }
+
+        let next_cursor = match start_key {
+            Some(key) => Some(encode_cursor(id_from_key(&key)?)),
+            None => None,
+        };
+        Ok(Page { items, next_cursor })
+    }
+
+    async fn put(&self, meal: Meal) -> Result<(), StoreError> {
+        self.client
+            .put_item(PutItemInput {
+                table_name: self.table_name.clone(),
+                item: meal.into(),
+                ..PutItemInput::default()
+            })
+            .await
+            .map(|_| ())
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+
+    async fn put_if_version(&self, meal: Meal, expected: u64) -> Result<(), StoreError> {
+        let (condition, names, values) = at_version(expected);
+        let put = self
+            .client
+            .put_item(PutItemInput {
+                table_name: self.table_name.clone(),
+                item: meal.into(),
+                condition_expression: Some(condition),
+                expression_attribute_names: Some(names),
+                expression_attribute_values: Some(values),
+                ..PutItemInput::default()
+            })
+            .await;
+        match put {
+            Ok(_) => Ok(()),
+            Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => {
+                Err(StoreError::Conflict)
+            }
+            Err(e) => Err(StoreError::Backend(e.to_string())),
+        }
+    }
+
+    async fn patch(&self, id: Uuid, patch: &MealPatch, expected: u64) -> Result<Meal, StoreError> {
+        let mut names = HashMap::new();
+        names.insert("#version".to_string(), "version".to_string());
+        let mut values = HashMap::new();
+        values.insert(":expected".to_string(), expected.into_attr());
+        values.insert(":one".to_string(), 1u64.into_attr());
+        values.insert(":zero".to_string(), 0u64.into_attr());
+        let mut sets = vec!["#version = if_not_exists(#version, :zero) + :one"];
+        if let Some(n) = &patch.name {
+            sets.push("#name = :name");
+            names.insert("#name".to_string(), "mealName".to_string());
+            values.insert(":name".to_string(), n.clone().into_attr());
+        }
+        if let Some(d) = &patch.description {
+            sets.push("#description = :description");
+            names.insert("#description".to_string(), "description".to_string());
+            values.insert(":description".to_string(), d.clone().into_attr());
+        }
+        // cleared stars are stored as NULL like any other None, not removed
+        if let Some(s) = patch.stars {
+            sets.push("#stars = :stars");
+            names.insert("#stars".to_string(), "stars".to_string());
+            values.insert(":stars".to_string(), s.into_attr());
+        }
+        // same as put_if_version, and it has to be there already
+        let condition = if expected == 0 {
+            "attribute_exists(id) AND (attribute_not_exists(#version) OR #version = :expected)"
+        } else {
+            "attribute_exists(id) AND #version = :expected"
+        };
+        let key = Meal {
+            id,
+            ..Default::default()
+        }
+        .key();
+        let updated = self
+            .client
+            .update_item(UpdateItemInput {
+                table_name: self.table_name.clone(),
+                key,
+                update_expression: Some(format!("SET {}", sets.join(", "))),
+                condition_expression: Some(condition.to_string()),
+                expression_attribute_names: Some(names),
+                expression_attribute_values: Some(values),
+                return_values: Some("ALL_NEW".to_string()),
+                ..UpdateItemInput::default()
+            })
+            .await;
+        match updated {
+            Ok(out) => Meal::from_attrs(out.attributes.unwrap_or_default())
+                .map_err(|e| StoreError::Malformed(e.to_string())),
+            Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {
+                Err(StoreError::Conflict)
+            }
+            Err(e) => Err(StoreError::Backend(e.to_string())),
+        }
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), StoreError> {
+        let m = Meal {
+            id,
+            ..Default::default()
+        };
+        self.client
+            .delete_item(DeleteItemInput {
+                table_name: self.table_name.clone(),
+                key: m.key(),
+                ..DeleteItemInput::default()
+            })
+            .await
+            .map(|_| ())
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+
+    async fn delete_if_version(&self, id: Uuid, expected: u64) -> Result<(), StoreError> {
+        let m = Meal {
+            id,
+            ..Default::default()
+        };
+        let (condition, names, values) = at_version(expected);
+        let deleted = self
+            .client
+            .delete_item(DeleteItemInput {
+                table_name: self.table_name.clone(),
+                key: m.key(),
+                condition_expression: Some(condition),
+                expression_attribute_names: Some(names),
+                expression_attribute_values: Some(values),
+                ..DeleteItemInput::default()
+            })
+            .await;
+        match deleted {
+            Ok(_) => Ok(()),
+            Err(RusotoError::Service(DeleteItemError::ConditionalCheckFailed(_))) => {
+                Err(StoreError::Conflict)
+            }
+            Err(e) => Err(StoreError::Backend(e.to_string())),
+        }
+    }
+}
+
+// The condition for put_if_version and delete_if_version, with its names and values. New
+// meals and ones from before versions don't have the attribute at all.
+fn at_version(
+    expected: u64,
+) -> (
+    String,
+    HashMap<String, String>,
+    HashMap<String, AttributeValue>,
+) {
+    let mut names = HashMap::new();
+    names.insert("#version".to_string(), "version".to_string());
+    let mut values = HashMap::new();
+    values.insert(":expected".to_string(), expected.into_attr());
+    let condition = if expected == 0 {
+        "attribute_not_exists(#version) OR #version = :expected"
+    } else {
+        "#version = :expected"
+    };
+    (condition.to_string(), names, values)
+}
+
+fn id_from_key(key: &HashMap<String, AttributeValue>) -> Result<Uuid, StoreError> {
+    key.get("id")
+        .and_then(|v| v.s.as_ref())
+        .and_then(|s| Uuid::parse_str(s).ok())
+        .ok_or_else(|| StoreError::Malformed(format!("unexpected scan key {:?}", key)))
+}
+
+// keeps everything in a HashMap: handy for local runs and tests, gone on restart
+#[derive(Default)]
+pub struct InMemoryMealStore {
+    meals: Mutex<HashMap<Uuid, Meal>>,
+}
+
+impl InMemoryMealStore {
+    pub fn new() -> InMemoryMealStore {
+        InMemoryMealStore::default()
+    }
+}
+
+#[async_trait]
+impl MealStore for InMemoryMealStore {
+    async fn get(&self, id: Uuid) -> Result<Option<Meal>, StoreError> {
+        Ok(self.meals.lock().await.get(&id).cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<Meal>, StoreError> {
+        Ok(self.meals.lock().await.values().cloned().collect())
+    }
+
+    async fn query(&self, q: &MealQuery) -> Result<Page<Meal>, StoreError> {
+        let mut meals: Vec<Meal> = self.meals.lock().await.values().cloned().collect();
+        // id order so cursors mean something
+        meals.sort_by_key(|m| m.id);
+        let mut matching = meals
+            .into_iter()
+            .filter(|m| q.start_after.is_none_or(|after| m.id > after))
+            .filter(|m| q.matches(m));
+        let items: Vec<Meal> = matching.by_ref().take(q.limit).collect();
+        let next_cursor = match (matching.next(), items.last()) {
+            (Some(_), Some(last)) => Some(encode_cursor(last.id)),
+            _ => None,
+        };
+        Ok(Page { items, next_cursor })
+    }
+
+    async fn put(&self, meal: Meal) -> Result<(), StoreError> {
+        self.meals.lock().await.insert(meal.id, meal);
+        Ok(())
+    }
+
+    async fn put_if_version(&self, meal: Meal, expected: u64) -> Result<(), StoreError> {
+        let mut meals = self.meals.lock().await;
+        let current = meals.get(&meal.id).map(|m| m.version).unwrap_or(0);
+        if current != expected {
+            return Err(StoreError::Conflict);
+        }
+        meals.insert(meal.id, meal);
+        Ok(())
+    }
+
+    async fn patch(&self, id: Uuid, patch: &MealPatch, expected: u64) -> Result<Meal, StoreError> {
+        let mut meals = self.meals.lock().await;
+        let meal = match meals.get_mut(&id) {
+            Some(m) if m.version == expected => m,
+            _ => return Err(StoreError::Conflict),
+        };
+        patch.apply(meal);
+        meal.version += 1;
+        Ok(meal.clone())
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), StoreError> {
+        // DynamoDB doesn't complain about deleting something that isn't there, match that
+        self.meals.lock().await.remove(&id);
+        Ok(())
+    }
+
+    async fn delete_if_version(&self, id: Uuid, expected: u64) -> Result<(), StoreError> {
+        let mut meals = self.meals.lock().await;
+        let current = meals.get(&id).map(|m| m.version).unwrap_or(0);
+        if current != expected {
+            return Err(StoreError::Conflict);
+        }
+        meals.remove(&id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_memory_round_trip() {
+        let store = InMemoryMealStore::new();
+        let m = Meal {
+            id: Uuid::new_v4(),
+            name: "Tacos".to_string(),
+            description: "Crunchy".to_string(),
+            ..Default::default()
+        };
+
+        store.put(m.clone()).await.unwrap();
+        assert_eq!(store.get(m.id).await.unwrap(), Some(m.clone()));
+        assert_eq!(store.list().await.unwrap(), vec![m.clone()]);
+
+        store.delete(m.id).await.unwrap();
+        assert_eq!(store.get(m.id).await.unwrap(), None);
+        assert!(store.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_put_if_version() {
+        let store = InMemoryMealStore::new();
+        let m = Meal {
+            id: Uuid::new_v4(),
+            name: "Tacos".to_string(),
+            version: 1,
+            ..Default::default()
+        };
+        // not there yet is version 0
+        assert!(matches!(
+            store.put_if_version(m.clone(), 1).await,
+            Err(StoreError::Conflict)
+        ));
+        store.put_if_version(m.clone(), 0).await.unwrap();
+
+        let newer = Meal {
+            version: 2,
+            ..m.clone()
+        };
+        store.put_if_version(newer.clone(), 1).await.unwrap();
+        // the other tab still thinks it's at 1
+        assert!(matches!(
+            store.put_if_version(newer.clone(), 1).await,
+            Err(StoreError::Conflict)
+        ));
+        assert_eq!(store.get(m.id).await.unwrap(), Some(newer.clone()));
+
+        // same goes for deleting it
+        assert!(matches!(
+            store.delete_if_version(m.id, 1).await,
+            Err(StoreError::Conflict)
+        ));
+        store.delete_if_version(m.id, 2).await.unwrap();
+        assert_eq!(store.get(m.id).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_old_meals_still_load() {
+        // what a meal looked like in the table before renditions and owners
+        let mut attrs: dynomite::Attributes = Meal {
+            id: Uuid::new_v4(),
+            name: "Tacos".to_string(),
+            ..Default::default()
+        }
+        .into();
+        attrs.remove("renditions");
+        attrs.remove("owner");
+        attrs.remove("version");
+        attrs.remove("deleted_at");
+        attrs.remove("purge_at");
+        let m = Meal::from_attrs(attrs).unwrap();
+        assert_eq!(m.renditions, None);
+        assert_eq!(m.owner, None);
+        assert_eq!(m.version, 0);
+        assert!(!m.in_trash());
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_pages_and_filters() {
+        let store = InMemoryMealStore::new();
+        for i in 0..5 {
+            store
+                .put(Meal {
+                    id: Uuid::new_v4(),
+                    name: format!("Meal {}", i),
+                    stars: Some(i),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+        // the trash never shows up
+        store
+            .put(Meal {
+                id: Uuid::new_v4(),
+                name: "Meal 1, deleted".to_string(),
+                stars: Some(5),
+                deleted_at: Some(1),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let q = MealQuery {
+            limit: 2,
+            ..MealQuery::default()
+        };
+        let mut seen = Vec::new();
+        let mut page = store.query(&q).await.unwrap();
+        loop {
+            assert!(page.items.len() <= 2);
+            seen.extend(page.items.iter().map(|m| m.id));
+            match page.next_cursor {
+                Some(c) => {
+                    let next = MealQuery {
+                        start_after: Some(decode_cursor(&c).unwrap()),
+                        ..q.clone()
+                    };
+                    page = store.query(&next).await.unwrap();
+                }
+                None => break,
+            }
+        }
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 5);
+
+        let q = MealQuery {
+            min_stars: Some(3),
+            ..MealQuery::default()
+        };
+        assert_eq!(store.query(&q).await.unwrap().items.len(), 2);
+        let q = MealQuery {
+            name: Some("Meal 1".to_string()),
+            ..MealQuery::default()
+        };
+        let page = store.query(&q).await.unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[test]
+    fn test_merge_patches() {
+        let patch = |j: serde_json::Value| MealPatch::from_json(&j);
+        assert_eq!(
+            patch(serde_json::json!({"description": "better", "stars": null})),
+            Ok(MealPatch {
+                description: Some("better".to_string()),
+                stars: Some(None),
+                ..MealPatch::default()
+            })
+        );
+        assert_eq!(
+            patch(serde_json::json!({"stars": 4})).unwrap().stars,
+            Some(Some(4))
+        );
+        assert!(patch(serde_json::json!({})).unwrap().is_empty());
+        for bad in &[
+            serde_json::json!([]),
+            serde_json::json!({"name": null}),
+            serde_json::json!({"stars": "lots"}),
+            serde_json::json!({"stars": 4.5}),
+            serde_json::json!({"owner": null}),
+            serde_json::json!({"colour": "red"}),
+        ] {
+            assert!(patch(bad.clone()).is_err(), "{} got through", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_patch() {
+        let store = InMemoryMealStore::new();
+        let m = Meal {
+            id: Uuid::new_v4(),
+            name: "Tacos".to_string(),
+            description: "Crunchy".to_string(),
+            stars: Some(3),
+            version: 2,
+            ..Default::default()
+        };
+        store.put(m.clone()).await.unwrap();
+        let p = MealPatch {
+            stars: Some(None),
+            ..MealPatch::default()
+        };
+        let patched = store.patch(m.id, &p, 2).await.unwrap();
+        assert_eq!(
+            patched,
+            Meal {
+                stars: None,
+                version: 3,
+                ..m.clone()
+            }
+        );
+        assert!(matches!(
+            store.patch(m.id, &p, 2).await,
+            Err(StoreError::Conflict)
+        ));
+        assert!(matches!(
+            store.patch(Uuid::new_v4(), &p, 0).await,
+            Err(StoreError::Conflict)
+        ));
+    }
+
+    #[test]
+    fn test_query_params() {
+        let params = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<String, String>>()
+        };
+        assert_eq!(
+            MealQuery::from_params(&params(&[])).unwrap(),
+            MealQuery::default()
+        );
+
+        let id = Uuid::new_v4();
+        let q = MealQuery::from_params(&params(&[
+            ("limit", "10"),
+            ("cursor", &encode_cursor(id)),
+            ("min_stars", "3"),
+            ("name", "Burr"),
+        ]))
+        .unwrap();
+        assert_eq!(q.limit, 10);
+        assert_eq!(q.start_after, Some(id));
+        assert_eq!(q.min_stars, Some(3));
+        assert_eq!(q.name, Some("Burr".to_string()));
+
+        for bad in &[
+            ("limit", "0"),
+            ("limit", "101"),
+            ("cursor", "nope"),
+            ("min_stars", "lots"),
+            ("colour", "red"),
+        ] {
+            assert!(MealQuery::from_params(&params(&[*bad])).is_err());
+        }
+    }
+}
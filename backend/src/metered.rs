@@ -0,0 +1,243 @@
+use crate::metrics::metrics;
+use async_trait::async_trait;
+use dynomite::dynamodb::*;
+use rusoto_core::{
+    request::{DispatchSignedRequestFuture, HttpClient},
+    signature::SignedRequest,
+    DispatchSignedRequest, RusotoError,
+};
+use std::{cell::Cell, future::Future, time::Duration, time::Instant};
+
+// How many requests the DynamoDB call being metered has sent, so retries can be told apart
+// from calls. The dispatcher counts them, it's the only thing that sees every attempt the
+// RetryingDynamoDb underneath makes.
+tokio::task_local! {
+    static ATTEMPTS: Cell<u32>;
+}
+
+// Times and counts every DynamoDB call, outside the retries so a call that took three goes
+// is one slow call with two retries.
+#[derive(Clone)]
+pub struct MeteredDynamoDb<D> {
+    inner: D,
+}
+
+impl<D> MeteredDynamoDb<D> {
+    pub fn new(inner: D) -> MeteredDynamoDb<D> {
+        MeteredDynamoDb { inner }
+    }
+}
+
+async fn meter<T, E>(op: &str, call: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+    let started = Instant::now();
+    let (result, attempts) = ATTEMPTS
+        .scope(Cell::new(0), async {
+            let result = call.await;
+            (result, ATTEMPTS.with(|a| a.get()))
+        })
+        .await;
+    metrics().dynamodb_call(op, result.is_ok(), started.elapsed(), attempts);
+    result
+}
+
+macro_rules! metered {
+    ($($op:ident($input:ty) -> $output:ty, $error:ty;)*) => {
+        #[async_trait]
+        impl<D> DynamoDb for MeteredDynamoDb<D>
+        where
+            D: DynamoDb + Sync + Send + 'static,
+        {
+            $(
+                async fn $op(&self, input: $input) -> Result<$output, RusotoError<$error>> {
+                    meter(stringify!($op), self.inner.$op(input)).await
+                }
+            )*
+
+            async fn describe_endpoints(
+                &self,
+            ) -> Result<DescribeEndpointsResponse, RusotoError<DescribeEndpointsError>> {
+                meter("describe_endpoints", self.inner.describe_endpoints()).await
+            }
+
+            async fn describe_limits(
+                &self,
+            ) -> Result<DescribeLimitsOutput, RusotoError<DescribeLimitsError>> {
+                meter("describe_limits", self.inner.describe_limits()).await
+            }
+        }
+    };
+}
+
+metered! {
+    batch_get_item(BatchGetItemInput) -> BatchGetItemOutput, BatchGetItemError;
+    batch_write_item(BatchWriteItemInput) -> BatchWriteItemOutput, BatchWriteItemError;
+    create_backup(CreateBackupInput) -> CreateBackupOutput, CreateBackupError;
+    create_global_table(CreateGlobalTableInput) -> CreateGlobalTableOutput, CreateGlobalTableError;
+    create_table(CreateTableInput) -> CreateTableOutput, CreateTableError;
+    delete_backup(DeleteBackupInput) -> DeleteBackupOutput, DeleteBackupError;
+    delete_item(DeleteItemInput) -> DeleteItemOutput, DeleteItemError;
+    delete_table(DeleteTableInput) -> DeleteTableOutput, DeleteTableError;
+    describe_backup(DescribeBackupInput) -> DescribeBackupOutput, DescribeBackupError;
+    describe_continuous_backups(DescribeContinuousBackupsInput) -> DescribeContinuousBackupsOutput, DescribeContinuousBackupsError;
+    describe_contributor_insights(DescribeContributorInsightsInput) -> DescribeContributorInsightsOutput, DescribeContributorInsightsError;
+    describe_global_table(DescribeGlobalTableInput) -> DescribeGlobalTableOutput, DescribeGlobalTableError;
+    describe_global_table_settings(DescribeGlobalTableSettingsInput) -> DescribeGlobalTableSettingsOutput, DescribeGlobalTableSettingsError;
+    describe_table(DescribeTableInput) -> DescribeTableOutput, DescribeTableError;
+    describe_table_replica_auto_scaling(DescribeTableReplicaAutoScalingInput) -> DescribeTableReplicaAutoScalingOutput, DescribeTableReplicaAutoScalingError;
+    describe_time_to_live(DescribeTimeToLiveInput) -> DescribeTimeToLiveOutput, DescribeTimeToLiveError;
+    get_item(GetItemInput) -> GetItemOutput, GetItemError;
+    list_backups(ListBackupsInput) -> ListBackupsOutput, ListBackupsError;
+    list_contributor_insights(ListContributorInsightsInput) -> ListContributorInsightsOutput, ListContributorInsightsError;
+    list_global_tables(ListGlobalTablesInput) -> ListGlobalTablesOutput, ListGlobalTablesError;
+    list_tables(ListTablesInput) -> ListTablesOutput, ListTablesError;
+    list_tags_of_resource(ListTagsOfResourceInput) -> ListTagsOfResourceOutput, ListTagsOfResourceError;
+    put_item(PutItemInput) -> PutItemOutput, PutItemError;
+    query(QueryInput) -> QueryOutput, QueryError;
+    restore_table_from_backup(RestoreTableFromBackupInput) -> RestoreTableFromBackupOutput, RestoreTableFromBackupError;
+    restore_table_to_point_in_time(RestoreTableToPointInTimeInput) -> RestoreTableToPointInTimeOutput, RestoreTableToPointInTimeError;
+    scan(ScanInput) -> ScanOutput, ScanError;
+    tag_resource(TagResourceInput) -> (), TagResourceError;
+    transact_get_items(TransactGetItemsInput) -> TransactGetItemsOutput, TransactGetItemsError;
+    transact_write_items(TransactWriteItemsInput) -> TransactWriteItemsOutput, TransactWriteItemsError;
+    untag_resource(UntagResourceInput) -> (), UntagResourceError;
+    update_continuous_backups(UpdateContinuousBackupsInput) -> UpdateContinuousBackupsOutput, UpdateContinuousBackupsError;
+    update_contributor_insights(UpdateContributorInsightsInput) -> UpdateContributorInsightsOutput, UpdateContributorInsightsError;
+    update_global_table(UpdateGlobalTableInput) -> UpdateGlobalTableOutput, UpdateGlobalTableError;
+    update_global_table_settings(UpdateGlobalTableSettingsInput) -> UpdateGlobalTableSettingsOutput, UpdateGlobalTableSettingsError;
+    update_item(UpdateItemInput) -> UpdateItemOutput, UpdateItemError;
+    update_table(UpdateTableInput) -> UpdateTableOutput, UpdateTableError;
+    update_table_replica_auto_scaling(UpdateTableReplicaAutoScalingInput) -> UpdateTableReplicaAutoScalingOutput, UpdateTableReplicaAutoScalingError;
+    update_time_to_live(UpdateTimeToLiveInput) -> UpdateTimeToLiveOutput, UpdateTimeToLiveError;
+}
+
+// Sits between the rusoto clients and the network. Counts DynamoDB attempts for the call
+// being metered and every S3 request, S3 has no retries of its own to untangle.
+#[derive(Clone)]
+pub struct MeteredDispatcher<D = HttpClient> {
+    inner: D,
+}
+
+impl<D> MeteredDispatcher<D> {
+    pub fn new(inner: D) -> MeteredDispatcher<D> {
+        MeteredDispatcher { inner }
+    }
+}
+
+impl<D: DispatchSignedRequest> DispatchSignedRequest for MeteredDispatcher<D> {
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        // not in a metered call when it's the S3 client, that's fine
+        let _ = ATTEMPTS.try_with(|a| a.set(a.get() + 1));
+        if request.service != "s3" {
+            return self.inner.dispatch(request, timeout);
+        }
+        let op = s3_op(
+            &request.method,
+            &request.path,
+            request.params.contains_key("delete"),
+        );
+        let sent = self.inner.dispatch(request, timeout);
+        Box::pin(async move {
+            let resp = sent.await;
+            metrics().s3_request(op, resp.as_ref().ok().map(|r| r.status.as_u16()));
+            resp
+        })
+    }
+}
+
+// Requests are path style, / for the account, /bucket for a bucket and /bucket/key for its
+// objects, which is enough to name everything the backend and rr-backup do.
+fn s3_op(method: &str, path: &str, delete: bool) -> &'static str {
+    let mut parts = path.trim_start_matches('/').splitn(2, '/');
+    let bucket = parts.next().is_some_and(|b| !b.is_empty());
+    let object = parts.next().is_some_and(|k| !k.is_empty());
+    match (method, bucket, object) {
+        ("GET", false, _) => "list_buckets",
+        ("PUT", true, false) => "create_bucket",
+        ("PUT", true, true) => "put_object",
+        ("GET", true, false) => "list_objects",
+        ("GET", true, true) => "get_object",
+        ("HEAD", true, false) => "head_bucket",
+        ("HEAD", true, true) => "head_object",
+        ("DELETE", true, false) => "delete_bucket",
+        ("DELETE", true, true) => "delete_object",
+        ("POST", true, false) if delete => "delete_objects",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dynomite::{retry::Policy, Retries};
+    use rusoto_core::{credential::StaticProvider, request::HttpResponse, ByteStream, Region};
+    use std::sync::{Arc, Mutex};
+    use warp::http::{HeaderMap, StatusCode};
+
+    // answers with whatever's next in the queue
+    #[derive(Clone)]
+    struct Canned(Arc<Mutex<Vec<(u16, &'static str)>>>);
+
+    impl DispatchSignedRequest for Canned {
+        fn dispatch(&self, _: SignedRequest, _: Option<Duration>) -> DispatchSignedRequestFuture {
+            let (status, body) = self.0.lock().unwrap().remove(0);
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status: StatusCode::from_u16(status).unwrap(),
+                    body: ByteStream::from(body.as_bytes().to_vec()),
+                    headers: HeaderMap::default(),
+                })
+            })
+        }
+    }
+
+    #[test]
+    fn test_s3_op() {
+        assert_eq!(s3_op("GET", "/", false), "list_buckets");
+        assert_eq!(s3_op("PUT", "/photos", false), "create_bucket");
+        assert_eq!(s3_op("PUT", "/photos/abc/1.jpg", false), "put_object");
+        assert_eq!(s3_op("GET", "/photos/", false), "list_objects");
+        assert_eq!(s3_op("GET", "/photos/abc/1.jpg", false), "get_object");
+        assert_eq!(s3_op("HEAD", "/photos", false), "head_bucket");
+        assert_eq!(s3_op("DELETE", "/photos/abc/1.jpg", false), "delete_object");
+        assert_eq!(s3_op("POST", "/photos", true), "delete_objects");
+        assert_eq!(s3_op("POST", "/photos", false), "other");
+    }
+
+    #[tokio::test]
+    async fn test_retries_counted() {
+        let canned = Canned(Arc::new(Mutex::new(vec![
+            (
+                400,
+                r#"{"__type":"com.amazonaws.dynamodb.v20120810#ProvisionedThroughputExceededException","message":"slow down"}"#,
+            ),
+            (200, "{}"),
+        ])));
+        let client = MeteredDynamoDb::new(
+            DynamoDbClient::new_with(
+                MeteredDispatcher::new(canned.clone()),
+                StaticProvider::new_minimal("key".to_string(), "secret".to_string()),
+                Region::Custom {
+                    name: "local".to_string(),
+                    endpoint: "http://localhost:1".to_string(),
+                },
+            )
+            .with_retries(Policy::Pause(3, Duration::from_millis(1))),
+        );
+        let resp = client
+            .get_item(GetItemInput {
+                table_name: "meals".to_string(),
+                ..GetItemInput::default()
+            })
+            .await;
+        assert!(resp.is_ok());
+        assert!(canned.0.lock().unwrap().is_empty());
+        // nothing else in the tests gets items
+        let out = metrics().render();
+        assert!(out.contains(r#"rr_dynamodb_retries_total{op="get_item"} 1"#));
+        assert!(out.contains(r#"rr_dynamodb_calls_total{op="get_item",outcome="ok"} 1"#));
+    }
+}
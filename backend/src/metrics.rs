@@ -0,0 +1,204 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use serde_json::Value;
+use std::time::Duration;
+
+// Everything GET /metrics reports. There's one per process so the DynamoDB and S3 clients
+// can count into it from wherever they were made, see metered.rs.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    dynamodb_calls: IntCounterVec,
+    dynamodb_duration: HistogramVec,
+    dynamodb_retries: IntCounterVec,
+    s3_requests: IntCounterVec,
+    logins: IntCounterVec,
+}
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoginOutcome {
+    Success,
+    Failure,
+    // turned away without checking the password, see login_throttle
+    Throttled,
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new();
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            let c = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+            registry.register(Box::new(c.clone())).unwrap();
+            c
+        };
+        let histogram = |name: &str, help: &str, labels: &[&str]| {
+            let h = HistogramVec::new(HistogramOpts::new(name, help), labels).unwrap();
+            registry.register(Box::new(h.clone())).unwrap();
+            h
+        };
+        Metrics {
+            http_requests: counter(
+                "rr_http_requests_total",
+                "Requests answered, by route and status class",
+                &["method", "route", "status"],
+            ),
+            http_duration: histogram(
+                "rr_http_request_duration_seconds",
+                "How long requests took to answer",
+                &["method", "route"],
+            ),
+            dynamodb_calls: counter(
+                "rr_dynamodb_calls_total",
+                "DynamoDB calls, after any retries",
+                &["op", "outcome"],
+            ),
+            dynamodb_duration: histogram(
+                "rr_dynamodb_call_duration_seconds",
+                "How long DynamoDB calls took, retries included",
+                &["op"],
+            ),
+            dynamodb_retries: counter(
+                "rr_dynamodb_retries_total",
+                "DynamoDB requests that were another go at a throttled or failed one",
+                &["op"],
+            ),
+            s3_requests: counter(
+                "rr_s3_requests_total",
+                "S3 requests, by status class or error when there was no response",
+                &["op", "outcome"],
+            ),
+            logins: counter("rr_logins_total", "POST /login attempts", &["outcome"]),
+            registry,
+        }
+    }
+
+    pub fn http_request(&self, method: &str, route: &str, status: u16, took: Duration) {
+        self.http_requests
+            .with_label_values(&[method, route, &status_class(status)])
+            .inc();
+        self.http_duration
+            .with_label_values(&[method, route])
+            .observe(took.as_secs_f64());
+    }
+
+    pub fn dynamodb_call(&self, op: &str, ok: bool, took: Duration, attempts: u32) {
+        let outcome = if ok { "ok" } else { "error" };
+        self.dynamodb_calls.with_label_values(&[op, outcome]).inc();
+        self.dynamodb_duration
+            .with_label_values(&[op])
+            .observe(took.as_secs_f64());
+        if attempts > 1 {
+            self.dynamodb_retries
+                .with_label_values(&[op])
+                .inc_by(u64::from(attempts - 1));
+        }
+    }
+
+    // None when it never got a response
+    pub fn s3_request(&self, op: &str, status: Option<u16>) {
+        let outcome = status
+            .map(status_class)
+            .unwrap_or_else(|| "error".to_string());
+        self.s3_requests.with_label_values(&[op, &outcome]).inc();
+    }
+
+    pub fn login(&self, outcome: LoginOutcome) {
+        let outcome = match outcome {
+            LoginOutcome::Success => "success",
+            LoginOutcome::Failure => "failure",
+            LoginOutcome::Throttled => "throttled",
+        };
+        self.logins.with_label_values(&[outcome]).inc();
+    }
+
+    // the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut out) {
+            warn!("couldn't encode the metrics: {}", e);
+        }
+        String::from_utf8(out).unwrap_or_default()
+    }
+}
+
+fn status_class(status: u16) -> String {
+    format!("{}xx", status / 100)
+}
+
+// Turns request paths back into the routes they matched, eg /meals/f11b...b881 into
+// /meals/{id}, so each meal doesn't get its own series. The routes come from the OpenAPI
+// document, which has every one of them.
+#[derive(Debug, Clone)]
+pub struct Routes(Vec<String>);
+
+impl Routes {
+    pub fn from_openapi(doc: &Value) -> Routes {
+        let paths = doc["paths"]
+            .as_object()
+            .map(|p| p.keys().cloned().collect())
+            .unwrap_or_default();
+        Routes(paths)
+    }
+
+    // "unmatched" for the 404s, there's no telling how many different ones there'll be
+    pub fn route(&self, path: &str) -> &str {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        self.0
+            .iter()
+            .find(|r| {
+                let route: Vec<&str> = r.trim_matches('/').split('/').collect();
+                route.len() == segments.len()
+                    && route
+                        .iter()
+                        .zip(&segments)
+                        .all(|(r, s)| r == s || (r.starts_with('{') && !s.is_empty()))
+            })
+            .map(|r| r.as_str())
+            .unwrap_or("unmatched")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routes() {
+        let routes = Routes::from_openapi(&crate::openapi::document("test"));
+        assert_eq!(routes.route("/meals"), "/meals");
+        assert_eq!(
+            routes.route("/meals/f11b1c5e-d6d8-4dce-8a9d-9e05d870b881"),
+            "/meals/{id}"
+        );
+        assert_eq!(
+            routes.route("/meals/f11b1c5e-d6d8-4dce-8a9d-9e05d870b881/revisions/3/restore"),
+            "/meals/{id}/revisions/{rev}/restore"
+        );
+        assert_eq!(routes.route("/users/me/password"), "/users/me/password");
+        assert_eq!(routes.route("/meals//photos"), "unmatched");
+        assert_eq!(routes.route("/wp-login.php"), "unmatched");
+    }
+
+    #[test]
+    fn test_render() {
+        let m = metrics();
+        m.http_request("GET", "/meals/{id}", 404, Duration::from_millis(3));
+        m.login(LoginOutcome::Throttled);
+        m.dynamodb_call("scan", true, Duration::from_millis(20), 3);
+        let out = m.render();
+        assert!(out
+            .contains(r#"rr_http_requests_total{method="GET",route="/meals/{id}",status="4xx"}"#));
+        assert!(out.contains("rr_http_request_duration_seconds_bucket{"));
+        assert!(out.contains(r#"rr_logins_total{outcome="throttled"}"#));
+        assert!(out.contains(r#"rr_dynamodb_retries_total{op="scan"}"#));
+    }
+}
@@ -0,0 +1,553 @@
+use crate::backend_types::{
+    ErrorResp, Health, Login, LoginResp, NewUser, PasswordChange, Readiness, UserResp,
+};
+use crate::meal_store::MAX_PAGE_SIZE;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+use shared::{ImportReport, Meal, MealDetail, MealRevision, MealSummary, Page, RefeedDay};
+
+// What GET /openapi.json hands out: every route in main.rs as OpenAPI 3, with the bodies'
+// schemas generated from the shared types. A new route has to go in here too,
+// test_every_route_documented fails until it does.
+pub fn document(version: &str) -> Value {
+    let mut d = Doc {
+        gen: SchemaSettings::openapi3().into_generator(),
+        paths: Map::new(),
+    };
+    let meal = d.schema::<Meal>();
+    let day = d.schema::<RefeedDay>();
+    let tokens = d.schema::<LoginResp>();
+    let health = d.schema::<Health>();
+    let readiness = d.schema::<Readiness>();
+    let login = d.schema::<Login>();
+    let page = d.schema::<Page<MealSummary>>();
+    let detail = d.schema::<MealDetail>();
+    let revisions = d.schema::<Vec<MealRevision>>();
+    let meals = d.schema::<Vec<Meal>>();
+    let report = d.schema::<ImportReport>();
+    let days = d.schema::<Vec<RefeedDay>>();
+    let new_user = d.schema::<NewUser>();
+    let user = d.schema::<UserResp>();
+    let password = d.schema::<PasswordChange>();
+    let bulk_file = json!({ "type": "string" });
+
+    d.add(
+        op(
+            "get",
+            "/health/live",
+            "Whether the backend process is up, and which version it is",
+        )
+        .reply(200, "up", health),
+    );
+    d.add(
+        op(
+            "get",
+            "/health/ready",
+            "Whether DynamoDB and S3 answer, with how long each took",
+        )
+        .reply(200, "ready for traffic", readiness.clone())
+        .reply(
+            503,
+            "DynamoDB or S3 failed or didn't answer in time",
+            readiness,
+        ),
+    );
+    d.add(op("get", "/openapi.json", "This document").reply(
+        200,
+        "an OpenAPI 3 document",
+        json!({ "type": "object" }),
+    ));
+    d.add(
+        op(
+            "get",
+            "/metrics",
+            "Request, DynamoDB, S3 and login counters for Prometheus to scrape",
+        )
+        .reply_as(
+            200,
+            "the Prometheus text exposition format",
+            "text/plain",
+            json!({ "type": "string" }),
+        ),
+    );
+
+    d.add(
+        op("post", "/login", "Trade a username and password for tokens")
+            .body("application/json", login)
+            .reply(
+                200,
+                "an access token, the refresh token comes in the rr_refresh cookie",
+                tokens.clone(),
+            ),
+    );
+    d.add(
+        op(
+            "post",
+            "/logout",
+            "Revoke the access token and its refresh token",
+        )
+        .authed()
+        .empty(204),
+    );
+    d.add(
+        op(
+            "post",
+            "/token/refresh",
+            "Trade the rr_refresh cookie in for new tokens",
+        )
+        .reply(
+            200,
+            "an access token, and a new refresh token in the rr_refresh cookie",
+            tokens,
+        ),
+    );
+
+    d.add(
+        op(
+            "get",
+            "/meals",
+            "One page of meals, oldest first, not counting the trash",
+        )
+        .query(
+            "limit",
+            json!({ "type": "integer", "minimum": 1, "maximum": MAX_PAGE_SIZE }),
+            "how many, at most",
+        )
+        .query(
+            "cursor",
+            json!({ "type": "string" }),
+            "the next_cursor from the page before",
+        )
+        .query(
+            "min_stars",
+            json!({ "type": "integer" }),
+            "leave out meals rated lower",
+        )
+        .query(
+            "name",
+            json!({ "type": "string" }),
+            "only meals with this in the name, case sensitive",
+        )
+        .reply(
+            200,
+            "a page of meals, each with its first photo's thumbnail",
+            page,
+        ),
+    );
+    d.add(
+        op("post", "/meals", "Add a meal, the caller owns it")
+            .authed()
+            .body("application/json", meal.clone())
+            .reply(201, "the meal as saved", meal.clone()),
+    );
+    d.add(
+        op("get", "/meals/{id}", "A meal and links to its photos").reply(200, "the meal", detail),
+    );
+    d.add(
+        op(
+            "put",
+            "/meals/{id}",
+            "Replace a meal, send the version it was read at",
+        )
+        .authed()
+        .body("application/json", meal.clone())
+        .reply(202, "the meal as saved", meal.clone())
+        .reply(
+            409,
+            "someone else changed it first, this is the meal as it is now",
+            meal.clone(),
+        ),
+    );
+    d.add(
+        op(
+            "patch",
+            "/meals/{id}",
+            "Change some of a meal's name, description and stars",
+        )
+        .authed()
+        .body("application/merge-patch+json", json!({ "type": "object" }))
+        .reply(200, "the meal as saved", meal.clone()),
+    );
+    d.add(
+        op("delete", "/meals/{id}", "Move a meal to the trash")
+            .authed()
+            .empty(204),
+    );
+    d.add(
+        op(
+            "get",
+            "/meals/{id}/revisions",
+            "Every change to a meal, newest first",
+        )
+        .authed()
+        .reply(200, "the revisions", revisions),
+    );
+    d.add(
+        op(
+            "post",
+            "/meals/{id}/revisions/{rev}/restore",
+            "Put a meal back how it was at a revision",
+        )
+        .authed()
+        .reply(200, "the meal as saved", meal.clone()),
+    );
+    d.add(
+        op(
+            "post",
+            "/meals/{id}/photos",
+            "Add a jpeg, png or webp photo to a meal",
+        )
+        .authed()
+        .body(
+            "multipart/form-data",
+            json!({
+                "type": "object",
+                "properties": { "photo": { "type": "string", "format": "binary" } },
+                "required": ["photo"]
+            }),
+        )
+        .reply(201, "the meal with the photo", meal.clone()),
+    );
+    d.add(
+        op(
+            "delete",
+            "/meals/{id}/photos/{file_name}",
+            "Remove a photo from a meal",
+        )
+        .authed()
+        .empty(204),
+    );
+
+    d.add(
+        op(
+            "get",
+            "/trash",
+            "The caller's trashed meals, everyone's for admins",
+        )
+        .authed()
+        .reply(200, "the trash, most recently deleted first", meals),
+    );
+    d.add(
+        op(
+            "post",
+            "/trash/{id}/restore",
+            "Take a meal back out of the trash",
+        )
+        .authed()
+        .reply(200, "the meal as restored", meal.clone()),
+    );
+
+    d.add(
+        op("get", "/export", "Every meal not in the trash as one file")
+            .query("format", formats(), "json by default")
+            .reply_as(200, "the meals", "application/json", bulk_file.clone())
+            .reply_as(200, "the meals", "application/x-ndjson", bulk_file.clone())
+            .reply_as(200, "the meals", "text/csv", bulk_file.clone()),
+    );
+    d.add(
+        op("post", "/import", "Add meals from an export file")
+            .authed()
+            .query("format", formats(), "json by default")
+            .query(
+                "dry_run",
+                json!({ "type": "boolean" }),
+                "only say what would happen",
+            )
+            .query(
+                "existing",
+                json!({ "type": "string", "enum": ["skip", "upsert"] }),
+                "what to do with meals that are already there, skip by default",
+            )
+            .body("application/json", bulk_file.clone())
+            .body("application/x-ndjson", bulk_file.clone())
+            .body("text/csv", bulk_file)
+            .reply(200, "what happened to each meal", report),
+    );
+
+    d.add(op("get", "/days", "Every refeed day, newest first").reply(200, "the days", days));
+    d.add(
+        op("post", "/days", "Add a refeed day")
+            .authed()
+            .body("application/json", day.clone())
+            .reply(201, "the day as saved", day.clone()),
+    );
+    d.add(op("get", "/days/{id}", "A refeed day").reply(200, "the day", day.clone()));
+    d.add(
+        op("put", "/days/{id}", "Replace a refeed day")
+            .authed()
+            .body("application/json", day.clone())
+            .reply(202, "the day as saved", day),
+    );
+    d.add(
+        op("delete", "/days/{id}", "Remove a refeed day")
+            .authed()
+            .empty(204),
+    );
+
+    d.add(
+        op("post", "/users", "Add a user, admins only")
+            .authed()
+            .body("application/json", new_user)
+            .reply(201, "the user", user),
+    );
+    d.add(
+        op(
+            "post",
+            "/users/me/password",
+            "Change the caller's password, which logs every session out",
+        )
+        .authed()
+        .body("application/json", password)
+        .empty(204),
+    );
+
+    let error = d.schema::<ErrorResp>();
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "refeed rampage",
+            "version": version,
+        },
+        "paths": d.paths,
+        "components": {
+            "schemas": d.gen.definitions(),
+            "responses": {
+                "Error": {
+                    "description": "what went wrong, code is stable enough to match on",
+                    "content": { "application/json": { "schema": error } }
+                }
+            },
+            "securitySchemes": {
+                "bearer": {
+                    "type": "apiKey",
+                    "in": "header",
+                    "name": "Authorization",
+                    "description": "\"bearer: \" and then the jwt from /login or /token/refresh"
+                }
+            }
+        }
+    })
+}
+
+fn formats() -> Value {
+    json!({ "type": "string", "enum": ["json", "jsonl", "csv"] })
+}
+
+struct Doc {
+    gen: SchemaGenerator,
+    paths: Map<String, Value>,
+}
+
+impl Doc {
+    // a $ref to the type's schema, which ends up in components
+    fn schema<T: JsonSchema>(&mut self) -> Value {
+        serde_json::to_value(self.gen.subschema_for::<T>()).unwrap_or_default()
+    }
+
+    fn add(&mut self, op: Op) {
+        let mut parameters = path_params(op.path);
+        parameters.extend(op.query);
+        let mut responses = op.responses;
+        responses.insert(
+            "default".to_string(),
+            json!({ "$ref": "#/components/responses/Error" }),
+        );
+        let mut o = json!({
+            "summary": op.summary,
+            "responses": responses,
+        });
+        if !parameters.is_empty() {
+            o["parameters"] = Value::Array(parameters);
+        }
+        if !op.body.is_empty() {
+            o["requestBody"] = json!({ "required": true, "content": op.body });
+        }
+        if op.authed {
+            o["security"] = json!([{ "bearer": [] }]);
+        }
+        let path = self
+            .paths
+            .entry(op.path.to_string())
+            .or_insert_with(|| json!({}));
+        path[op.method] = o;
+    }
+}
+
+struct Op {
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    authed: bool,
+    query: Vec<Value>,
+    body: Map<String, Value>,
+    responses: Map<String, Value>,
+}
+
+fn op(method: &'static str, path: &'static str, summary: &'static str) -> Op {
+    Op {
+        method,
+        path,
+        summary,
+        authed: false,
+        query: Vec::new(),
+        body: Map::new(),
+        responses: Map::new(),
+    }
+}
+
+impl Op {
+    fn authed(mut self) -> Op {
+        self.authed = true;
+        self
+    }
+
+    fn query(mut self, name: &str, schema: Value, description: &str) -> Op {
+        self.query.push(json!({
+            "name": name,
+            "in": "query",
+            "description": description,
+            "schema": schema,
+        }));
+        self
+    }
+
+    fn body(mut self, content_type: &str, schema: Value) -> Op {
+        self.body
+            .insert(content_type.to_string(), json!({ "schema": schema }));
+        self
+    }
+
+    fn reply(self, status: u16, description: &str, schema: Value) -> Op {
+        self.reply_as(status, description, "application/json", schema)
+    }
+
+    fn reply_as(mut self, status: u16, description: &str, content_type: &str, schema: Value) -> Op {
+        let r = self
+            .responses
+            .entry(status.to_string())
+            .or_insert_with(|| json!({ "description": description, "content": {} }));
+        r["content"][content_type] = json!({ "schema": schema });
+        self
+    }
+
+    fn empty(mut self, status: u16) -> Op {
+        self.responses
+            .insert(status.to_string(), json!({ "description": "done" }));
+        self
+    }
+}
+
+// the {id}s in the path, every id is a uuid
+fn path_params(path: &str) -> Vec<Value> {
+    path.split('/')
+        .filter_map(|s| s.strip_prefix('{')?.strip_suffix('}'))
+        .map(|name| {
+            let schema = match name {
+                "id" => json!({ "type": "string", "format": "uuid" }),
+                "rev" => json!({ "type": "integer", "minimum": 1 }),
+                _ => json!({ "type": "string" }),
+            };
+            json!({ "name": name, "in": "path", "required": true, "schema": schema })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    // /meals/{id}/photos/{file_name} -> /meals/{}/photos/{}
+    fn normalize(path: &str) -> String {
+        path.split('/')
+            .map(|s| if s.starts_with('{') { "{}" } else { s })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    // Every warp::path! in main.rs and the method filter right after it. Routes can't be
+    // listed at runtime, so this reads the source.
+    fn routes() -> HashSet<(String, String)> {
+        let src = include_str!("main.rs");
+        let mut routes = HashSet::new();
+        for (i, m) in src.match_indices("warp::path!(") {
+            let rest = &src[i + m.len()..];
+            let close = rest.find(')').unwrap();
+            let path: String = rest[..close]
+                .split('/')
+                .map(|s| {
+                    let s = s.trim();
+                    if s.starts_with('"') {
+                        format!("/{}", s.trim_matches('"'))
+                    } else {
+                        "/{}".to_string()
+                    }
+                })
+                .collect();
+            let after = &rest[close..];
+            let m = after.find(".and(warp::").unwrap() + ".and(warp::".len();
+            let method: String = after[m..]
+                .chars()
+                .take_while(|c| c.is_ascii_alphabetic())
+                .collect();
+            assert!(
+                ["get", "post", "put", "patch", "delete"].contains(&method.as_str()),
+                "{} isn't followed by a method filter",
+                path
+            );
+            routes.insert((method, path));
+        }
+        routes
+    }
+
+    #[test]
+    fn test_every_route_documented() {
+        let doc = document("test");
+        let mut documented = HashSet::new();
+        for (path, ops) in doc["paths"].as_object().unwrap() {
+            for method in ops.as_object().unwrap().keys() {
+                documented.insert((method.clone(), normalize(path)));
+            }
+        }
+        let routes = routes();
+        assert!(routes.len() > 20);
+        let missing: Vec<_> = routes.difference(&documented).collect();
+        assert!(missing.is_empty(), "not in openapi.rs: {:?}", missing);
+        let stale: Vec<_> = documented.difference(&routes).collect();
+        assert!(stale.is_empty(), "no such routes: {:?}", stale);
+    }
+
+    fn refs(v: &Value, found: &mut Vec<String>) {
+        match v {
+            Value::Object(o) => {
+                for (k, v) in o {
+                    match v {
+                        Value::String(r) if k == "$ref" => found.push(r.clone()),
+                        _ => refs(v, found),
+                    }
+                }
+            }
+            Value::Array(a) => a.iter().for_each(|v| refs(v, found)),
+            _ => (),
+        }
+    }
+
+    #[test]
+    fn test_refs_resolve() {
+        let doc = document("test");
+        let mut found = Vec::new();
+        refs(&doc, &mut found);
+        assert!(found.contains(&"#/components/schemas/Meal".to_string()));
+        for r in found {
+            let pointer = r.trim_start_matches('#');
+            assert!(doc.pointer(pointer).is_some(), "{} goes nowhere", r);
+        }
+        let meal = &doc["components"]["schemas"]["Meal"];
+        assert_eq!(meal["properties"]["id"]["format"], "uuid");
+        assert_eq!(
+            doc["paths"]["/meals/{id}"]["put"]["parameters"][0]["name"],
+            "id"
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use crate::config::Config;
+use crate::tables::DynamoClient;
+use dynomite::dynamodb::{DescribeTableInput, DynamoDb};
+use rusoto_core::RusotoError;
+use rusoto_s3::{HeadBucketRequest, S3Client, S3};
+use shared::DependencyCheck;
+use std::error::Error;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+// What GET /health/ready looks at before saying the backend can take traffic: the meals
+// table and the photo bucket. Both get asked at once and neither gets longer than
+// ready_timeout_ms, so a hung dependency can't hang the health check too. Both are
+// required unless s3_required is off, the backend still serves everything but photo
+// changes without the bucket so that's a choice between not ready and degraded.
+#[derive(Clone)]
+pub struct Dependencies {
+    // None with the in-memory store, there's no DynamoDB to wait on
+    dynamodb: Option<DynamoClient>,
+    meals_table: String,
+    s3: S3Client,
+    bucket_name: String,
+    s3_required: bool,
+    timeout: Duration,
+}
+
+impl Dependencies {
+    pub fn new(config: &Config, dynamodb: Option<DynamoClient>, s3: S3Client) -> Dependencies {
+        Dependencies {
+            dynamodb,
+            meals_table: config.meals_table.clone(),
+            s3,
+            bucket_name: config.bucket_name.clone(),
+            s3_required: config.s3_required,
+            timeout: Duration::from_millis(config.ready_timeout_ms),
+        }
+    }
+
+    pub async fn check(&self) -> Vec<DependencyCheck> {
+        let dynamodb = async {
+            match &self.dynamodb {
+                Some(client) => Some(
+                    timed(
+                        "dynamodb",
+                        true,
+                        self.timeout,
+                        client.describe_table(DescribeTableInput {
+                            table_name: self.meals_table.clone(),
+                        }),
+                    )
+                    .await,
+                ),
+                None => None,
+            }
+        };
+        let s3 = timed(
+            "s3",
+            self.s3_required,
+            self.timeout,
+            self.s3.head_bucket(HeadBucketRequest {
+                bucket: self.bucket_name.clone(),
+            }),
+        );
+        let (dynamodb, s3) = futures::join!(dynamodb, s3);
+        dynamodb.into_iter().chain(Some(s3)).collect()
+    }
+}
+
+async fn timed<T, E: Error + 'static>(
+    name: &str,
+    required: bool,
+    timeout: Duration,
+    call: impl Future<Output = Result<T, RusotoError<E>>>,
+) -> DependencyCheck {
+    let started = Instant::now();
+    let error = match tokio::time::timeout(timeout, call).await {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => Some(describe(e)),
+        Err(_) => Some(format!("no answer in {}ms", timeout.as_millis())),
+    };
+    if let Some(e) = &error {
+        warn!("{} isn't ready: {}", name, e);
+    }
+    DependencyCheck {
+        name: name.to_string(),
+        required,
+        ok: error.is_none(),
+        latency_ms: started.elapsed().as_millis() as u64,
+        error,
+    }
+}
+
+// HEAD responses have no body, so a missing bucket or bad credentials are only a status
+fn describe<E: Error + 'static>(e: RusotoError<E>) -> String {
+    match e {
+        RusotoError::Unknown(resp) => format!("the service said {}", resp.status),
+        e => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_core::{credential::StaticProvider, HttpClient, Region};
+    use std::net::TcpListener;
+
+    fn s3_at(endpoint: String) -> S3Client {
+        S3Client::new_with(
+            HttpClient::new().unwrap(),
+            StaticProvider::new_minimal("key".to_string(), "secret".to_string()),
+            Region::Custom {
+                name: "us-east-1".to_string(),
+                endpoint,
+            },
+        )
+    }
+
+    fn deps(s3: S3Client) -> Dependencies {
+        Dependencies {
+            dynamodb: None,
+            meals_table: "meals".to_string(),
+            s3,
+            bucket_name: "refeed-rampage".to_string(),
+            s3_required: true,
+            timeout: Duration::from_millis(200),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unreachable() {
+        // bound and dropped, so nothing's listening there
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let checks = deps(s3_at(format!("http://127.0.0.1:{}", port)))
+            .check()
+            .await;
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].name, "s3");
+        assert!(checks[0].required);
+        assert!(!checks[0].ok);
+        assert!(checks[0].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_hung() {
+        // takes the connection and never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let checks = deps(s3_at(endpoint)).check().await;
+        assert!(!checks[0].ok);
+        assert_eq!(checks[0].error.as_deref(), Some("no answer in 200ms"));
+        assert!(checks[0].latency_ms >= 200 && checks[0].latency_ms < 2000);
+    }
+}
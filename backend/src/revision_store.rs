@@ -0,0 +1,328 @@
+use crate::meal_store::StoreError;
+use crate::tables::DynamoClient;
+use crate::token_store::now_secs;
+use async_trait::async_trait;
+use dynomite::{
+    dynamodb::{DynamoDb, GetItemInput, PutItemError, PutItemInput, QueryInput},
+    Attribute, FromAttributes, Item,
+};
+use rusoto_core::RusotoError;
+use shared::{Meal, MealRevision, RevisionAction};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+// every saved version of every meal, only ever added to
+pub type RevisionDb = Arc<dyn RevisionStore>;
+
+// two changes to one meal at once both go for the same rev, the loser tries the next one
+static ADD_ATTEMPTS: usize = 5;
+
+// Every change to a meal, oldest at rev 1. Revisions only ever get added.
+#[async_trait]
+pub trait RevisionStore: Send + Sync {
+    // newest first
+    async fn list(&self, meal_id: Uuid) -> Result<Vec<MealRevision>, StoreError>;
+    async fn get(&self, meal_id: Uuid, rev: u64) -> Result<Option<MealRevision>, StoreError>;
+    // stores the meal as the next revision and gives it back
+    async fn add(
+        &self,
+        meal: Meal,
+        action: RevisionAction,
+        changed_by: Uuid,
+    ) -> Result<MealRevision, StoreError>;
+}
+
+#[derive(Item, Debug, Clone, Default, PartialEq)]
+struct StoredRevision {
+    #[dynomite(partition_key)]
+    meal_id: Uuid,
+    #[dynomite(sort_key)]
+    rev: u64,
+    action: String,
+    changed_by: Uuid,
+    changed_at: u64,
+    // the meal as JSON, so old revisions still load as Meal picks up fields
+    meal: String,
+}
+
+fn action_name(action: RevisionAction) -> &'static str {
+    match action {
+        RevisionAction::Created => "created",
+        RevisionAction::Updated => "updated",
+        RevisionAction::Deleted => "deleted",
+        RevisionAction::Restored => "restored",
+    }
+}
+
+impl StoredRevision {
+    fn new(r: &MealRevision) -> Result<StoredRevision, StoreError> {
+        Ok(StoredRevision {
+            meal_id: r.meal_id,
+            rev: r.rev,
+            action: action_name(r.action).to_string(),
+            changed_by: r.changed_by,
+            changed_at: r.changed_at,
+            meal: serde_json::to_string(&r.meal)
+                .map_err(|e| StoreError::Malformed(e.to_string()))?,
+        })
+    }
+
+    fn revision(self) -> Result<MealRevision, StoreError> {
+        let action = match self.action.as_str() {
+            "created" => RevisionAction::Created,
+            "updated" => RevisionAction::Updated,
+            "deleted" => RevisionAction::Deleted,
+            "restored" => RevisionAction::Restored,
+            other => {
+                return Err(StoreError::Malformed(format!(
+                    "unknown revision action '{}'",
+                    other
+                )))
+            }
+        };
+        Ok(MealRevision {
+            meal_id: self.meal_id,
+            rev: self.rev,
+            action,
+            changed_by: self.changed_by,
+            changed_at: self.changed_at,
+            meal: serde_json::from_str(&self.meal)
+                .map_err(|e| StoreError::Malformed(e.to_string()))?,
+        })
+    }
+}
+
+// Keyed by meal_id with rev as the sort key, so a meal's history is one query
+pub struct DynamoRevisionStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoRevisionStore {
+    pub fn new(client: DynamoClient, table_name: &str) -> DynamoRevisionStore {
+        DynamoRevisionStore {
+            client,
+            table_name: table_name.to_string(),
+        }
+    }
+
+    // a page of the meal's revisions, newest first
+    async fn query(
+        &self,
+        meal_id: Uuid,
+        limit: Option<i64>,
+        start_key: Option<dynomite::Attributes>,
+    ) -> Result<(Vec<MealRevision>, Option<dynomite::Attributes>), StoreError> {
+        let mut values = HashMap::new();
+        values.insert(":meal_id".to_string(), meal_id.into_attr());
+        let page = self
+            .client
+            .query(QueryInput {
+                table_name: self.table_name.clone(),
+                key_condition_expression: Some("meal_id = :meal_id".to_string()),
+                expression_attribute_values: Some(values),
+                scan_index_forward: Some(false),
+                limit,
+                exclusive_start_key: start_key,
+                ..QueryInput::default()
+            })
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+        let mut revisions = Vec::new();
+        for attrs in page.items.unwrap_or_default() {
+            revisions.push(
+                StoredRevision::from_attrs(attrs)
+                    .map_err(|e| StoreError::Malformed(e.to_string()))?
+                    .revision()?,
+            );
+        }
+        Ok((revisions, page.last_evaluated_key))
+    }
+}
+
+#[async_trait]
+impl RevisionStore for DynamoRevisionStore {
+    async fn list(&self, meal_id: Uuid) -> Result<Vec<MealRevision>, StoreError> {
+        let mut revisions = Vec::new();
+        let mut start_key = None;
+        loop {
+            let (mut page, next) = self.query(meal_id, None, start_key).await?;
+            revisions.append(&mut page);
+            start_key = next;
+            if start_key.is_none() {
+                return Ok(revisions);
+            }
+        }
+    }
+
+    async fn get(&self, meal_id: Uuid, rev: u64) -> Result<Option<MealRevision>, StoreError> {
+        let r = StoredRevision {
+            meal_id,
+            rev,
+            ..Default::default()
+        };
+        let item = self
+            .client
+            .get_item(GetItemInput {
+                table_name: self.table_name.clone(),
+                key: r.key(),
+                ..GetItemInput::default()
+            })
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+        match item.item {
+            Some(attrs) => StoredRevision::from_attrs(attrs)
+                .map_err(|e| StoreError::Malformed(e.to_string()))?
+                .revision()
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn add(
+        &self,
+        meal: Meal,
+        action: RevisionAction,
+        changed_by: Uuid,
+    ) -> Result<MealRevision, StoreError> {
+        let mut names = HashMap::new();
+        names.insert("#rev".to_string(), "rev".to_string());
+        for _ in 0..ADD_ATTEMPTS {
+            let (latest, _) = self.query(meal.id, Some(1), None).await?;
+            let revision = MealRevision {
+                meal_id: meal.id,
+                rev: latest.first().map(|r| r.rev).unwrap_or(0) + 1,
+                action,
+                changed_by,
+                changed_at: now_secs(),
+                meal: meal.clone(),
+            };
+            let put = self
+                .client
+                .put_item(PutItemInput {
+                    table_name: self.table_name.clone(),
+                    item: StoredRevision::new(&revision)?.into(),
+                    condition_expression: Some("attribute_not_exists(#rev)".to_string()),
+                    expression_attribute_names: Some(names.clone()),
+                    ..PutItemInput::default()
+                })
+                .await;
+            match put {
+                Ok(_) => return Ok(revision),
+                Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => continue,
+                Err(e) => return Err(StoreError::Backend(e.to_string())),
+            }
+        }
+        Err(StoreError::Conflict)
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryRevisionStore {
+    revisions: Mutex<HashMap<Uuid, Vec<MealRevision>>>,
+}
+
+impl InMemoryRevisionStore {
+    pub fn new() -> InMemoryRevisionStore {
+        InMemoryRevisionStore::default()
+    }
+}
+
+#[async_trait]
+impl RevisionStore for InMemoryRevisionStore {
+    async fn list(&self, meal_id: Uuid) -> Result<Vec<MealRevision>, StoreError> {
+        let revisions = self.revisions.lock().await;
+        Ok(revisions
+            .get(&meal_id)
+            .map(|r| r.iter().rev().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn get(&self, meal_id: Uuid, rev: u64) -> Result<Option<MealRevision>, StoreError> {
+        let revisions = self.revisions.lock().await;
+        Ok(revisions
+            .get(&meal_id)
+            .and_then(|r| r.iter().find(|r| r.rev == rev))
+            .cloned())
+    }
+
+    async fn add(
+        &self,
+        meal: Meal,
+        action: RevisionAction,
+        changed_by: Uuid,
+    ) -> Result<MealRevision, StoreError> {
+        let mut revisions = self.revisions.lock().await;
+        let history = revisions.entry(meal.id).or_default();
+        let revision = MealRevision {
+            meal_id: meal.id,
+            rev: history.len() as u64 + 1,
+            action,
+            changed_by,
+            changed_at: now_secs(),
+            meal,
+        };
+        history.push(revision.clone());
+        Ok(revision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_memory_revisions() {
+        let store = InMemoryRevisionStore::new();
+        let who = Uuid::new_v4();
+        let mut m = Meal {
+            id: Uuid::new_v4(),
+            name: "Tacos".to_string(),
+            description: "Crunchy".to_string(),
+            version: 1,
+            ..Default::default()
+        };
+        let first = store
+            .add(m.clone(), RevisionAction::Created, who)
+            .await
+            .unwrap();
+        assert_eq!(first.rev, 1);
+        m.description = "Soggy".to_string();
+        let second = store
+            .add(m.clone(), RevisionAction::Updated, who)
+            .await
+            .unwrap();
+        assert_eq!(second.rev, 2);
+
+        let listed = store.list(m.id).await.unwrap();
+        assert_eq!(listed, vec![second, first.clone()]);
+        assert_eq!(store.get(m.id, 1).await.unwrap(), Some(first));
+        assert_eq!(store.get(m.id, 3).await.unwrap(), None);
+        assert!(store.list(Uuid::new_v4()).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_dynamo_attrs_round_trip() {
+        let r = MealRevision {
+            meal_id: Uuid::new_v4(),
+            rev: 7,
+            action: RevisionAction::Restored,
+            changed_by: Uuid::new_v4(),
+            changed_at: 1_600_000_000,
+            meal: Meal {
+                name: "Pho".to_string(),
+                owner: Some(Uuid::new_v4()),
+                version: 4,
+                ..Default::default()
+            },
+        };
+        let attrs: dynomite::Attributes = StoredRevision::new(&r).unwrap().into();
+        let back = StoredRevision::from_attrs(attrs)
+            .unwrap()
+            .revision()
+            .unwrap();
+        assert_eq!(back, r);
+    }
+}
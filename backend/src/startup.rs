@@ -0,0 +1,135 @@
+use rand::Rng;
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+// How long to keep at something at startup, when DynamoDB or S3 might still be coming up
+// themselves (a local DynamoDB in docker, or the instance's network).
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub attempts: u32,
+    pub first: Duration,
+    pub max: Duration,
+    // an attempt that hasn't answered by then counts as failed, a hung connection would
+    // otherwise never get to the next one
+    pub per_try: Duration,
+}
+
+// Eight tries of at most 5s with seven waits of at most 0.5, 1, 2, 4, 8, 15 and 15s between
+// them: 40s of trying and 45.5s of waiting, so 85.5s at the very most before giving up.
+// rrmeals.service waits 160s for ready, the rest is for making the other tables and the
+// admin once DynamoDB is answering.
+pub const STARTUP: Backoff = Backoff {
+    attempts: 8,
+    first: Duration::from_millis(500),
+    max: Duration::from_secs(15),
+    per_try: Duration::from_secs(5),
+};
+
+impl Backoff {
+    // doubles each time, somewhere between half and all of it so restarts don't line up
+    fn wait(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .first
+            .checked_mul(1 << (attempt - 1).min(16))
+            .map_or(self.max, |w| w.min(self.max));
+        let ms = ceiling.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(ms / 2, ms + 1))
+    }
+}
+
+// the last error when it never worked
+pub async fn retry<T, E, F, Fut>(what: &str, backoff: Backoff, mut f: F) -> Result<T, E>
+where
+    E: Display + From<String>,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 1;
+    loop {
+        let tried = tokio::time::timeout(backoff.per_try, f())
+            .await
+            .unwrap_or_else(|_| Err(format!("no answer in {:?}", backoff.per_try).into()));
+        match tried {
+            Ok(t) => return Ok(t),
+            Err(e) if attempt >= backoff.attempts => return Err(e),
+            Err(e) => {
+                let wait = backoff.wait(attempt);
+                warn!(
+                    "{} didn't work ({} of {}), trying again in {:?}: {}",
+                    what, attempt, backoff.attempts, wait, e
+                );
+                tokio::time::delay_for(wait).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait() {
+        for attempt in 1..40 {
+            let w = STARTUP.wait(attempt);
+            assert!(w <= STARTUP.max);
+            if attempt == 1 {
+                assert!(w >= Duration::from_millis(250) && w <= Duration::from_millis(500));
+            }
+            if attempt > 6 {
+                assert!(w >= STARTUP.max / 2);
+            }
+        }
+        // what the comment on STARTUP adds up
+        let waits: Duration = (1..STARTUP.attempts)
+            .map(|a| {
+                STARTUP
+                    .first
+                    .checked_mul(1 << (a - 1))
+                    .unwrap()
+                    .min(STARTUP.max)
+            })
+            .sum();
+        assert_eq!(waits, Duration::from_millis(45_500));
+        assert_eq!(STARTUP.per_try * STARTUP.attempts, Duration::from_secs(40));
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let quick = Backoff {
+            attempts: 3,
+            first: Duration::from_millis(1),
+            max: Duration::from_millis(2),
+            per_try: Duration::from_millis(50),
+        };
+        let mut tries = 0;
+        let r: Result<(), String> = retry("nothing", quick, || {
+            tries += 1;
+            async { Err("nope".to_string()) }
+        })
+        .await;
+        assert_eq!(r, Err("nope".to_string()));
+        assert_eq!(tries, 3);
+
+        let mut tries = 0;
+        let r: Result<u32, String> = retry("something", quick, || {
+            tries += 1;
+            let t = tries;
+            async move {
+                if t < 2 {
+                    Err("not yet".to_string())
+                } else {
+                    Ok(t)
+                }
+            }
+        })
+        .await;
+        assert_eq!(r, Ok(2));
+
+        // one that never answers gets given up on too
+        let r: Result<(), String> = retry("hanging", quick, futures::future::pending).await;
+        assert_eq!(r, Err("no answer in 50ms".to_string()));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::config::Config;
+use crate::metered::{MeteredDispatcher, MeteredDynamoDb};
+use crate::startup;
+use dynomite::{
+    dynamodb::{
+        AttributeDefinition, CreateTableInput, DynamoDb, DynamoDbClient, KeySchemaElement,
+        ProvisionedThroughput, TimeToLiveSpecification, UpdateTimeToLiveInput,
+    },
+    retry::{Policy, RetryingDynamoDb},
+    Retries,
+};
+use rusoto_core::{
+    credential::{DefaultCredentialsProvider, ProfileProvider},
+    HttpClient,
+};
+
+// what the stores talk to DynamoDB with, retrying and counted for /metrics
+pub type DynamoClient = MeteredDynamoDb<RetryingDynamoDb<DynamoDbClient>>;
+
+// handle local vs "real" dynamodb
+pub fn get_dynamodb_client(config: &Config) -> Result<DynamoClient, String> {
+    let http_client = MeteredDispatcher::new(
+        HttpClient::new().map_err(|e| format!("couldn't make an HTTP client: {}", e))?,
+    );
+    let client = match config.dynamodb_endpoint {
+        None => {
+            info!("Using real Dynamodb with a new client");
+            // use profile provider only
+            let profile_creds = ProfileProvider::new()
+                .map_err(|e| format!("couldn't load the AWS profile credentials: {}", e))?;
+            DynamoDbClient::new_with(http_client, profile_creds, config.dynamodb_region())
+        }
+        Some(_) => {
+            info!("Using local Dynamodb with a new client");
+            let creds = DefaultCredentialsProvider::new()
+                .map_err(|e| format!("couldn't load AWS credentials: {}", e))?;
+            DynamoDbClient::new_with(http_client, creds, config.dynamodb_region())
+        }
+    };
+    Ok(MeteredDynamoDb::new(client.with_retries(Policy::default())))
+}
+
+// most tables are keyed by a string id
+static ID_KEY: &[(&str, &str)] = &[("id", "S")];
+// a meal's revisions sit together under its id, in rev order
+static REVISION_KEY: &[(&str, &str)] = &[("meal_id", "S"), ("rev", "N")];
+
+// keys are (name, attribute type), the first is the hash key and the second the range key
+async fn make_table(
+    client: DynamoClient,
+    table_name: &str,
+    keys: &[(&str, &str)],
+) -> Result<(), String> {
+    let table_name = table_name.to_string();
+    let create_table_req = client.create_table(CreateTableInput {
+        table_name,
+        key_schema: keys
+            .iter()
+            .zip(&["HASH", "RANGE"])
+            .map(|((name, _), key_type)| KeySchemaElement {
+                attribute_name: name.to_string(),
+                key_type: key_type.to_string(),
+            })
+            .collect(),
+        attribute_definitions: keys
+            .iter()
+            .map(|(name, attribute_type)| AttributeDefinition {
+                attribute_name: name.to_string(),
+                attribute_type: attribute_type.to_string(),
+            })
+            .collect(),
+        provisioned_throughput: Some(ProvisionedThroughput {
+            read_capacity_units: 10,  // 25 max for free tier
+            write_capacity_units: 10, // 25 max for free tier
+        }),
+        ..CreateTableInput::default()
+    });
+    let f = create_table_req.await;
+    match f {
+        Ok(_) => {
+            debug!("All good making table");
+            Ok(())
+        }
+        Err(e) => {
+            // table may not be ready yet, wait and retry
+            // Also, local dynamo returns a different string than real:
+            let e_msg = e.to_string();
+            debug!("error message is '{}'. Checking if that contains the string 'Table already exists'.", e_msg);
+            if e_msg.contains("preexisting table") || e_msg.contains("Table already exists") {
+                return Ok(());
+            }
+            Err(e_msg)
+        }
+    }
+}
+
+// Errors when DynamoDB never answered, there's nothing to serve without it
+pub async fn prepopulate_db(client: DynamoClient, config: &Config) -> Result<(), String> {
+    debug!("Waiting for the db to be available");
+    startup::retry("making the meals table", startup::STARTUP, || {
+        make_table(client.clone(), &config.meals_table, ID_KEY)
+    })
+    .await
+    .map_err(|e| format!("DynamoDB isn't available: {}", e))?;
+    debug!("DB is available");
+    // the meals table is there now, make the rest next to it
+    for (table, keys) in &[
+        (&config.days_table, ID_KEY),
+        (&config.users_table, ID_KEY),
+        (&config.tokens_table, ID_KEY),
+        (&config.revisions_table, REVISION_KEY),
+    ] {
+        if let Err(e) = make_table(client.clone(), table, keys).await {
+            debug!(
+                "Issue creating table {}: {}. Forging ahead anyways.",
+                table, e
+            );
+        }
+    }
+    // the trash sweep normally gets there first, this catches anything it misses
+    let ttl = client.update_time_to_live(UpdateTimeToLiveInput {
+        table_name: config.meals_table.clone(),
+        time_to_live_specification: TimeToLiveSpecification {
+            attribute_name: "purge_at".into(),
+            enabled: true,
+        },
+    });
+    if let Err(e) = ttl.await {
+        // it says so when it's already on
+        debug!("Couldn't turn on TTL for {}: {}", config.meals_table, e);
+    }
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use image::imageops::FilterType;
+use image::io::Reader;
+use image::{DynamicImage, GenericImageView, ImageFormat, ImageOutputFormat};
+use std::fmt;
+use std::io::Cursor;
+
+// phone photos are ~12 megapixels, leave plenty of room but don't decode bombs
+static MAX_PIXELS: u64 = 50_000_000;
+
+// the smaller versions of each photo we make, by name and width
+pub static RENDITIONS: &[(&str, u32)] = &[("thumb", 200), ("medium", 800)];
+
+#[derive(Debug)]
+pub enum ThumbnailError {
+    Unsupported(String),
+    TooBig(u32, u32),
+    Corrupt(String),
+}
+
+impl fmt::Display for ThumbnailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThumbnailError::Unsupported(t) => write!(f, "can't make thumbnails of {}", t),
+            ThumbnailError::TooBig(w, h) => write!(f, "photo is too big at {}x{}", w, h),
+            ThumbnailError::Corrupt(e) => write!(f, "couldn't read photo: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ThumbnailError {}
+
+#[derive(Debug)]
+pub struct Rendition {
+    pub name: &'static str,
+    pub content_type: &'static str,
+    pub data: Vec<u8>,
+}
+
+// Decode the photo and make every rendition of it. PNGs stay PNG to keep transparency,
+// everything else comes out as JPEG since the image crate can't write WebP.
+// This is CPU heavy, run it off the async runtime.
+pub fn make_renditions(data: &[u8], content_type: &str) -> Result<Vec<Rendition>, ThumbnailError> {
+    let format = match content_type {
+        "image/jpeg" => ImageFormat::Jpeg,
+        "image/png" => ImageFormat::Png,
+        "image/webp" => ImageFormat::WebP,
+        other => return Err(ThumbnailError::Unsupported(other.to_string())),
+    };
+
+    let mut reader = Reader::new(Cursor::new(data));
+    reader.set_format(format);
+    let (w, h) = reader
+        .into_dimensions()
+        .map_err(|e| ThumbnailError::Corrupt(e.to_string()))?;
+    if u64::from(w) * u64::from(h) > MAX_PIXELS {
+        return Err(ThumbnailError::TooBig(w, h));
+    }
+
+    let img = image::load_from_memory_with_format(data, format)
+        .map_err(|e| ThumbnailError::Corrupt(e.to_string()))?;
+
+    RENDITIONS
+        .iter()
+        .map(|(name, width)| {
+            let (content_type, data) = encode(&resize(&img, *width), format)?;
+            Ok(Rendition {
+                name,
+                content_type,
+                data,
+            })
+        })
+        .collect()
+}
+
+// scale down to the width keeping the aspect ratio, never scale up
+fn resize(img: &DynamicImage, width: u32) -> DynamicImage {
+    if img.width() <= width {
+        return img.clone();
+    }
+    img.resize(width, u32::MAX, FilterType::Triangle)
+}
+
+fn encode(
+    img: &DynamicImage,
+    source: ImageFormat,
+) -> Result<(&'static str, Vec<u8>), ThumbnailError> {
+    let mut out = Vec::new();
+    let content_type = match source {
+        ImageFormat::Png => {
+            img.write_to(&mut out, ImageOutputFormat::Png)
+                .map_err(|e| ThumbnailError::Corrupt(e.to_string()))?;
+            "image/png"
+        }
+        _ => {
+            DynamicImage::ImageRgb8(img.to_rgb8())
+                .write_to(&mut out, ImageOutputFormat::Jpeg(85))
+                .map_err(|e| ThumbnailError::Corrupt(e.to_string()))?;
+            "image/jpeg"
+        }
+    };
+    Ok((content_type, out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+
+    fn photo(width: u32, height: u32, format: ImageOutputFormat) -> Vec<u8> {
+        let img = ImageBuffer::from_fn(width, height, |x, y| {
+            Rgb([(x % 256) as u8, (y % 256) as u8, 0])
+        });
+        let mut out = Vec::new();
+        DynamicImage::ImageRgb8(img)
+            .write_to(&mut out, format)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_renditions_from_jpeg() {
+        let r = make_renditions(
+            &photo(1600, 1200, ImageOutputFormat::Jpeg(90)),
+            "image/jpeg",
+        )
+        .unwrap();
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].name, "thumb");
+        assert_eq!(r[0].content_type, "image/jpeg");
+
+        let thumb = image::load_from_memory(&r[0].data).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (200, 150));
+        let medium = image::load_from_memory(&r[1].data).unwrap();
+        assert_eq!((medium.width(), medium.height()), (800, 600));
+    }
+
+    #[test]
+    fn test_small_png_stays_png_and_small() {
+        let r = make_renditions(&photo(120, 90, ImageOutputFormat::Png), "image/png").unwrap();
+        for rendition in r {
+            assert_eq!(rendition.content_type, "image/png");
+            let img = image::load_from_memory(&rendition.data).unwrap();
+            assert_eq!((img.width(), img.height()), (120, 90));
+        }
+    }
+
+    #[test]
+    fn test_bad_input() {
+        let mut truncated = photo(400, 300, ImageOutputFormat::Jpeg(90));
+        truncated.truncate(100);
+        assert!(matches!(
+            make_renditions(&truncated, "image/jpeg"),
+            Err(ThumbnailError::Corrupt(_))
+        ));
+        assert!(matches!(
+            make_renditions(b"RIFF\0\0\0\0WEBPVP8 nope", "image/webp"),
+            Err(ThumbnailError::Corrupt(_))
+        ));
+        assert!(matches!(
+            make_renditions(b"GIF89a", "image/gif"),
+            Err(ThumbnailError::Unsupported(_))
+        ));
+    }
+}
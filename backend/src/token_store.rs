@@ -0,0 +1,646 @@
+use crate::meal_store::StoreError;
+use crate::tables::DynamoClient;
+use async_trait::async_trait;
+use dynomite::{
+    dynamodb::{
+        DeleteItemInput, DynamoDb, GetItemInput, PutItemInput, ScanInput, UpdateItemError,
+        UpdateItemInput,
+    },
+    Attribute, Attributes, FromAttributes, Item,
+};
+use rusoto_core::RusotoError;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+// the tokens we've handed out and haven't been revoked yet
+pub type JwtDb = Arc<dyn TokenStore>;
+
+// What happened when a refresh token got traded in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Refresh {
+    // first use, it's spent now. Also a second use right behind the first, see use_refresh
+    Fresh { user_id: Uuid, family: Uuid },
+    // spent already, someone's holding a copy
+    Reused { user_id: Uuid, family: Uuid },
+    // never ours, revoked or expired
+    Unknown,
+}
+
+// Tokens from one login share a family. Refreshing hands out new tokens in the same
+// family, so revoking the family logs out that one login and nothing else.
+#[async_trait]
+pub trait TokenStore: Send + Sync {
+    // an access token, the JWT that goes in the Authorization header
+    async fn insert(
+        &self,
+        token: &str,
+        user_id: Uuid,
+        family: Uuid,
+        expires_at: u64,
+    ) -> Result<(), StoreError>;
+    async fn insert_refresh(
+        &self,
+        token: &str,
+        user_id: Uuid,
+        family: Uuid,
+        expires_at: u64,
+    ) -> Result<(), StoreError>;
+    // who the access token belongs to, None once it's revoked or expired
+    async fn owner(&self, token: &str) -> Result<Option<Uuid>, StoreError>;
+    // Spend a refresh token, each one only works once. Two tabs refreshing at the same time
+    // both send the same one though, so for grace_secs after it's spent it still counts
+    // as Fresh instead of Reused.
+    async fn use_refresh(&self, token: &str, grace_secs: u64) -> Result<Refresh, StoreError>;
+    // the token and everything else from the same login
+    async fn revoke(&self, token: &str) -> Result<(), StoreError>;
+    async fn revoke_family(&self, family: Uuid) -> Result<(), StoreError>;
+    async fn revoke_user(&self, user_id: Uuid) -> Result<(), StoreError>;
+    // drop everything that expired by now, returns how many went
+    async fn purge_expired(&self, now: u64) -> Result<usize, StoreError>;
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Only a hash of each token gets stored, a copy of the table isn't a pile of logins
+fn token_id(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[derive(Item, Debug, Clone, Default, PartialEq)]
+struct StoredToken {
+    // token_id() of the token
+    #[dynomite(partition_key)]
+    id: String,
+    user_id: Uuid,
+    // seconds since the epoch, same as the JWT's exp
+    expires_at: u64,
+    // tokens from before refresh tokens don't have one
+    #[dynomite(default)]
+    family: Option<Uuid>,
+    #[dynomite(default)]
+    refresh: bool,
+    // spent refresh tokens stick around until they expire so reuse can be spotted
+    #[dynomite(default)]
+    used: bool,
+    // when it got spent, tokens spent before the grace period don't have one
+    #[dynomite(default)]
+    used_at: Option<u64>,
+}
+
+impl StoredToken {
+    fn live(&self, now: u64) -> bool {
+        self.expires_at > now
+    }
+
+    fn just_used(&self, now: u64, grace_secs: u64) -> bool {
+        self.used_at.is_some_and(|at| now < at + grace_secs)
+    }
+}
+
+pub struct DynamoTokenStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoTokenStore {
+    pub fn new(client: DynamoClient, table_name: &str) -> DynamoTokenStore {
+        DynamoTokenStore {
+            client,
+            table_name: table_name.to_string(),
+        }
+    }
+
+    async fn put(&self, t: StoredToken) -> Result<(), StoreError> {
+        self.client
+            .put_item(PutItemInput {
+                table_name: self.table_name.clone(),
+                item: t.into(),
+                ..PutItemInput::default()
+            })
+            .await
+            .map(|_| ())
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+
+    async fn get_id(&self, id: String) -> Result<Option<StoredToken>, StoreError> {
+        let t = StoredToken {
+            id,
+            ..Default::default()
+        };
+        let item = self
+            .client
+            .get_item(GetItemInput {
+                table_name: self.table_name.clone(),
+                key: t.key(),
+                ..GetItemInput::default()
+            })
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+        match item.item {
+            Some(attrs) => StoredToken::from_attrs(attrs)
+                .map(Some)
+                .map_err(|e| StoreError::Malformed(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    async fn delete_id(&self, id: String) -> Result<(), StoreError> {
+        let t = StoredToken {
+            id,
+            ..Default::default()
+        };
+        self.client
+            .delete_item(DeleteItemInput {
+                table_name: self.table_name.clone(),
+                key: t.key(),
+                ..DeleteItemInput::default()
+            })
+            .await
+            .map(|_| ())
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+
+    // every stored token matching the filter
+    async fn scan(
+        &self,
+        filter: &str,
+        names: HashMap<String, String>,
+        values: Attributes,
+    ) -> Result<Vec<StoredToken>, StoreError> {
+        let mut found = Vec::new();
+        let mut start_key = None;
+        loop {
+            let scanned = self
+                .client
+                .scan(ScanInput {
+                    table_name: self.table_name.clone(),
+                    exclusive_start_key: start_key,
+                    filter_expression: Some(filter.to_string()),
+                    expression_attribute_names: Some(names.clone()),
+                    expression_attribute_values: Some(values.clone()),
+                    ..ScanInput::default()
+                })
+                .await
+                .map_err(|e| StoreError::Backend(e.to_string()))?;
+            for attrs in scanned.items.unwrap_or_default() {
+                found.push(
+                    StoredToken::from_attrs(attrs)
+                        .map_err(|e| StoreError::Malformed(e.to_string()))?,
+                );
+            }
+            start_key = scanned.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(found);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl TokenStore for DynamoTokenStore {
+    async fn insert(
+        &self,
+        token: &str,
+        user_id: Uuid,
+        family: Uuid,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        self.put(StoredToken {
+            id: token_id(token),
+            user_id,
+            expires_at,
+            family: Some(family),
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn insert_refresh(
+        &self,
+        token: &str,
+        user_id: Uuid,
+        family: Uuid,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        self.put(StoredToken {
+            id: token_id(token),
+            user_id,
+            expires_at,
+            family: Some(family),
+            refresh: true,
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn owner(&self, token: &str) -> Result<Option<Uuid>, StoreError> {
+        Ok(self
+            .get_id(token_id(token))
+            .await?
+            .filter(|t| !t.refresh && t.live(now_secs()))
+            .map(|t| t.user_id))
+    }
+
+    // The condition makes marking it used atomic, two requests racing with the same
+    // refresh token can't both win.
+    async fn use_refresh(&self, token: &str, grace_secs: u64) -> Result<Refresh, StoreError> {
+        let id = token_id(token);
+        let now = now_secs();
+        let t = StoredToken {
+            id: id.clone(),
+            ..Default::default()
+        };
+        let mut names = HashMap::new();
+        names.insert("#refresh".to_string(), "refresh".to_string());
+        names.insert("#used".to_string(), "used".to_string());
+        names.insert("#used_at".to_string(), "used_at".to_string());
+        names.insert("#expires_at".to_string(), "expires_at".to_string());
+        let mut values = HashMap::new();
+        values.insert(":yes".to_string(), true.into_attr());
+        values.insert(":no".to_string(), false.into_attr());
+        values.insert(":now".to_string(), now.into_attr());
+        let updated = self
+            .client
+            .update_item(UpdateItemInput {
+                table_name: self.table_name.clone(),
+                key: t.key(),
+                update_expression: Some("SET #used = :yes, #used_at = :now".to_string()),
+                condition_expression: Some(
+                    "#refresh = :yes AND #used = :no AND #expires_at > :now".to_string(),
+                ),
+                expression_attribute_names: Some(names),
+                expression_attribute_values: Some(values),
+                return_values: Some("ALL_NEW".to_string()),
+                ..UpdateItemInput::default()
+            })
+            .await;
+        match updated {
+            Ok(out) => {
+                let t = StoredToken::from_attrs(out.attributes.unwrap_or_default())
+                    .map_err(|e| StoreError::Malformed(e.to_string()))?;
+                Ok(match t.family {
+                    Some(family) => Refresh::Fresh {
+                        user_id: t.user_id,
+                        family,
+                    },
+                    None => Refresh::Unknown,
+                })
+            }
+            // missing, expired, not a refresh token or spent already: look to see which
+            Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {
+                Ok(match self.get_id(id).await? {
+                    Some(t) if t.refresh && t.used && t.live(now) => spent(&t, now, grace_secs),
+                    _ => Refresh::Unknown,
+                })
+            }
+            Err(e) => Err(StoreError::Backend(e.to_string())),
+        }
+    }
+
+    async fn revoke(&self, token: &str) -> Result<(), StoreError> {
+        let id = token_id(token);
+        match self.get_id(id.clone()).await? {
+            Some(StoredToken {
+                family: Some(family),
+                ..
+            }) => self.revoke_family(family).await,
+            _ => self.delete_id(id).await,
+        }
+    }
+
+    async fn revoke_family(&self, family: Uuid) -> Result<(), StoreError> {
+        let mut names = HashMap::new();
+        names.insert("#family".to_string(), "family".to_string());
+        let mut values = HashMap::new();
+        values.insert(":family".to_string(), family.into_attr());
+        for t in self.scan("#family = :family", names, values).await? {
+            self.delete_id(t.id).await?;
+        }
+        Ok(())
+    }
+
+    async fn revoke_user(&self, user_id: Uuid) -> Result<(), StoreError> {
+        let mut names = HashMap::new();
+        names.insert("#user_id".to_string(), "user_id".to_string());
+        let mut values = HashMap::new();
+        values.insert(":user_id".to_string(), user_id.into_attr());
+        for t in self.scan("#user_id = :user_id", names, values).await? {
+            self.delete_id(t.id).await?;
+        }
+        Ok(())
+    }
+
+    async fn purge_expired(&self, now: u64) -> Result<usize, StoreError> {
+        let mut names = HashMap::new();
+        names.insert("#expires_at".to_string(), "expires_at".to_string());
+        let mut values = HashMap::new();
+        values.insert(":now".to_string(), now.into_attr());
+        let expired = self.scan("#expires_at <= :now", names, values).await?;
+        let count = expired.len();
+        for t in expired {
+            self.delete_id(t.id).await?;
+        }
+        Ok(count)
+    }
+}
+
+// what an already spent refresh token turning up again means
+fn spent(t: &StoredToken, now: u64, grace_secs: u64) -> Refresh {
+    match t.family {
+        Some(family) if t.just_used(now, grace_secs) => Refresh::Fresh {
+            user_id: t.user_id,
+            family,
+        },
+        Some(family) => Refresh::Reused {
+            user_id: t.user_id,
+            family,
+        },
+        None => Refresh::Unknown,
+    }
+}
+
+// gone on restart, which logs everyone out
+#[derive(Default)]
+pub struct InMemoryTokenStore {
+    tokens: Mutex<HashMap<String, StoredToken>>,
+}
+
+impl InMemoryTokenStore {
+    pub fn new() -> InMemoryTokenStore {
+        InMemoryTokenStore::default()
+    }
+}
+
+#[async_trait]
+impl TokenStore for InMemoryTokenStore {
+    async fn insert(
+        &self,
+        token: &str,
+        user_id: Uuid,
+        family: Uuid,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        let id = token_id(token);
+        let t = StoredToken {
+            id: id.clone(),
+            user_id,
+            expires_at,
+            family: Some(family),
+            ..Default::default()
+        };
+        self.tokens.lock().await.insert(id, t);
+        Ok(())
+    }
+
+    async fn insert_refresh(
+        &self,
+        token: &str,
+        user_id: Uuid,
+        family: Uuid,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        let id = token_id(token);
+        let t = StoredToken {
+            id: id.clone(),
+            user_id,
+            expires_at,
+            family: Some(family),
+            refresh: true,
+            ..Default::default()
+        };
+        self.tokens.lock().await.insert(id, t);
+        Ok(())
+    }
+
+    async fn owner(&self, token: &str) -> Result<Option<Uuid>, StoreError> {
+        let now = now_secs();
+        Ok(self
+            .tokens
+            .lock()
+            .await
+            .get(&token_id(token))
+            .filter(|t| !t.refresh && t.live(now))
+            .map(|t| t.user_id))
+    }
+
+    async fn use_refresh(&self, token: &str, grace_secs: u64) -> Result<Refresh, StoreError> {
+        let now = now_secs();
+        let mut tokens = self.tokens.lock().await;
+        let t = match tokens.get_mut(&token_id(token)) {
+            Some(t) if t.refresh && t.live(now) => t,
+            _ => return Ok(Refresh::Unknown),
+        };
+        let (user_id, family) = match t.family {
+            Some(family) => (t.user_id, family),
+            None => return Ok(Refresh::Unknown),
+        };
+        if t.used {
+            return Ok(spent(t, now, grace_secs));
+        }
+        t.used = true;
+        t.used_at = Some(now);
+        Ok(Refresh::Fresh { user_id, family })
+    }
+
+    async fn revoke(&self, token: &str) -> Result<(), StoreError> {
+        let mut tokens = self.tokens.lock().await;
+        let id = token_id(token);
+        match tokens.get(&id).and_then(|t| t.family) {
+            Some(family) => tokens.retain(|_, t| t.family != Some(family)),
+            None => {
+                tokens.remove(&id);
+            }
+        }
+        Ok(())
+    }
+
+    async fn revoke_family(&self, family: Uuid) -> Result<(), StoreError> {
+        self.tokens
+            .lock()
+            .await
+            .retain(|_, t| t.family != Some(family));
+        Ok(())
+    }
+
+    async fn revoke_user(&self, user_id: Uuid) -> Result<(), StoreError> {
+        self.tokens.lock().await.retain(|_, t| t.user_id != user_id);
+        Ok(())
+    }
+
+    async fn purge_expired(&self, now: u64) -> Result<usize, StoreError> {
+        let mut tokens = self.tokens.lock().await;
+        let before = tokens.len();
+        tokens.retain(|_, t| t.live(now));
+        Ok(before - tokens.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_memory_tokens() {
+        let store = InMemoryTokenStore::new();
+        let (sam, al) = (Uuid::new_v4(), Uuid::new_v4());
+        let later = now_secs() + 60;
+        store
+            .insert("sam1", sam, Uuid::new_v4(), later)
+            .await
+            .unwrap();
+        store
+            .insert("sam2", sam, Uuid::new_v4(), later)
+            .await
+            .unwrap();
+        store
+            .insert("al1", al, Uuid::new_v4(), later)
+            .await
+            .unwrap();
+        store.insert("old", al, Uuid::new_v4(), 10).await.unwrap();
+
+        assert_eq!(store.owner("sam1").await.unwrap(), Some(sam));
+        assert_eq!(store.owner("nope").await.unwrap(), None);
+        // expired but not swept yet still doesn't count
+        assert_eq!(store.owner("old").await.unwrap(), None);
+
+        store.revoke("al1").await.unwrap();
+        assert_eq!(store.owner("al1").await.unwrap(), None);
+
+        store.revoke_user(sam).await.unwrap();
+        assert_eq!(store.owner("sam1").await.unwrap(), None);
+        assert_eq!(store.owner("sam2").await.unwrap(), None);
+
+        assert_eq!(store.purge_expired(now_secs()).await.unwrap(), 1);
+        assert_eq!(store.purge_expired(now_secs()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_tokens() {
+        let store = InMemoryTokenStore::new();
+        let (sam, family) = (Uuid::new_v4(), Uuid::new_v4());
+        let later = now_secs() + 60;
+        store.insert("access1", sam, family, later).await.unwrap();
+        store
+            .insert_refresh("refresh1", sam, family, later)
+            .await
+            .unwrap();
+
+        // a refresh token isn't an access token
+        assert_eq!(store.owner("refresh1").await.unwrap(), None);
+        assert_eq!(
+            store.use_refresh("access1", 0).await.unwrap(),
+            Refresh::Unknown
+        );
+        assert_eq!(
+            store.use_refresh("nope", 0).await.unwrap(),
+            Refresh::Unknown
+        );
+
+        assert_eq!(
+            store.use_refresh("refresh1", 0).await.unwrap(),
+            Refresh::Fresh {
+                user_id: sam,
+                family
+            }
+        );
+        assert_eq!(
+            store.use_refresh("refresh1", 0).await.unwrap(),
+            Refresh::Reused {
+                user_id: sam,
+                family
+            }
+        );
+
+        // another login's tokens don't go with the family
+        let other = Uuid::new_v4();
+        store.insert("access2", sam, other, later).await.unwrap();
+        store.revoke_family(family).await.unwrap();
+        assert_eq!(store.owner("access1").await.unwrap(), None);
+        assert_eq!(
+            store.use_refresh("refresh1", 0).await.unwrap(),
+            Refresh::Unknown
+        );
+        assert_eq!(store.owner("access2").await.unwrap(), Some(sam));
+
+        store
+            .insert_refresh("refresh2", sam, other, 10)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.use_refresh("refresh2", 0).await.unwrap(),
+            Refresh::Unknown
+        );
+    }
+
+    #[tokio::test]
+    async fn test_revoke_takes_the_family() {
+        let store = InMemoryTokenStore::new();
+        let (sam, family) = (Uuid::new_v4(), Uuid::new_v4());
+        let later = now_secs() + 60;
+        store.insert("access1", sam, family, later).await.unwrap();
+        store
+            .insert_refresh("refresh1", sam, family, later)
+            .await
+            .unwrap();
+        store
+            .insert("access2", sam, Uuid::new_v4(), later)
+            .await
+            .unwrap();
+
+        store.revoke("access1").await.unwrap();
+        assert_eq!(
+            store.use_refresh("refresh1", 0).await.unwrap(),
+            Refresh::Unknown
+        );
+        assert_eq!(store.owner("access2").await.unwrap(), Some(sam));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_grace() {
+        let store = InMemoryTokenStore::new();
+        let (sam, family) = (Uuid::new_v4(), Uuid::new_v4());
+        let later = now_secs() + 60;
+        store
+            .insert_refresh("refresh1", sam, family, later)
+            .await
+            .unwrap();
+        let fresh = Refresh::Fresh {
+            user_id: sam,
+            family,
+        };
+        // two tabs at once both get through
+        assert_eq!(store.use_refresh("refresh1", 10).await.unwrap(), fresh);
+        assert_eq!(store.use_refresh("refresh1", 10).await.unwrap(), fresh);
+
+        // not once it's been a while
+        let t = StoredToken {
+            used_at: Some(now_secs() - 30),
+            ..store.tokens.lock().await[&token_id("refresh1")].clone()
+        };
+        assert!(!t.just_used(now_secs(), 10));
+        store.tokens.lock().await.insert(t.id.clone(), t);
+        assert_eq!(
+            store.use_refresh("refresh1", 10).await.unwrap(),
+            Refresh::Reused {
+                user_id: sam,
+                family
+            }
+        );
+    }
+
+    #[test]
+    fn test_token_ids() {
+        assert_eq!(token_id("abc"), token_id("abc"));
+        assert_ne!(token_id("abc"), token_id("abd"));
+        assert_eq!(token_id("abc").len(), 64);
+    }
+}
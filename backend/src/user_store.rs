@@ -0,0 +1,212 @@
+use crate::meal_store::StoreError;
+use crate::tables::DynamoClient;
+use async_trait::async_trait;
+use dynomite::{
+    dynamodb::{DynamoDb, GetItemInput, PutItemInput, ScanInput},
+    Attribute, FromAttributes, Item,
+};
+use once_cell::sync::Lazy;
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+// everyone who can log in, looked up by id or username
+pub type UserDb = Arc<dyn UserStore>;
+
+// Someone who can log in. Never goes over the wire as is, see backend_types::UserResp.
+#[derive(Item, Debug, Clone, Default, PartialEq)]
+pub struct User {
+    #[dynomite(partition_key)]
+    pub id: Uuid,
+    pub username: String,
+    // argon2 encoded, salt and parameters included
+    pub password_hash: String,
+    pub admin: bool,
+}
+
+#[async_trait]
+pub trait UserStore: Send + Sync {
+    async fn get(&self, id: Uuid) -> Result<Option<User>, StoreError>;
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, StoreError>;
+    async fn put(&self, user: User) -> Result<(), StoreError>;
+}
+
+pub struct DynamoUserStore {
+    client: DynamoClient,
+    table_name: String,
+}
+
+impl DynamoUserStore {
+    pub fn new(client: DynamoClient, table_name: &str) -> DynamoUserStore {
+        DynamoUserStore {
+            client,
+            table_name: table_name.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl UserStore for DynamoUserStore {
+    async fn get(&self, id: Uuid) -> Result<Option<User>, StoreError> {
+        let u = User {
+            id,
+            ..Default::default()
+        };
+        let item = self
+            .client
+            .get_item(GetItemInput {
+                table_name: self.table_name.clone(),
+                key: u.key(),
+                ..GetItemInput::default()
+            })
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+        match item.item {
+            Some(attrs) => User::from_attrs(attrs)
+                .map(Some)
+                .map_err(|e| StoreError::Malformed(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    // there's only ever a handful of us so a filtered scan beats keeping an index around
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, StoreError> {
+        let mut names = HashMap::new();
+        names.insert("#username".to_string(), "username".to_string());
+        let mut values = HashMap::new();
+        values.insert(":username".to_string(), username.to_string().into_attr());
+        let mut start_key = None;
+        loop {
+            let scanned = self
+                .client
+                .scan(ScanInput {
+                    table_name: self.table_name.clone(),
+                    exclusive_start_key: start_key,
+                    filter_expression: Some("#username = :username".to_string()),
+                    expression_attribute_names: Some(names.clone()),
+                    expression_attribute_values: Some(values.clone()),
+                    ..ScanInput::default()
+                })
+                .await
+                .map_err(|e| StoreError::Backend(e.to_string()))?;
+            if let Some(attrs) = scanned.items.unwrap_or_default().into_iter().next() {
+                return User::from_attrs(attrs)
+                    .map(Some)
+                    .map_err(|e| StoreError::Malformed(e.to_string()));
+            }
+            start_key = scanned.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(None);
+            }
+        }
+    }
+
+    async fn put(&self, user: User) -> Result<(), StoreError> {
+        self.client
+            .put_item(PutItemInput {
+                table_name: self.table_name.clone(),
+                item: user.into(),
+                ..PutItemInput::default()
+            })
+            .await
+            .map(|_| ())
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+}
+
+#[derive(Default)]
+pub struct InMemoryUserStore {
+    users: Mutex<HashMap<Uuid, User>>,
+}
+
+impl InMemoryUserStore {
+    pub fn new() -> InMemoryUserStore {
+        InMemoryUserStore::default()
+    }
+}
+
+#[async_trait]
+impl UserStore for InMemoryUserStore {
+    async fn get(&self, id: Uuid) -> Result<Option<User>, StoreError> {
+        Ok(self.users.lock().await.get(&id).cloned())
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, StoreError> {
+        Ok(self
+            .users
+            .lock()
+            .await
+            .values()
+            .find(|u| u.username == username)
+            .cloned())
+    }
+
+    async fn put(&self, user: User) -> Result<(), StoreError> {
+        self.users.lock().await.insert(user.id, user);
+        Ok(())
+    }
+}
+
+// Argon2id with the OWASP suggested 19MiB and two passes. This takes a while on purpose,
+// call it from spawn_blocking.
+pub fn hash_password(password: &str) -> Result<String, argon2::Error> {
+    let salt: [u8; 16] = rand::thread_rng().gen();
+    let config = argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        mem_cost: 19 * 1024,
+        time_cost: 2,
+        ..argon2::Config::default()
+    };
+    argon2::hash_encoded(password.as_bytes(), &salt, &config)
+}
+
+// a hash we can't read counts as a wrong password
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    argon2::verify_encoded(hash, password.as_bytes()).unwrap_or(false)
+}
+
+// Checked instead when there's no such user, so a wrong username takes as long to turn
+// down as a wrong password and the timing doesn't give away who has an account.
+static NOBODY: Lazy<String> = Lazy::new(|| hash_password("nobody's password").unwrap_or_default());
+
+pub fn dummy_hash() -> &'static str {
+    &NOBODY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_hashing() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password(&hash, "correct horse"));
+        assert!(!verify_password(&hash, "battery staple"));
+        assert!(!verify_password("not a hash", "correct horse"));
+        // salted, so the same password hashes differently every time
+        assert_ne!(hash, hash_password("correct horse").unwrap());
+        assert!(dummy_hash().starts_with("$argon2id$"));
+        assert!(!verify_password(dummy_hash(), "correct horse"));
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_find_by_username() {
+        let store = InMemoryUserStore::new();
+        let u = User {
+            id: Uuid::new_v4(),
+            username: "sam".to_string(),
+            password_hash: "x".to_string(),
+            admin: false,
+        };
+        store.put(u.clone()).await.unwrap();
+        assert_eq!(
+            store.find_by_username("sam").await.unwrap(),
+            Some(u.clone())
+        );
+        assert_eq!(store.find_by_username("Sam").await.unwrap(), None);
+        assert_eq!(store.get(u.id).await.unwrap(), Some(u));
+    }
+}
@@ -19,8 +19,13 @@ impl std::default::Default for MyWorld {
                 name: "".to_string(),
                 id: Uuid::new_v4(),
                 photos: None,
+                renditions: None,
                 description: "".to_string(),
                 stars: None,
+                owner: None,
+                version: 0,
+                deleted_at: None,
+                purge_at: None,
             },
             resp_code: 0,
         }
@@ -29,8 +34,11 @@ impl std::default::Default for MyWorld {
 
 // https://github.com/bbqsrc/cucumber-rust
 mod example_steps {
-    use super::Meal;
+    use client::{native::Client, Api, MealsQuery};
     use cucumber::steps;
+    use uuid::Uuid;
+
+    static URL: &str = "http://127.0.0.1:3030";
 
     // TODO: an AFTER step that clears myworld
 
@@ -40,25 +48,26 @@ mod example_steps {
         };
 
         when "I try to delete a meal without auth" |world, _step| {
-            let resp = reqwest::blocking::get("http://127.0.0.1:3030/meals").unwrap()
-            .json::<Vec<Meal>>().unwrap();
-            let meal_to_del = resp[0].id;
-            let client = reqwest::blocking::Client::new();
-            let r2 = client.delete(&format!("http://127.0.0.1:3030/meals/{}", meal_to_del)).send().unwrap();
-            world.resp_code = r2.status().as_u16();
+            let client = Client::new();
+            let api = Api::new(URL);
+            let meals = client.send(api.meals(&MealsQuery::default())).unwrap().items;
+            let meal_to_del = meals[0].meal.id;
+            match client.send(api.delete_meal(meal_to_del)) {
+                Ok(()) => panic!("deleted a meal without logging in"),
+                Err(e) => world.resp_code = e.status().map(|s| s.as_u16()).unwrap_or_default(),
+            }
         };
 
         when "I request all meals" |world, _step| {
-            let resp = reqwest::blocking::get("http://127.0.0.1:3030/meals").unwrap()
-            .json::<Vec<Meal>>().unwrap();
-            world.meals = resp;
+            let page = Client::new().send(Api::new(URL).meals(&MealsQuery::default())).unwrap();
+            world.meals = page.items.into_iter().map(|s| s.meal).collect();
         };
 
         when "I request to see a specific meal" |world, _step| {
           // a well known one
-          let resp = reqwest::blocking::get("http://127.0.0.1:3030/meals/f11b1c5e-d6d8-4dce-8a9d-9e05d870b881").expect("GET for a meal should work, but it didn't.");
-          match resp.json::<Meal>() {
-              Ok(o) => world.meal = o,
+          let id = Uuid::parse_str("f11b1c5e-d6d8-4dce-8a9d-9e05d870b881").unwrap();
+          match Client::new().send(Api::new(URL).meal(id)) {
+              Ok(o) => world.meal = o.meal,
               Err(e) => panic!("got an error: {}", e),
           }
       };
@@ -68,15 +77,15 @@ mod example_steps {
         };
 
         then "I see some meals" |world, _step| {
-            assert_eq!(world.meals.len() > 0, true);
+            assert!(!world.meals.is_empty());
             println!("Meals be all {:?}", world.meals);
         };
 
         then "I can see that meal" |world, _step| {
           println!("meal is {:?}", world.meal);
-          assert_eq!(world.meal.name.len() > 0, true);
-          assert_eq!(world.meal.description.len() > 0, true);
-          assert_eq!(world.meal.stars.is_some(), true);
+          assert!(!world.meal.name.is_empty());
+          assert!(!world.meal.description.is_empty());
+          assert!(world.meal.stars.is_some());
       };
     });
 }
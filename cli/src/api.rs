@@ -0,0 +1,166 @@
+use client::native::Client;
+use client::{Call, Error};
+use serde_derive::{Deserialize, Serialize};
+use shared::Login;
+use std::fs;
+use std::path::PathBuf;
+
+// what `refeed login` leaves behind for the other commands
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Session {
+    pub url: String,
+    pub user: String,
+    pub jwt: String,
+    // None when the server didn't hand one out, the next 401 means logging in again
+    pub refresh: Option<String>,
+}
+
+pub struct Api {
+    client: Client,
+    api: client::Api,
+    session_file: PathBuf,
+    session: Option<Session>,
+}
+
+impl Api {
+    pub fn new(url: &str, session_file: PathBuf) -> Api {
+        let session = fs::read(&session_file)
+            .ok()
+            .and_then(|s| serde_json::from_slice(&s).ok());
+        Api {
+            client: Client::new(),
+            api: client::Api::new(url),
+            session_file,
+            session,
+        }
+    }
+
+    pub fn login(&mut self, user: &str, pw: &str) -> Result<(), String> {
+        let login = Login {
+            user: user.to_string(),
+            pw: pw.to_string(),
+        };
+        let tokens = self
+            .client
+            .send(self.api.login(&login))
+            .map_err(|e| e.to_string())?;
+        self.save(Some(Session {
+            url: self.api.url().to_string(),
+            user: user.to_string(),
+            jwt: tokens.jwt,
+            refresh: tokens.refresh,
+        }))
+    }
+
+    // forgets the session even when the server's already forgotten it
+    pub fn logout(&mut self) -> Result<(), String> {
+        if self.session.is_some() {
+            match self.try_send(|a| a.logout())? {
+                Err(e) if !e.is_unauthorized() => return Err(e.to_string()),
+                _ => (),
+            }
+        }
+        self.save(None)
+    }
+
+    pub fn user(&self) -> Option<&str> {
+        self.session.as_ref().map(|s| s.user.as_str())
+    }
+
+    // without logging in
+    pub fn get<T>(&self, call: impl FnOnce(&client::Api) -> Call<T>) -> Result<T, String> {
+        self.client.send(call(&self.api)).map_err(|e| e.to_string())
+    }
+
+    // With the cached token. An expired one gets refreshed and the call goes again, once.
+    pub fn send<T>(&mut self, call: impl Fn(&client::Api) -> Call<T>) -> Result<T, String> {
+        self.try_send(call)?.map_err(|e| e.to_string())
+    }
+
+    // the outside error is not being logged in, the inside one is what the server said
+    fn try_send<T>(
+        &mut self,
+        call: impl Fn(&client::Api) -> Call<T>,
+    ) -> Result<Result<T, Error>, String> {
+        let session = self.session()?.clone();
+        let authed = self.api.clone().with_token(&session.jwt);
+        match self.client.send(call(&authed)) {
+            Err(e) if e.is_unauthorized() && session.refresh.is_some() => {
+                let jwt = self.refresh(&session)?;
+                let authed = self.api.clone().with_token(&jwt);
+                Ok(self.client.send(call(&authed)))
+            }
+            r => Ok(r),
+        }
+    }
+
+    fn session(&self) -> Result<&Session, String> {
+        match &self.session {
+            Some(s) if s.url == self.api.url() => Ok(s),
+            Some(s) => Err(format!(
+                "logged in to {} rather than {}, run refeed login",
+                s.url,
+                self.api.url()
+            )),
+            None => Err("not logged in, run refeed login".to_string()),
+        }
+    }
+
+    fn refresh(&mut self, session: &Session) -> Result<String, String> {
+        let tokens = match self
+            .client
+            .send(self.api.refresh(session.refresh.as_deref()))
+        {
+            Ok(t) => t,
+            Err(e) if e.is_unauthorized() => {
+                self.save(None)?;
+                return Err("the login has expired, run refeed login".to_string());
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        self.save(Some(Session {
+            jwt: tokens.jwt.clone(),
+            refresh: tokens.refresh,
+            ..session.clone()
+        }))?;
+        Ok(tokens.jwt)
+    }
+
+    fn save(&mut self, session: Option<Session>) -> Result<(), String> {
+        let what = self.session_file.display().to_string();
+        match &session {
+            Some(s) => {
+                if let Some(dir) = self.session_file.parent() {
+                    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", what, e))?;
+                }
+                let data = serde_json::to_vec_pretty(s).map_err(|e| e.to_string())?;
+                write_private(&self.session_file, &data).map_err(|e| format!("{}: {}", what, e))?;
+            }
+            None if self.session_file.exists() => {
+                fs::remove_file(&self.session_file).map_err(|e| format!("{}: {}", what, e))?
+            }
+            None => (),
+        }
+        self.session = session;
+        Ok(())
+    }
+}
+
+// the token's as good as a password, only we get to read it
+#[cfg(unix)]
+fn write_private(path: &PathBuf, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(data)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &PathBuf, data: &[u8]) -> std::io::Result<()> {
+    fs::write(path, data)
+}
@@ -0,0 +1,241 @@
+mod api;
+mod output;
+
+use api::Api;
+use client::MealsQuery;
+use output::Output;
+use shared::Meal;
+use std::path::PathBuf;
+use std::process::Command;
+use structopt::StructOpt;
+use uuid::Uuid;
+
+// GET /meals hands out at most this many at a time
+static PAGE_SIZE: usize = 100;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "refeed",
+    about = "Looks after refeed rampage meals from the command line"
+)]
+struct Args {
+    /// Where the API is
+    #[structopt(long, env = "REFEED_URL", default_value = "http://127.0.0.1:3030")]
+    url: String,
+    /// Where the login is kept between runs, defaults to refeed/session.json in the config dir
+    #[structopt(long, env = "REFEED_SESSION", parse(from_os_str))]
+    session: Option<PathBuf>,
+    /// table or json
+    #[structopt(short, long, global = true, default_value = "table")]
+    output: Output,
+    #[structopt(subcommand)]
+    command: Cmd,
+}
+
+#[derive(StructOpt, Debug)]
+enum Cmd {
+    /// Log in and keep the token for the other commands
+    Login {
+        #[structopt(long, env = "REFEED_USER")]
+        user: String,
+        /// Asked for when it's not given
+        #[structopt(long, env = "REFEED_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Log out and forget the token
+    Logout,
+    /// Every meal, or the ones matching
+    List {
+        #[structopt(long)]
+        min_stars: Option<i32>,
+        /// Case sensitive, part of the name is fine
+        #[structopt(long)]
+        name: Option<String>,
+    },
+    /// One meal
+    Show { id: Uuid },
+    /// A new meal, it's yours
+    Create {
+        #[structopt(long)]
+        name: String,
+        #[structopt(long)]
+        description: String,
+        #[structopt(long)]
+        stars: Option<i32>,
+    },
+    /// Change a meal in $EDITOR
+    Edit { id: Uuid },
+    /// Move a meal to the trash
+    Delete { id: Uuid },
+    /// Give a meal 1 to 5 stars, or none to clear them
+    Rate { id: Uuid, stars: Stars },
+}
+
+// what `refeed rate` takes
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Stars(Option<i32>);
+
+impl std::str::FromStr for Stars {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Stars(None)),
+            _ => match s.parse() {
+                Ok(n) if (1..=5).contains(&n) => Ok(Stars(Some(n))),
+                _ => Err("stars are 1 to 5, or none".to_string()),
+            },
+        }
+    }
+}
+
+fn main() {
+    let args = Args::from_args();
+    match run(args) {
+        Ok(out) if out.is_empty() => (),
+        Ok(out) => println!("{}", out),
+        Err(e) => {
+            eprintln!("refeed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn session_file(args: &Args) -> Result<PathBuf, String> {
+    match &args.session {
+        Some(f) => Ok(f.clone()),
+        None => dirs::config_dir()
+            .map(|d| d.join("refeed").join("session.json"))
+            .ok_or_else(|| "no config dir to keep the login in, use --session".to_string()),
+    }
+}
+
+// what to print
+fn run(args: Args) -> Result<String, String> {
+    let mut api = Api::new(&args.url, session_file(&args)?);
+    let out = args.output;
+    match args.command {
+        Cmd::Login { user, password } => {
+            let pw = match password {
+                Some(p) => p,
+                None => rpassword::read_password_from_tty(Some("Password: "))
+                    .map_err(|e| format!("couldn't read the password: {}", e))?,
+            };
+            api.login(&user, &pw)?;
+            Ok(format!("logged in to {} as {}", args.url, user))
+        }
+        Cmd::Logout => {
+            let who = api.user().map(|u| u.to_string());
+            api.logout()?;
+            Ok(who.map(|u| format!("logged {} out", u)).unwrap_or_default())
+        }
+        Cmd::List { min_stars, name } => {
+            let meals = list(&api, min_stars, name.as_deref())?;
+            Ok(out.meals(&meals))
+        }
+        Cmd::Show { id } => {
+            let detail = api.get(|a| a.meal(id))?;
+            Ok(out.detail(&detail))
+        }
+        Cmd::Create {
+            name,
+            description,
+            stars,
+        } => {
+            let meal = Meal {
+                name,
+                description,
+                stars,
+                ..Meal::default()
+            };
+            let created = api.send(|a| a.create_meal(&meal))?;
+            Ok(out.meal(&created))
+        }
+        Cmd::Edit { id } => edit(&mut api, id, out),
+        Cmd::Delete { id } => {
+            api.send(|a| a.delete_meal(id))?;
+            Ok(format!("moved {} to the trash", id))
+        }
+        Cmd::Rate { id, stars } => {
+            let patch = serde_json::json!({ "stars": stars.0 });
+            let rated = api.send(|a| a.patch_meal(id, &patch))?;
+            Ok(out.meal(&rated))
+        }
+    }
+}
+
+// every page of GET /meals
+fn list(api: &Api, min_stars: Option<i32>, name: Option<&str>) -> Result<Vec<Meal>, String> {
+    let mut meals = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let query = MealsQuery {
+            limit: Some(PAGE_SIZE),
+            cursor,
+            min_stars,
+            name: name.map(|n| n.to_string()),
+        };
+        let page = api.get(|a| a.meals(&query))?;
+        meals.extend(page.items.into_iter().map(|s| s.meal));
+        cursor = page.next_cursor;
+        if cursor.is_none() {
+            return Ok(meals);
+        }
+    }
+}
+
+// The meal goes in a file for $EDITOR and whatever comes back gets saved, unless nothing
+// changed. The version in the file is what a conflict gets checked against.
+fn edit(api: &mut Api, id: Uuid, out: Output) -> Result<String, String> {
+    let detail = api.get(|a| a.meal(id))?;
+    let before = serde_json::to_string_pretty(&detail.meal).map_err(|e| e.to_string())?;
+    let file = std::env::temp_dir().join(format!("refeed-{}.json", id));
+    std::fs::write(&file, &before).map_err(|e| format!("{}: {}", file.display(), e))?;
+    let edited = run_editor(&file).and_then(|_| {
+        std::fs::read_to_string(&file).map_err(|e| format!("{}: {}", file.display(), e))
+    });
+    let _ = std::fs::remove_file(&file);
+    let edited = edited?;
+    if edited.trim() == before.trim() {
+        return Ok("nothing changed".to_string());
+    }
+    let meal: Meal =
+        serde_json::from_str(&edited).map_err(|e| format!("that's not a meal any more: {}", e))?;
+    if meal.id != id {
+        return Err("the id can't change, create a new meal instead".to_string());
+    }
+    let saved = api.send(|a| a.update_meal(&meal))?;
+    Ok(out.meal(&saved))
+}
+
+// $VISUAL, then $EDITOR, then vi. It can have arguments, eg "code --wait".
+fn run_editor(file: &PathBuf) -> Result<(), String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().ok_or("$EDITOR is empty")?;
+    let status = Command::new(program)
+        .args(words)
+        .arg(file)
+        .status()
+        .map_err(|e| format!("couldn't run {}: {}", editor, e))?;
+    if !status.success() {
+        return Err(format!("{} gave up, nothing saved", editor));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stars() {
+        assert_eq!("3".parse(), Ok(Stars(Some(3))));
+        assert_eq!("none".parse(), Ok(Stars(None)));
+        assert!("0".parse::<Stars>().is_err());
+        assert!("6".parse::<Stars>().is_err());
+        assert!("lots".parse::<Stars>().is_err());
+    }
+}
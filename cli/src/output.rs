@@ -0,0 +1,173 @@
+use shared::{Meal, MealDetail};
+use std::str::FromStr;
+
+// descriptions get cut down to this in the list so rows stay on one line
+static MAX_DESCRIPTION: usize = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    Table,
+    Json,
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Output::Table),
+            "json" => Ok(Output::Json),
+            other => Err(format!("unknown output '{}', it's table or json", other)),
+        }
+    }
+}
+
+impl Output {
+    pub fn meals(self, meals: &[Meal]) -> String {
+        match self {
+            Output::Json => pretty(&meals),
+            Output::Table => table(
+                &["ID", "NAME", "STARS", "VERSION", "DESCRIPTION"],
+                meals
+                    .iter()
+                    .map(|m| {
+                        vec![
+                            m.id.to_string(),
+                            m.name.clone(),
+                            stars(m.stars),
+                            m.version.to_string(),
+                            shorten(&m.description),
+                        ]
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn meal(self, meal: &Meal) -> String {
+        match self {
+            Output::Json => pretty(meal),
+            Output::Table => fields(meal, None),
+        }
+    }
+
+    pub fn detail(self, detail: &MealDetail) -> String {
+        match self {
+            Output::Json => pretty(detail),
+            Output::Table => fields(&detail.meal, Some(detail.photos.len())),
+        }
+    }
+}
+
+fn pretty<T: serde::Serialize + ?Sized>(v: &T) -> String {
+    serde_json::to_string_pretty(v).unwrap_or_default()
+}
+
+fn stars(stars: Option<i32>) -> String {
+    stars
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+// newlines would break the table up too
+fn shorten(s: &str) -> String {
+    let s = s.replace(['\r', '\n'], " ");
+    if s.chars().count() <= MAX_DESCRIPTION {
+        return s;
+    }
+    let cut: String = s.chars().take(MAX_DESCRIPTION - 1).collect();
+    format!("{}…", cut)
+}
+
+// one meal, a field per line
+fn fields(meal: &Meal, photos: Option<usize>) -> String {
+    let mut rows = vec![
+        ("id", meal.id.to_string()),
+        ("name", meal.name.clone()),
+        ("description", meal.description.clone()),
+        ("stars", stars(meal.stars)),
+        (
+            "owner",
+            meal.owner
+                .map(|o| o.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+        ),
+        ("version", meal.version.to_string()),
+    ];
+    if let Some(p) = photos {
+        rows.push(("photos", p.to_string()));
+    }
+    rows.iter()
+        .map(|(k, v)| format!("{:<12} {}", k, v))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// columns padded out to their widest cell
+fn table(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let line = |cells: Vec<String>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(c, w)| format!("{:<width$}", c, width = w))
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    };
+    let mut lines = vec![line(headers.iter().map(|h| h.to_string()).collect())];
+    lines.extend(rows.into_iter().map(line));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_table() {
+        let meals = vec![
+            Meal {
+                id: Uuid::nil(),
+                name: "Burritos".to_string(),
+                description: "Amazing\nburritos".to_string(),
+                stars: Some(4),
+                version: 12,
+                ..Default::default()
+            },
+            Meal {
+                id: Uuid::nil(),
+                name: "Pho".to_string(),
+                description: "x".repeat(50),
+                ..Default::default()
+            },
+        ];
+        let out = Output::Table.meals(&meals);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[0],
+            format!("{:<36}  NAME      STARS  VERSION  DESCRIPTION", "ID")
+        );
+        assert!(lines[1].ends_with("Burritos  4      12       Amazing burritos"));
+        assert!(lines[2].ends_with(&format!("Pho       -      0        {}…", "x".repeat(39))));
+    }
+
+    #[test]
+    fn test_json_round_trips() {
+        let meal = Meal {
+            id: Uuid::new_v4(),
+            name: "Pho".to_string(),
+            stars: Some(5),
+            ..Default::default()
+        };
+        let out = Output::Json.meals(std::slice::from_ref(&meal));
+        assert_eq!(serde_json::from_str::<Vec<Meal>>(&out).unwrap(), vec![meal]);
+        assert_eq!("json".parse(), Ok(Output::Json));
+        assert!("yaml".parse::<Output>().is_err());
+    }
+}
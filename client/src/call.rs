@@ -0,0 +1,241 @@
+use crate::error::{Error, ErrorCode};
+use http::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use shared::{ErrorResp, LoginResp, Meal, Readiness, REFRESH_COOKIE};
+
+// One request to the API, ready for whichever backend sends it, and what the response
+// turns into.
+pub struct Call<T> {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Option<Vec<u8>>,
+    // browsers only send and keep the refresh cookie when they're asked to
+    pub with_cookies: bool,
+    decode: fn(Response) -> Result<T, Error>,
+}
+
+// what came back, as much as the backends need to hand over
+pub struct Response {
+    pub status: StatusCode,
+    // browsers don't let us see these, so it's always empty there
+    pub set_cookies: Vec<String>,
+    pub body: Vec<u8>,
+}
+
+// from /login and /token/refresh
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tokens {
+    pub jwt: String,
+    // seconds until jwt stops working
+    pub expires_in: u64,
+    // the rr_refresh cookie, None in browsers where it stays in the cookie jar
+    pub refresh: Option<String>,
+}
+
+impl<T> Call<T> {
+    pub(crate) fn new(
+        method: Method,
+        url: String,
+        decode: fn(Response) -> Result<T, Error>,
+    ) -> Call<T> {
+        Call {
+            method,
+            url,
+            headers: Vec::new(),
+            body: None,
+            with_cookies: false,
+            decode,
+        }
+    }
+
+    pub(crate) fn header(mut self, name: &'static str, value: String) -> Call<T> {
+        self.headers.push((name, value));
+        self
+    }
+
+    pub(crate) fn body(mut self, content_type: &str, body: Vec<u8>) -> Call<T> {
+        self.body = Some(body);
+        self.header("content-type", content_type.to_string())
+    }
+
+    pub(crate) fn json<B: serde::Serialize + ?Sized>(self, body: &B) -> Call<T> {
+        // our own types always serialize
+        let body = serde_json::to_vec(body).unwrap_or_default();
+        self.body("application/json", body)
+    }
+
+    pub(crate) fn with_cookies(mut self) -> Call<T> {
+        self.with_cookies = true;
+        self
+    }
+
+    pub fn finish(self, resp: Response) -> Result<T, Error> {
+        (self.decode)(resp)
+    }
+}
+
+pub(crate) fn json<T: DeserializeOwned>(resp: Response) -> Result<T, Error> {
+    let resp = check(resp)?;
+    serde_json::from_slice(&resp.body).map_err(|e| Error::Decode(e.to_string()))
+}
+
+// for the 204s
+pub(crate) fn empty(resp: Response) -> Result<(), Error> {
+    check(resp).map(|_| ())
+}
+
+pub(crate) fn text(resp: Response) -> Result<String, Error> {
+    let resp = check(resp)?;
+    String::from_utf8(resp.body).map_err(|e| Error::Decode(e.to_string()))
+}
+
+pub(crate) fn tokens(resp: Response) -> Result<Tokens, Error> {
+    let refresh = resp
+        .set_cookies
+        .iter()
+        .find_map(|c| cookie_value(c, REFRESH_COOKIE));
+    let login: LoginResp = json(resp)?;
+    Ok(Tokens {
+        jwt: login.jwt,
+        expires_in: login.expires_in,
+        refresh,
+    })
+}
+
+// a 503 from /health/ready has the failed checks in it, worth more than Error::Status
+pub(crate) fn readiness(resp: Response) -> Result<Readiness, Error> {
+    if resp.status == StatusCode::SERVICE_UNAVAILABLE {
+        if let Ok(r) = serde_json::from_slice(&resp.body) {
+            return Ok(r);
+        }
+    }
+    json(resp)
+}
+
+fn check(resp: Response) -> Result<Response, Error> {
+    let status = resp.status;
+    if status.is_success() {
+        return Ok(resp);
+    }
+    if let Ok(e) = serde_json::from_slice::<ErrorResp>(&resp.body) {
+        return Err(Error::Api {
+            status,
+            code: ErrorCode::from(e.code.as_str()),
+            message: e.error,
+        });
+    }
+    // a version conflict sends the meal as it is now instead
+    if status == StatusCode::CONFLICT {
+        if let Ok(m) = serde_json::from_slice::<Meal>(&resp.body) {
+            return Err(Error::Conflict(Box::new(m)));
+        }
+    }
+    Err(Error::Status(status))
+}
+
+// rr_refresh=abc; HttpOnly; Path=/token -> abc, an empty one is the server clearing it
+pub fn cookie_value(header: &str, name: &str) -> Option<String> {
+    let pair = header.split(';').next()?.trim();
+    let value = pair.strip_prefix(name)?.strip_prefix('=')?;
+    Some(value.to_string()).filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resp(status: StatusCode, body: &str) -> Response {
+        Response {
+            status,
+            set_cookies: vec![],
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_cookie_value() {
+        assert_eq!(
+            cookie_value(
+                "rr_refresh=abc.def; HttpOnly; SameSite=Strict; Path=/token",
+                REFRESH_COOKIE
+            ),
+            Some("abc.def".to_string())
+        );
+        assert_eq!(cookie_value("rr_refresh=; Max-Age=0", REFRESH_COOKIE), None);
+        assert_eq!(cookie_value("other=abc", REFRESH_COOKIE), None);
+        assert_eq!(cookie_value("rr_refreshed=abc", REFRESH_COOKIE), None);
+    }
+
+    #[test]
+    fn test_errors() {
+        let e = json::<Meal>(resp(
+            StatusCode::NOT_FOUND,
+            r#"{"error":"no such meal","code":"not_found"}"#,
+        ))
+        .unwrap_err();
+        assert_eq!(
+            e,
+            Error::Api {
+                status: StatusCode::NOT_FOUND,
+                code: ErrorCode::NotFound,
+                message: "no such meal".to_string()
+            }
+        );
+        assert_eq!(e.to_string(), "no such meal (404)");
+
+        let e = json::<Meal>(resp(
+            StatusCode::CONFLICT,
+            r#"{"id":"f11b1c5e-d6d8-4dce-8a9d-9e05d870b881","name":"Tacos","description":"","version":3}"#,
+        ))
+        .unwrap_err();
+        match &e {
+            Error::Conflict(m) => assert_eq!(m.version, 3),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert_eq!(
+            e.to_string(),
+            "someone else changed it in the meantime, have another look"
+        );
+
+        let e = empty(resp(StatusCode::BAD_GATEWAY, "<html>")).unwrap_err();
+        assert_eq!(e.to_string(), "the server said 502 Bad Gateway");
+
+        let e = empty(resp(
+            StatusCode::IM_A_TEAPOT,
+            r#"{"error":"short and stout","code":"teapot"}"#,
+        ))
+        .unwrap_err();
+        assert!(matches!(e, Error::Api { code: ErrorCode::Other(c), .. } if c == "teapot"));
+    }
+
+    #[test]
+    fn test_tokens() {
+        let mut r = resp(StatusCode::OK, r#"{"jwt":"a.b.c","expires_in":900}"#);
+        r.set_cookies = vec!["rr_refresh=xyz; HttpOnly; Path=/token".to_string()];
+        assert_eq!(
+            tokens(r).unwrap(),
+            Tokens {
+                jwt: "a.b.c".to_string(),
+                expires_in: 900,
+                refresh: Some("xyz".to_string())
+            }
+        );
+        assert!(json::<Meal>(resp(StatusCode::OK, "[]")).is_err());
+    }
+
+    #[test]
+    fn test_readiness() {
+        let r = readiness(resp(
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"ready":false,"degraded":true,"version":"dev","checks":[{"name":"dynamodb","required":true,"ok":false,"latency_ms":2000,"error":"no answer in 2000ms"}]}"#,
+        ))
+        .unwrap();
+        assert!(!r.ready);
+        assert_eq!(r.checks[0].error.as_deref(), Some("no answer in 2000ms"));
+
+        // something in front of it gave up instead
+        let e = readiness(resp(StatusCode::SERVICE_UNAVAILABLE, "<html>")).unwrap_err();
+        assert_eq!(e, Error::Status(StatusCode::SERVICE_UNAVAILABLE));
+    }
+}
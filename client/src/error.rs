@@ -0,0 +1,94 @@
+use http::StatusCode;
+use shared::Meal;
+use std::fmt;
+
+// the `code` in the backend's ErrorResp, see errors.rs over there
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    Conflict,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    Unprocessable,
+    TooManyRequests,
+    Internal,
+    Unavailable,
+    // one from a newer backend
+    Other(String),
+}
+
+impl From<&str> for ErrorCode {
+    fn from(code: &str) -> Self {
+        match code {
+            "bad_request" => ErrorCode::BadRequest,
+            "unauthorized" => ErrorCode::Unauthorized,
+            "forbidden" => ErrorCode::Forbidden,
+            "not_found" => ErrorCode::NotFound,
+            "method_not_allowed" => ErrorCode::MethodNotAllowed,
+            "conflict" => ErrorCode::Conflict,
+            "payload_too_large" => ErrorCode::PayloadTooLarge,
+            "unsupported_media_type" => ErrorCode::UnsupportedMediaType,
+            "unprocessable" => ErrorCode::Unprocessable,
+            "too_many_requests" => ErrorCode::TooManyRequests,
+            "internal" => ErrorCode::Internal,
+            "unavailable" => ErrorCode::Unavailable,
+            other => ErrorCode::Other(other.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    // the backend said no and why
+    Api {
+        status: StatusCode,
+        code: ErrorCode,
+        message: String,
+    },
+    // a save lost to someone else's, this is the meal as it is now
+    Conflict(Box<Meal>),
+    // an error without a body we know, a proxy in the way or similar
+    Status(StatusCode),
+    // never got a response
+    Transport(String),
+    // got one, but not what that endpoint sends
+    Decode(String),
+}
+
+impl Error {
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Api { status, .. } | Error::Status(status) => Some(*status),
+            Error::Conflict(_) => Some(StatusCode::CONFLICT),
+            Error::Transport(_) | Error::Decode(_) => None,
+        }
+    }
+
+    // the token's expired or was never any good
+    pub fn is_unauthorized(&self) -> bool {
+        self.status() == Some(StatusCode::UNAUTHORIZED)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Api {
+                status, message, ..
+            } => write!(f, "{} ({})", message, status.as_u16()),
+            Error::Conflict(_) => write!(
+                f,
+                "someone else changed it in the meantime, have another look"
+            ),
+            Error::Status(status) => write!(f, "the server said {}", status),
+            Error::Transport(e) => write!(f, "{}", e),
+            Error::Decode(e) => write!(f, "didn't understand the response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,402 @@
+// One typed method per endpoint of the refeed rampage API. Each hands back a Call that
+// says what to send and what the response turns into, then the `native` feature's
+// blocking reqwest client or the `wasm` feature's browser fetch sends it:
+//
+//     let api = Api::new("http://127.0.0.1:3030");
+//     let detail = native::Client::new().send(api.meal(id))?;
+//     let detail = wasm::send(api.meal(id)).await?;
+mod call;
+mod error;
+#[cfg(feature = "native")]
+pub mod native;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use call::{cookie_value, Call, Response, Tokens};
+pub use error::{Error, ErrorCode};
+
+use http::Method;
+use shared::{
+    Health, ImportReport, Login, Meal, MealDetail, MealRevision, MealSummary, NewUser, Page,
+    PasswordChange, Readiness, RefeedDay, UserResp, REFRESH_COOKIE,
+};
+use uuid::Uuid;
+
+// GET /meals, everything's optional
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MealsQuery {
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+    pub min_stars: Option<i32>,
+    // case sensitive, part of the name is fine
+    pub name: Option<String>,
+}
+
+// what POST /import does with meals that are already there
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ImportOptions {
+    // say what would happen without changing anything
+    pub dry_run: bool,
+    // update meals that exist rather than skipping them
+    pub upsert: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Api {
+    url: String,
+    token: Option<String>,
+}
+
+impl Api {
+    pub fn new(url: &str) -> Api {
+        Api {
+            url: url.trim().trim_end_matches('/').to_string(),
+            token: None,
+        }
+    }
+
+    // for the endpoints that need logging in
+    pub fn with_token(mut self, jwt: &str) -> Api {
+        self.token = Some(jwt.to_string());
+        self
+    }
+
+    pub fn set_token(&mut self, jwt: Option<String>) {
+        self.token = jwt;
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn call<T>(
+        &self,
+        method: Method,
+        path: &str,
+        decode: fn(Response) -> Result<T, Error>,
+    ) -> Call<T> {
+        Call::new(method, format!("{}{}", self.url, path), decode)
+    }
+
+    fn authed<T>(
+        &self,
+        method: Method,
+        path: &str,
+        decode: fn(Response) -> Result<T, Error>,
+    ) -> Call<T> {
+        let c = self.call(method, path, decode);
+        match &self.token {
+            Some(t) => c.header("Authorization", format!("bearer: {}", t)),
+            // the backend says 401, same as for an expired one
+            None => c,
+        }
+    }
+
+    pub fn live(&self) -> Call<Health> {
+        self.call(Method::GET, "/health/live", call::json)
+    }
+
+    // not ready is still an answer, see call::readiness
+    pub fn ready(&self) -> Call<Readiness> {
+        self.call(Method::GET, "/health/ready", call::readiness)
+    }
+
+    pub fn login(&self, login: &Login) -> Call<Tokens> {
+        self.call(Method::POST, "/login", call::tokens)
+            .json(login)
+            .with_cookies()
+    }
+
+    // Trades the refresh token in for a new pair. Browsers keep it in their cookie jar,
+    // pass None there, everyone else passes the one from the last Tokens.
+    pub fn refresh(&self, refresh: Option<&str>) -> Call<Tokens> {
+        let c = self
+            .call(Method::POST, "/token/refresh", call::tokens)
+            .with_cookies();
+        match refresh {
+            Some(r) => c.header("Cookie", format!("{}={}", REFRESH_COOKIE, r)),
+            None => c,
+        }
+    }
+
+    pub fn logout(&self) -> Call<()> {
+        self.authed(Method::POST, "/logout", call::empty)
+            .with_cookies()
+    }
+
+    pub fn meals(&self, query: &MealsQuery) -> Call<Page<MealSummary>> {
+        let mut params = Vec::new();
+        if let Some(l) = query.limit {
+            params.push(("limit", l.to_string()));
+        }
+        if let Some(c) = &query.cursor {
+            params.push(("cursor", c.clone()));
+        }
+        if let Some(s) = query.min_stars {
+            params.push(("min_stars", s.to_string()));
+        }
+        if let Some(n) = &query.name {
+            params.push(("name", n.clone()));
+        }
+        self.call(Method::GET, &with_query("/meals", &params), call::json)
+    }
+
+    pub fn meal(&self, id: Uuid) -> Call<MealDetail> {
+        self.call(Method::GET, &format!("/meals/{}", id), call::json)
+    }
+
+    pub fn create_meal(&self, meal: &Meal) -> Call<Meal> {
+        self.authed(Method::POST, "/meals", call::json).json(meal)
+    }
+
+    // a stale version comes back as Error::Conflict with the meal as it is now
+    pub fn update_meal(&self, meal: &Meal) -> Call<Meal> {
+        self.authed(Method::PUT, &format!("/meals/{}", meal.id), call::json)
+            .json(meal)
+    }
+
+    // a JSON merge patch of name, description and stars
+    pub fn patch_meal(&self, id: Uuid, patch: &serde_json::Value) -> Call<Meal> {
+        let body = patch.to_string().into_bytes();
+        self.authed(Method::PATCH, &format!("/meals/{}", id), call::json)
+            .body("application/merge-patch+json", body)
+    }
+
+    // into the trash, see restore_from_trash
+    pub fn delete_meal(&self, id: Uuid) -> Call<()> {
+        self.authed(Method::DELETE, &format!("/meals/{}", id), call::empty)
+    }
+
+    // jpeg, png or webp, and content_type has to say which
+    pub fn upload_photo(&self, id: Uuid, content_type: &str, data: &[u8]) -> Call<Meal> {
+        let boundary = format!("rr-{}", Uuid::new_v4().to_simple());
+        self.authed(Method::POST, &format!("/meals/{}/photos", id), call::json)
+            .body(
+                &format!("multipart/form-data; boundary={}", boundary),
+                multipart(&boundary, content_type, data),
+            )
+    }
+
+    pub fn delete_photo(&self, id: Uuid, file_name: &str) -> Call<()> {
+        let path = format!("/meals/{}/photos/{}", id, encode(file_name));
+        self.authed(Method::DELETE, &path, call::empty)
+    }
+
+    pub fn revisions(&self, id: Uuid) -> Call<Vec<MealRevision>> {
+        let path = format!("/meals/{}/revisions", id);
+        self.authed(Method::GET, &path, call::json)
+    }
+
+    pub fn restore_revision(&self, id: Uuid, rev: u64) -> Call<Meal> {
+        let path = format!("/meals/{}/revisions/{}/restore", id, rev);
+        self.authed(Method::POST, &path, call::json)
+    }
+
+    pub fn trash(&self) -> Call<Vec<Meal>> {
+        self.authed(Method::GET, "/trash", call::json)
+    }
+
+    pub fn restore_from_trash(&self, id: Uuid) -> Call<Meal> {
+        let path = format!("/trash/{}/restore", id);
+        self.authed(Method::POST, &path, call::json)
+    }
+
+    // json, jsonl or csv
+    pub fn export(&self, format: &str) -> Call<String> {
+        let path = with_query("/export", &[("format", format.to_string())]);
+        self.call(Method::GET, &path, call::text)
+    }
+
+    pub fn import(
+        &self,
+        format: &str,
+        options: ImportOptions,
+        file: Vec<u8>,
+    ) -> Call<ImportReport> {
+        let path = with_query(
+            "/import",
+            &[
+                ("format", format.to_string()),
+                ("dry_run", options.dry_run.to_string()),
+                (
+                    "existing",
+                    if options.upsert { "upsert" } else { "skip" }.to_string(),
+                ),
+            ],
+        );
+        let content_type = match format {
+            "csv" => "text/csv",
+            "jsonl" => "application/x-ndjson",
+            _ => "application/json",
+        };
+        self.authed(Method::POST, &path, call::json)
+            .body(content_type, file)
+    }
+
+    pub fn days(&self) -> Call<Vec<RefeedDay>> {
+        self.call(Method::GET, "/days", call::json)
+    }
+
+    pub fn day(&self, id: Uuid) -> Call<RefeedDay> {
+        self.call(Method::GET, &format!("/days/{}", id), call::json)
+    }
+
+    pub fn create_day(&self, day: &RefeedDay) -> Call<RefeedDay> {
+        self.authed(Method::POST, "/days", call::json).json(day)
+    }
+
+    pub fn update_day(&self, day: &RefeedDay) -> Call<RefeedDay> {
+        self.authed(Method::PUT, &format!("/days/{}", day.id), call::json)
+            .json(day)
+    }
+
+    pub fn delete_day(&self, id: Uuid) -> Call<()> {
+        self.authed(Method::DELETE, &format!("/days/{}", id), call::empty)
+    }
+
+    // admins only
+    pub fn create_user(&self, user: &NewUser) -> Call<UserResp> {
+        self.authed(Method::POST, "/users", call::json).json(user)
+    }
+
+    // logs every other session out too
+    pub fn change_password(&self, change: &PasswordChange) -> Call<()> {
+        self.authed(Method::POST, "/users/me/password", call::empty)
+            .json(change)
+    }
+}
+
+fn with_query(path: &str, params: &[(&str, String)]) -> String {
+    if params.is_empty() {
+        return path.to_string();
+    }
+    let query: Vec<String> = params
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, encode(v)))
+        .collect();
+    format!("{}?{}", path, query.join("&"))
+}
+
+// percent encodes everything but the unreserved characters
+fn encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// one `photo` field, what the backend's upload expects
+fn multipart(boundary: &str, content_type: &str, data: &[u8]) -> Vec<u8> {
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"photo\"\r\nContent-Type: {}\r\n\r\n",
+        boundary, content_type
+    )
+    .into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header<T>(c: &Call<T>, name: &str) -> Option<String> {
+        c.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.clone())
+    }
+
+    #[test]
+    fn test_urls() {
+        let api = Api::new("http://127.0.0.1:3030/\n");
+        let c = api.meals(&MealsQuery::default());
+        assert_eq!(c.url, "http://127.0.0.1:3030/meals");
+        assert_eq!(c.method, Method::GET);
+
+        let c = api.meals(&MealsQuery {
+            limit: Some(10),
+            cursor: Some("abc=".to_string()),
+            min_stars: Some(4),
+            name: Some("Pad thai & co".to_string()),
+        });
+        assert_eq!(
+            c.url,
+            "http://127.0.0.1:3030/meals?limit=10&cursor=abc%3D&min_stars=4&name=Pad%20thai%20%26%20co"
+        );
+
+        let c = api.import(
+            "csv",
+            ImportOptions {
+                dry_run: true,
+                upsert: true,
+            },
+            vec![],
+        );
+        assert_eq!(
+            c.url,
+            "http://127.0.0.1:3030/import?format=csv&dry_run=true&existing=upsert"
+        );
+        assert_eq!(header(&c, "content-type").as_deref(), Some("text/csv"));
+    }
+
+    #[test]
+    fn test_auth() {
+        let api = Api::new("http://localhost");
+        let id = Uuid::new_v4();
+        assert_eq!(header(&api.delete_meal(id), "authorization"), None);
+        let c = api.with_token("a.b.c").delete_meal(id);
+        assert_eq!(c.method, Method::DELETE);
+        assert_eq!(c.url, format!("http://localhost/meals/{}", id));
+        assert_eq!(
+            header(&c, "authorization").as_deref(),
+            Some("bearer: a.b.c")
+        );
+
+        let c = Api::new("http://localhost").refresh(Some("xyz"));
+        assert_eq!(header(&c, "cookie").as_deref(), Some("rr_refresh=xyz"));
+        assert!(c.with_cookies);
+    }
+
+    #[test]
+    fn test_bodies() {
+        let api = Api::new("http://localhost").with_token("t");
+        let meal = Meal {
+            id: Uuid::new_v4(),
+            name: "Pho".to_string(),
+            ..Default::default()
+        };
+        let c = api.update_meal(&meal);
+        assert_eq!(
+            serde_json::from_slice::<Meal>(c.body.as_ref().unwrap()).unwrap(),
+            meal
+        );
+        assert_eq!(
+            header(&c, "content-type").as_deref(),
+            Some("application/json")
+        );
+
+        let c = api.patch_meal(meal.id, &serde_json::json!({ "stars": null }));
+        assert_eq!(c.body.as_deref(), Some(&br#"{"stars":null}"#[..]));
+        assert_eq!(
+            header(&c, "content-type").as_deref(),
+            Some("application/merge-patch+json")
+        );
+
+        let c = api.upload_photo(meal.id, "image/png", b"\x89PNG");
+        let content_type = header(&c, "content-type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/form-data; boundary=")
+            .unwrap();
+        let body = String::from_utf8_lossy(c.body.as_ref().unwrap()).to_string();
+        assert!(body.starts_with(&format!("--{}\r\n", boundary)));
+        assert!(body.contains("name=\"photo\""));
+        assert!(body.contains("Content-Type: image/png\r\n\r\n"));
+        assert!(body.ends_with(&format!("\r\n--{}--\r\n", boundary)));
+    }
+}
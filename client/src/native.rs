@@ -0,0 +1,41 @@
+use crate::{Call, Error, Response};
+
+// blocking, for tests and command line tools
+#[derive(Clone, Default)]
+pub struct Client {
+    http: reqwest::blocking::Client,
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client::default()
+    }
+
+    pub fn send<T>(&self, call: Call<T>) -> Result<T, Error> {
+        let mut r = self.http.request(call.method.clone(), &call.url);
+        for (name, value) in &call.headers {
+            r = r.header(*name, value);
+        }
+        if let Some(body) = &call.body {
+            r = r.body(body.clone());
+        }
+        let resp = r.send().map_err(|e| Error::Transport(e.to_string()))?;
+        let status = resp.status();
+        let set_cookies = resp
+            .headers()
+            .get_all("set-cookie")
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .map(|h| h.to_string())
+            .collect();
+        let body = resp
+            .bytes()
+            .map_err(|e| Error::Transport(e.to_string()))?
+            .to_vec();
+        call.finish(Response {
+            status,
+            set_cookies,
+            body,
+        })
+    }
+}
@@ -0,0 +1,43 @@
+use crate::{Call, Error, Response};
+use http::StatusCode;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{RequestCredentials, RequestInit};
+
+// the browser's fetch, for the frontend
+pub async fn send<T>(call: Call<T>) -> Result<T, Error> {
+    let mut init = RequestInit::new();
+    init.method(call.method.as_str());
+    if call.with_cookies {
+        init.credentials(RequestCredentials::Include);
+    }
+    let body = call.body.as_deref().map(js_sys::Uint8Array::from);
+    if let Some(b) = &body {
+        init.body(Some(b));
+    }
+    let request = web_sys::Request::new_with_str_and_init(&call.url, &init).map_err(js_error)?;
+    for (name, value) in &call.headers {
+        request.headers().set(name, value).map_err(js_error)?;
+    }
+    let window =
+        web_sys::window().ok_or_else(|| Error::Transport("no window to fetch from".to_string()))?;
+    let resp: web_sys::Response = JsFuture::from(window.fetch_with_request(&request))
+        .await
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+    let buf = JsFuture::from(resp.array_buffer().map_err(js_error)?)
+        .await
+        .map_err(js_error)?;
+    let status = StatusCode::from_u16(resp.status()).map_err(|e| Error::Decode(e.to_string()))?;
+    call.finish(Response {
+        status,
+        set_cookies: vec![],
+        body: js_sys::Uint8Array::new(&buf).to_vec(),
+    })
+}
+
+// fetch only fails outright when it never got a response
+fn js_error(e: JsValue) -> Error {
+    Error::Transport(e.as_string().unwrap_or_else(|| format!("{:?}", e)))
+}
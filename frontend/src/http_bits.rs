@@ -1,53 +1,60 @@
-use crate::frontend_types::LoginInput;
-use crate::{Meal, Msg, URL_BASE};
-use seed::*; // scope down?
+use crate::{Msg, URL_BASE};
+use client::{wasm, Api, MealsQuery};
+use shared::{Login, Meal};
 use uuid::Uuid;
-// https://seed-rs.org/guide/http-requests-and-state
+// the requests themselves are in the client crate, this just turns them into messages
+
+fn api() -> Api {
+    Api::new(URL_BASE)
+}
+
+fn authed(auth: &str) -> Api {
+    api().with_token(auth)
+}
 
 pub async fn delete_meal(id: Uuid, auth: String) -> Result<Msg, Msg> {
-    let url = format!("{}/meals/{}", URL_BASE.replace("\n", ""), id);
-    Request::new(url)
-        .method(Method::Delete)
-        .header("Authorization", &format!("bearer: {}", auth))
-        .fetch_json_data(Msg::MealDeleted)
-        .await
+    Ok(Msg::MealDeleted(
+        wasm::send(authed(&auth).delete_meal(id)).await,
+    ))
 }
 
-pub async fn fetch_meals() -> Result<Msg, Msg> {
-    let url = format!("{}/meals", URL_BASE.replace("\n", ""));
-    Request::new(url).fetch_json_data(Msg::MealsFetched).await
+// one page at a time, start with no cursor and pass back the next_cursor for the next page
+pub async fn fetch_meals(cursor: Option<String>) -> Result<Msg, Msg> {
+    let query = MealsQuery {
+        cursor,
+        ..MealsQuery::default()
+    };
+    Ok(Msg::MealsFetched(wasm::send(api().meals(&query)).await))
 }
 
 pub async fn create_meal(meal: Meal, auth: String) -> Result<Msg, Msg> {
-    let url = format!("{}/meals", URL_BASE.replace("\n", ""));
-    Request::new(url)
-        .method(Method::Post)
-        .header("Authorization", &format!("bearer: {}", auth))
-        .send_json(&meal)
-        .fetch_json_data(Msg::MealCreated)
-        .await
+    Ok(Msg::MealCreated(
+        wasm::send(authed(&auth).create_meal(&meal)).await,
+    ))
+}
+
+// the refresh token cookie gets kept by the browser
+pub async fn login(login: Login) -> Result<Msg, Msg> {
+    Ok(Msg::LoginResp(wasm::send(api().login(&login)).await))
+}
+
+// trade the refresh token cookie in for a new access token
+pub async fn refresh() -> Result<Msg, Msg> {
+    Ok(Msg::Refreshed(wasm::send(api().refresh(None)).await))
 }
 
-pub async fn login(login: LoginInput) -> Result<Msg, Msg> {
-    let url = format!("{}/login", URL_BASE.replace("\n", ""));
-    Request::new(url)
-        .method(Method::Post)
-        .send_json(&login)
-        .fetch_json_data(Msg::LoginResp)
-        .await
+// nothing to do with the response, we're logged out locally either way
+pub async fn logout(auth: String) -> Result<Msg, Msg> {
+    let _ = wasm::send(authed(&auth).logout()).await;
+    Ok(Msg::NoOp)
 }
 
 pub async fn update_meal(meal: Meal, auth: String) -> Result<Msg, Msg> {
-    let url = format!("{}/meals/{}", URL_BASE.replace("\n", ""), meal.id);
-    Request::new(url)
-        .method(Method::Put)
-        .header("Authorization", &format!("bearer: {}", auth))
-        .send_json(&meal)
-        .fetch_json_data(Msg::MealCreated)
-        .await
+    Ok(Msg::MealCreated(
+        wasm::send(authed(&auth).update_meal(&meal)).await,
+    ))
 }
 
 pub async fn fetch_meal(id: Uuid) -> Result<Msg, Msg> {
-    let url = format!("{}/meals/{}", URL_BASE.replace("\n", ""), id);
-    Request::new(url).fetch_json_data(Msg::MealFetched).await
+    Ok(Msg::MealFetched(wasm::send(api().meal(id)).await))
 }
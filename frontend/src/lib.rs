@@ -5,8 +5,9 @@ pub mod frontend_types;
 mod http_bits;
 mod navigation;
 mod stars;
-use seed::{browser::service::fetch, prelude::*, *};
-use shared::Meal;
+use client::Tokens;
+use seed::{prelude::*, *};
+use shared::{Login, Meal, MealDetail, MealSummary, Page, Photo};
 use uuid::Uuid;
 
 static URL_BASE: &str = include_str!("api_loc.txt");
@@ -18,11 +19,14 @@ pub struct Model {
     meals: frontend_types::MealMap,
     meal_under_construction: Meal,
     meal: Meal,
+    photos: Vec<Photo>,
     error: Option<String>,
     page: Pages,
-    login: Option<frontend_types::LoginInput>,
+    login: Option<Login>,
     auth: Option<String>,
     sort: Option<frontend_types::SortingOptions>,
+    // where the next page of meals starts, None when we've got them all
+    next_cursor: Option<String>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -46,18 +50,30 @@ impl Default for Model {
                 description: "".to_string(),
                 id: Uuid::new_v4(),
                 photos: None,
+                renditions: None,
                 stars: None,
+                owner: None,
+                version: 0,
+                deleted_at: None,
+                purge_at: None,
             },
             meal: Meal {
                 name: "".to_string(),
                 description: "".to_string(),
                 id: Uuid::new_v4(),
                 photos: None,
+                renditions: None,
                 stars: None,
+                owner: None,
+                version: 0,
+                deleted_at: None,
+                purge_at: None,
             },
+            photos: vec![],
             login: None,
             auth: None,
             sort: None,
+            next_cursor: None,
         }
     }
 }
@@ -78,37 +94,31 @@ impl Model {
 pub enum Msg {
     NoOp,
     // editing
-    EditMeal {
-        meal_id: Uuid,
-    },
+    EditMeal { meal_id: Uuid },
     MealCreateUpdateName(String),
     MealCreateUpdateDescription(String),
     MealCreateUpdateStars(i32),
     CreateNewMeal(Meal),
     SaveMeal(Meal),
     MealValidationError,
-    MealCreated(seed::fetch::ResponseDataResult<frontend_types::MealCreatedResponse>),
+    MealCreated(Result<Meal, client::Error>),
     // deleting
-    DeleteMeal {
-        meal_id: Uuid,
-    },
-    MealDeleted(seed::fetch::ResponseDataResult<frontend_types::MealDeletedResponse>),
+    DeleteMeal { meal_id: Uuid },
+    MealDeleted(Result<(), client::Error>),
     // changing page
     ChangePage(Pages),
     // fetching etc
-    FetchData {
-        meal_id: Option<Uuid>,
-    },
-    MealsFetched(fetch::ResponseDataResult<frontend_types::MealMap>),
-    MealFetched(fetch::ResponseDataResult<Meal>),
+    FetchData { meal_id: Option<Uuid> },
+    MealsFetched(Result<Page<MealSummary>, client::Error>),
+    MoreMeals,
+    MealFetched(Result<MealDetail, client::Error>),
     // login
     LoginUserUpdated(String),
     LoginPwUpdated(String),
-    Login {
-        login: Option<frontend_types::LoginInput>,
-    },
+    Login { login: Option<Login> },
     Logout,
-    LoginResp(seed::fetch::ResponseDataResult<frontend_types::LoginResp>),
+    LoginResp(Result<Tokens, client::Error>),
+    Refreshed(Result<Tokens, client::Error>),
     LoginFromTxt,
     ChangeSort,
     Rehydrate,
@@ -119,24 +129,27 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
     // TODO: move these around to group like things together
     match msg {
         Msg::Logout => {
-            let storage = seed::storage::get_storage().unwrap();
-            seed::storage::store_data(&storage, "authjwt", &"".to_string());
-            model.auth = None;
+            // let the backend know too so the token is no good to anyone else
+            if let Some(auth) = model.auth.take() {
+                orders.perform_cmd(http_bits::logout(auth));
+            }
         }
+        // the access token only lives in memory, the refresh token cookie gets us a new one
         Msg::Rehydrate => {
-            let storage = seed::storage::get_storage().unwrap();
-            let mut j = match storage.get_item("authjwt") {
-                Ok(a) => match a {
-                    Some(b) => b,
-                    None => "".to_string(),
-                },
-                Err(_) => "".to_string(),
-            };
-            j = j.replace("\"", "");
-            if !j.is_empty() {
-                model.auth = Some(j);
+            // tokens from before refresh tokens
+            if let Some(storage) = seed::storage::get_storage() {
+                let _ = storage.remove_item("authjwt");
             }
+            orders.skip().perform_cmd(http_bits::refresh());
         }
+        Msg::Refreshed(r) => match r {
+            Ok(fresh) => {
+                schedule_refresh(fresh.expires_in, orders);
+                model.auth = Some(fresh.jwt);
+            }
+            // not logged in, or that login is over
+            Err(_) => model.auth = None,
+        },
         Msg::ChangeSort => {
             match &model.sort {
                 Some(x) => match x {
@@ -155,10 +168,10 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
             match &model.sort {
                 Some(x) => match x {
                     frontend_types::SortingOptions::StarsAsc => {
-                        model.meals.sort_by_key(|y| y.stars);
+                        model.meals.sort_by_key(|y| y.meal.stars);
                     }
                     frontend_types::SortingOptions::StarsDesc => {
-                        model.meals.sort_by_key(|y| y.stars);
+                        model.meals.sort_by_key(|y| y.meal.stars);
                         model.meals.reverse();
                     }
                 },
@@ -177,8 +190,7 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
         Msg::LoginResp(l) => match l {
             Ok(login_ok) => {
                 model.error = None;
-                let storage = seed::storage::get_storage().unwrap();
-                seed::storage::store_data(&storage, "authjwt", &login_ok.jwt);
+                schedule_refresh(login_ok.expires_in, orders);
                 model.auth = Some(login_ok.jwt);
                 seed::push_route(vec!["meals"]);
                 orders.send_msg(Msg::ChangePage(Pages::Meals));
@@ -201,7 +213,7 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
         Msg::LoginUserUpdated(u) => match model.login.as_mut() {
             Some(m) => m.user = u,
             None => {
-                model.login = Some(frontend_types::LoginInput {
+                model.login = Some(Login {
                     user: u,
                     pw: "".to_string(),
                 })
@@ -210,7 +222,7 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
         Msg::LoginPwUpdated(pw) => match model.login.as_mut() {
             Some(m) => m.pw = pw,
             None => {
-                model.login = Some(frontend_types::LoginInput {
+                model.login = Some(Login {
                     user: "".to_string(),
                     pw,
                 })
@@ -242,11 +254,16 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
         Msg::DeleteMeal { meal_id: id } => {
             if model.auth.is_none() {
                 orders.send_msg(Msg::ChangePage(Pages::Login));
-            } else {
+            } else if seed::window()
+                .confirm_with_message("Move this meal to the trash?")
+                .unwrap_or(false)
+            {
                 log!(format!("model auth is something: '{:?}'", model.auth));
                 orders
                     .skip()
                     .perform_cmd(http_bits::delete_meal(id, model.auth.clone().unwrap()));
+            } else {
+                orders.skip();
             }
         }
         Msg::MealValidationError => {
@@ -294,17 +311,34 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
             seed::push_route(vec!["meals", &m.id.to_string()]);
             orders.send_msg(Msg::ChangePage(Pages::ViewSpecificMeal { meal_id: m.id }));
         }
+        Msg::MealCreated(Err(client::Error::Conflict(_))) => {
+            model.error = Some(
+                "Someone else changed this meal while you were editing it, reload to see their changes."
+                    .to_string(),
+            );
+        }
         Msg::MealCreated(Err(fail_reason)) => {
-            model.error = Some(format!("Couldn't create meal: {:#?}", fail_reason));
+            model.error = Some(format!("Couldn't create meal: {}", fail_reason));
         }
         Msg::FetchData { meal_id } => {
             match meal_id {
                 Some(id) => orders.skip().perform_cmd(http_bits::fetch_meal(id)),
-                None => orders.skip().perform_cmd(http_bits::fetch_meals()),
+                None => {
+                    model.meals.clear();
+                    orders.skip().perform_cmd(http_bits::fetch_meals(None))
+                }
             };
         }
-        Msg::MealsFetched(Ok(meals)) => {
-            model.meals = meals;
+        Msg::MoreMeals => {
+            if let Some(cursor) = model.next_cursor.take() {
+                orders
+                    .skip()
+                    .perform_cmd(http_bits::fetch_meals(Some(cursor)));
+            }
+        }
+        Msg::MealsFetched(Ok(page)) => {
+            model.meals.extend(page.items);
+            model.next_cursor = page.next_cursor;
             model.error = None;
         }
         Msg::MealsFetched(Err(fail_reason)) => {
@@ -313,11 +347,12 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                 "Fetch error - Sending message failed - {:#?}",
                 fail_reason
             ));
-            model.error = Some(format!("Error fetching meals: {:?}", fail_reason));
+            model.error = Some(format!("Error fetching meals: {}", fail_reason));
         }
-        Msg::MealFetched(Ok(meal)) => {
+        Msg::MealFetched(Ok(detail)) => {
             model.meals = vec![];
-            model.meal = meal;
+            model.meal = detail.meal;
+            model.photos = detail.photos;
             model.meal_under_construction = model.meal.clone();
             model.error = None;
         }
@@ -326,7 +361,7 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                 "Fetch error - Sending message failed - {:#?}",
                 fail_reason
             ));
-            model.error = Some(format!("Error fetching meal: {:?}", fail_reason));
+            model.error = Some(format!("Error fetching meal: {}", fail_reason));
         }
         Msg::ChangePage(page) => {
             if let Pages::ViewSpecificMeal { meal_id } = page {
@@ -349,7 +384,12 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
                     description: "".to_string(),
                     id: Uuid::new_v4(),
                     photos: None,
+                    renditions: None,
                     stars: None,
+                    owner: None,
+                    version: 0,
+                    deleted_at: None,
+                    purge_at: None,
                 };
             }
             model.page = page;
@@ -385,7 +425,7 @@ fn view(model: &Model) -> impl View<Msg> {
             c
         }
         Pages::ViewSpecificMeal { meal_id } => {
-            let mut c = vec![meal_item(&model.meal)];
+            let mut c = vec![meal_item(&model.meal), meal_photos(&model.photos)];
             c.push(button![
                 simple_ev(
                     Ev::Click,
@@ -551,11 +591,48 @@ fn meal_item(m: &Meal) -> Node<Msg> {
     ]
 }
 
+// the URLs are presigned by the backend so the browser fetches straight from the bucket
+fn meal_photos(photos: &[Photo]) -> Node<Msg> {
+    div![
+        class!["row"],
+        photos
+            .iter()
+            .map(|p| {
+                // the medium rendition is plenty for the gallery, link through to the original
+                let src = p.medium_url.as_ref().unwrap_or(&p.url);
+                div![
+                    class!["col-md-4"],
+                    a![
+                        attrs! {At::Href => p.url},
+                        img![
+                            class!["img-fluid img-thumbnail"],
+                            attrs! {At::Src => src, At::Alt => p.key},
+                        ]
+                    ]
+                ]
+            })
+            .collect::<Vec<Node<Msg>>>()
+    ]
+}
+
+// the thumbnail is small enough for a table row, meals without one get an empty cell
+fn meal_preview(s: &MealSummary) -> Node<Msg> {
+    match &s.thumb_url {
+        Some(src) => img![
+            class!["img-thumbnail"],
+            style! {St::MaxHeight => "48px"},
+            attrs! {At::Src => src, At::Alt => s.meal.name},
+        ],
+        None => empty![],
+    }
+}
+
 fn meal_list(model: &Model) -> Vec<Node<Msg>> {
     let bodies: Vec<Node<Msg>> = model
         .meals
         .iter()
-        .map(|m| {
+        .map(|s| {
+            let m = &s.meal;
             tr![
                 style! {St::Cursor => "pointer"},
                 attrs! {At::Href => format!("/meals/{}", m.id)},
@@ -564,6 +641,7 @@ fn meal_list(model: &Model) -> Vec<Node<Msg>> {
                     attrs! {At::Href => format!("/meals/{}/edit", m.id)},
                     "✏️"
                 ]],
+                td![meal_preview(s)],
                 td![m.name],
                 td![m.description],
                 td![stars::stars(m.stars)]
@@ -584,6 +662,7 @@ fn meal_list(model: &Model) -> Vec<Node<Msg>> {
         table![
             class!["table table-striped table-sm"],
             thead![tr![
+                th![attrs! { At::Scope => "col" }],
                 th![attrs! { At::Scope => "col" }],
                 th!["name", attrs! { At::Scope => "col" }],
                 th!["description", attrs! { At::Scope => "col" }],
@@ -598,6 +677,10 @@ fn meal_list(model: &Model) -> Vec<Node<Msg>> {
         ]
     ];
     let b = p![button![attrs! {At::Href => "/meals/create"}, "➕"]];
+    if model.next_cursor.is_some() {
+        let more = p![button![simple_ev(Ev::Click, Msg::MoreMeals), "more meals"]];
+        return vec![l, more, b];
+    }
     vec![l, b]
 }
 
@@ -647,8 +730,18 @@ pub fn render() {
         .build_and_start();
 }
 
+// get a new access token a minute before this one runs out
+fn schedule_refresh(expires_in: u64, orders: &mut impl Orders<Msg>) {
+    let (app, msg_mapper) = (orders.clone_app(), orders.msg_mapper());
+    let millis = expires_in.saturating_sub(60).max(10) * 1000;
+    seed::set_timeout(
+        Box::new(move || app.update(msg_mapper(Msg::Rehydrate))),
+        millis as i32,
+    );
+}
+
 fn after_mount(url: Url, orders: &mut impl Orders<Msg>) -> AfterMount<Model> {
-    // try to rehydrate stored jwt
+    // pick up where the last visit left off if the refresh token's still good
     orders.send_msg(Msg::Rehydrate);
     let mut m: Model = Default::default();
 
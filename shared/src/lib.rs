@@ -1,18 +1,298 @@
 #[cfg(feature = "dynamo_bits")]
 use dynomite::Item;
+#[cfg(feature = "openapi")]
+use schemars::JsonSchema;
 
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 #[cfg_attr(feature = "dynamo_bits", derive(Item))]
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
 #[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq, Hash)]
 pub struct Meal {
     #[cfg_attr(feature = "dynamo_bits", dynomite(rename = "mealName"))]
     pub name: String,
     #[cfg_attr(feature = "dynamo_bits", dynomite(partition_key))]
     pub id: Uuid,
-    // This will be populated by the backend as presigned URLs for the frontend to fetch
+    // Pipe separated list of S3 keys. Managed by the backend's photo endpoints,
+    // see MealDetail for URLs the frontend can fetch.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub photos: Option<String>,
+    // Pipe separated list of S3 keys for the thumbnail and medium sized copies of the photos.
+    // Meals saved before there were renditions don't have the attribute at all.
+    #[cfg_attr(feature = "dynamo_bits", dynomite(default))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub renditions: Option<String>,
     pub description: String,
     pub stars: Option<i32>,
+    // the user who made it, only they or an admin can change it. None for meals from
+    // before there were accounts, only admins can change those.
+    #[cfg_attr(feature = "dynamo_bits", dynomite(default))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Uuid>,
+    // Goes up by one with every change. Send back the version you were editing, the
+    // backend turns the update down if someone else changed it since. Meals from
+    // before versions are at 0.
+    #[cfg_attr(feature = "dynamo_bits", dynomite(default))]
+    #[serde(default)]
+    pub version: u64,
+    // Seconds since the epoch it went in the trash, None for meals that haven't. Trashed
+    // meals only show up in GET /trash until they're restored or purged.
+    #[cfg_attr(feature = "dynamo_bits", dynomite(default))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<u64>,
+    // when a trashed meal and its photos are gone for good, also the table's TTL attribute
+    #[cfg_attr(feature = "dynamo_bits", dynomite(default))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purge_at: Option<u64>,
+}
+
+impl Meal {
+    pub fn in_trash(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+}
+
+// One page of a list. Pass next_cursor back as the cursor to get the page after it,
+// there's nothing more to get when it's None.
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+// One refeed day: what got eaten and how it went
+#[cfg_attr(feature = "dynamo_bits", derive(Item))]
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct RefeedDay {
+    // the backend picks the id when a day gets created, so it can be left out
+    #[cfg_attr(feature = "dynamo_bits", dynomite(partition_key))]
+    #[serde(default)]
+    pub id: Uuid,
+    // YYYY-MM-DD, one refeed day per date
+    pub date: String,
+    // ids of the meals eaten that day
+    pub meals: Vec<Uuid>,
+    pub notes: String,
+    // how I felt, 1 to 5 like meal stars
+    pub energy: Option<i32>,
+    pub mood: Option<i32>,
+    pub sleep: Option<i32>,
+}
+
+// A meal photo with a presigned URL that's good until expires_at (seconds since the epoch)
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Photo {
+    pub key: String,
+    pub url: String,
+    // smaller copies, None for photos uploaded before the backend made them
+    pub thumb_url: Option<String>,
+    pub medium_url: Option<String>,
+    pub expires_at: u64,
+}
+
+// What the backend returns for a single meal: the meal with its photos ready to show
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct MealDetail {
+    #[serde(flatten)]
+    pub meal: Meal,
+    pub photos: Vec<Photo>,
+}
+
+impl MealDetail {
+    pub fn new(meal: Meal, photos: Vec<Photo>) -> MealDetail {
+        // the raw keys would collide with the photos list when serialized
+        MealDetail {
+            meal: Meal {
+                photos: None,
+                renditions: None,
+                ..meal
+            },
+            photos,
+        }
+    }
+}
+
+// One meal in GET /meals: the meal without its raw S3 keys, and a presigned URL for the
+// first photo's thumbnail, None when it doesn't have one
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct MealSummary {
+    #[serde(flatten)]
+    pub meal: Meal,
+    pub thumb_url: Option<String>,
+}
+
+impl MealSummary {
+    pub fn new(meal: Meal, thumb_url: Option<String>) -> MealSummary {
+        MealSummary {
+            meal: Meal {
+                photos: None,
+                renditions: None,
+                ..meal
+            },
+            thumb_url,
+        }
+    }
+}
+
+// What a change did to a meal
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum RevisionAction {
+    Created,
+    Updated,
+    Deleted,
+    Restored,
+}
+
+// One change to a meal and the whole meal as it was right after it, or right before
+// for a delete. They never change once written, see GET /meals/{id}/revisions.
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct MealRevision {
+    pub meal_id: Uuid,
+    // counts up from 1 for each meal
+    pub rev: u64,
+    pub action: RevisionAction,
+    pub changed_by: Uuid,
+    // seconds since the epoch
+    pub changed_at: u64,
+    pub meal: Meal,
+}
+
+// What POST /import did with one meal from the file, or would have done on a dry run
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportOutcome {
+    Created,
+    Updated,
+    Skipped,
+    Failed,
+}
+
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ImportRow {
+    // counts from 1 in the order the meals are in the file
+    pub row: usize,
+    // None when the row couldn't be read as a meal at all
+    pub id: Option<Uuid>,
+    pub outcome: ImportOutcome,
+    // why it failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Everything POST /import did, row by row. Nothing was written when dry_run is set.
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub rows: Vec<ImportRow>,
+}
+
+impl ImportReport {
+    pub fn add(&mut self, row: ImportRow) {
+        match row.outcome {
+            ImportOutcome::Created => self.created += 1,
+            ImportOutcome::Updated => self.updated += 1,
+            ImportOutcome::Skipped => self.skipped += 1,
+            ImportOutcome::Failed => self.failed += 1,
+        }
+        self.rows.push(row);
+    }
+}
+
+// the cookie /login and /token/refresh hand the refresh token out in
+pub static REFRESH_COOKIE: &str = "rr_refresh";
+
+// every error body, the codes are the ones in the backend's errors.rs
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ErrorResp {
+    pub error: String,
+    pub code: String,
+}
+
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Health {
+    pub healthy: bool,
+    pub version: String,
+}
+
+// GET /health/ready, a 503 when a required check failed. A failed optional one only
+// makes it degraded, eg no photo storage when the backend's s3_required is off.
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Readiness {
+    pub ready: bool,
+    pub degraded: bool,
+    pub version: String,
+    pub checks: Vec<DependencyCheck>,
+}
+
+// one of the services the backend can't do without, eg dynamodb or s3
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct DependencyCheck {
+    pub name: String,
+    pub required: bool,
+    pub ok: bool,
+    pub latency_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Login {
+    pub user: String,
+    pub pw: String,
+}
+
+// from /login and /token/refresh, the refresh token itself only goes in the cookie
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct LoginResp {
+    pub jwt: String,
+    // seconds until jwt stops working
+    pub expires_in: u64,
+}
+
+// POST /users, admins only. No Debug so the password can't end up in a log.
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NewUser {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub admin: bool,
+}
+
+// a user without the password hash
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
+pub struct UserResp {
+    pub id: Uuid,
+    pub username: String,
+    pub admin: bool,
+}
+
+#[cfg_attr(feature = "openapi", derive(JsonSchema))]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PasswordChange {
+    pub current_password: String,
+    pub new_password: String,
 }